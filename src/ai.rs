@@ -0,0 +1,838 @@
+//! AI backends.
+//!
+//! Everything claudesh asks the AI goes through `AiBackend`: one system
+//! prompt and one user message in, one reply out. Which backend is used is
+//! chosen in the `[ai]` section of `~/.claudesh/config.toml`, or forced to
+//! the offline mock with `$CLAUDESH_MOCK_AI`.
+//!
+//! Requests run on a worker thread via `start`, so the shell can give up on
+//! them when the user presses Ctrl-C or the request takes too long. The HTTP
+//! backends always stream the reply, so a request given up on notices at the
+//! next piece and closes its connection.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::signals;
+
+const DEFAULT_ANTHROPIC_URL: &str = "https://api.anthropic.com";
+const DEFAULT_ANTHROPIC_MODEL: &str = "claude-sonnet-4-5";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+/// How long a request may take unless `ai.timeout` says otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long a reply may go without sending anything before the connection
+/// is dropped. Replies are streamed, so a live one sends something often.
+const HTTP_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a waiting request checks for Ctrl-C.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Which feature a request comes from, i.e. which prompt file it uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptKind {
+    Generate,
+    Script,
+    Explain,
+    Ask,
+    Fix,
+    Judgy,
+    History,
+    Hint,
+}
+
+impl PromptKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            PromptKind::Generate => "generate",
+            PromptKind::Script => "script",
+            PromptKind::Explain => "explain",
+            PromptKind::Ask => "ask",
+            PromptKind::Fix => "fix",
+            PromptKind::Judgy => "judgy",
+            PromptKind::History => "history",
+            PromptKind::Hint => "hint",
+        }
+    }
+}
+
+pub struct Request {
+    pub kind: PromptKind,
+    pub system: String,
+    /// The full user message, environment context included.
+    pub message: String,
+    /// The part of `message` that came from the feature itself.
+    pub input: String,
+    pub cwd: PathBuf,
+    cancel: Arc<Cancel>,
+}
+
+impl Request {
+    pub fn new(kind: PromptKind, system: &str, message: String, input: &str, cwd: &Path) -> Self {
+        Request {
+            kind,
+            system: system.to_string(),
+            message,
+            input: input.to_string(),
+            cwd: cwd.to_path_buf(),
+            cancel: Arc::default(),
+        }
+    }
+
+    /// Whether the shell has given up on this request.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Why a request produced no reply.
+#[derive(Debug)]
+pub enum AiError {
+    /// The user pressed Ctrl-C.
+    Cancelled,
+    /// No reply within `ai.timeout`.
+    TimedOut(Duration),
+    /// The backend failed; the message is ready to show to the user.
+    Failed(String),
+}
+
+impl AiError {
+    /// The exit status of a feature whose request ended this way: 130 like
+    /// any interrupted command, 124 like timeout(1), or 1.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            AiError::Cancelled => 128 + libc::SIGINT,
+            AiError::TimedOut(_) => 124,
+            AiError::Failed(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for AiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AiError::Cancelled => write!(f, "cancelled"),
+            AiError::TimedOut(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+            AiError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub trait AiBackend: Send + Sync {
+    /// Send the request, returning the reply text. Errors are ready to show
+    /// to the user.
+    fn complete(&self, request: &Request) -> Result<String, String>;
+
+    /// Like `complete`, but hand the reply to `on_text` piece by piece as it
+    /// arrives. Returns the whole reply. Backends that can't stream deliver
+    /// it in one piece.
+    fn stream(&self, request: &Request, on_text: &mut dyn FnMut(&str)) -> Result<String, String> {
+        let text = self.complete(request)?;
+        on_text(&text);
+        Ok(text)
+    }
+
+    /// Why the backend can't be used, if it can't.
+    fn unavailable_reason(&self) -> Option<String>;
+}
+
+/// The `[ai]` section of config.toml.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AiSettings {
+    /// "claude-cli" (default), "anthropic" or "openai".
+    pub backend: Option<String>,
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    /// API key; falls back to $ANTHROPIC_API_KEY or $OPENAI_API_KEY.
+    pub api_key: Option<String>,
+    /// Endpoint root, e.g. "http://localhost:11434/v1" for a local model.
+    pub base_url: Option<String>,
+    /// Canned responses for the "mock" backend.
+    pub fixtures: Option<PathBuf>,
+    /// Where the "mock" backend appends each request it receives, as JSON lines.
+    pub record: Option<PathBuf>,
+    /// Seconds to wait for a reply before giving up.
+    pub timeout: Option<u64>,
+}
+
+impl AiSettings {
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT)
+    }
+}
+
+// ─── Running requests ────────────────────────────────────────────────────────
+
+/// Lets the shell give up on a request that is still running, killing the
+/// backend's child process if it has one.
+#[derive(Default)]
+struct Cancel {
+    cancelled: AtomicBool,
+    /// Process group of the running child.
+    child: Mutex<Option<i32>>,
+}
+
+impl Cancel {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(pgid) = *self.child.lock().unwrap() {
+            kill_group(pgid);
+        }
+    }
+
+    /// Kill `child`, which leads its own process group, if the request is
+    /// cancelled before `untrack`.
+    fn track(&self, child: &Child) {
+        let pgid = child.id() as i32;
+        *self.child.lock().unwrap() = Some(pgid);
+        if self.cancelled.load(Ordering::SeqCst) {
+            kill_group(pgid);
+        }
+    }
+
+    /// Forget the child. Must happen before it is reaped, so a recycled pid
+    /// is never killed.
+    fn untrack(&self) {
+        self.child.lock().unwrap().take();
+    }
+}
+
+fn kill_group(pgid: i32) {
+    // SAFETY: plain syscall; the group is our own child's.
+    unsafe {
+        libc::kill(-pgid, libc::SIGTERM);
+    }
+}
+
+enum Event {
+    Text(String),
+    Done(Result<String, String>),
+}
+
+/// A request running on a worker thread.
+pub struct Pending {
+    events: Receiver<Event>,
+    cancel: Arc<Cancel>,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+/// Send `request` in the background, streaming the reply if `stream` is set.
+pub fn start(
+    backend: &Arc<dyn AiBackend>,
+    request: Request,
+    timeout: Duration,
+    stream: bool,
+) -> Pending {
+    // Only Ctrl-C pressed from now on cancels the request
+    signals::take_interrupt();
+
+    let (sender, events) = mpsc::channel();
+    let cancel = Arc::clone(&request.cancel);
+    let backend = Arc::clone(backend);
+    thread::spawn(move || {
+        let result = if stream {
+            let text_sender = sender.clone();
+            backend.stream(&request, &mut |text| {
+                text_sender.send(Event::Text(text.to_string())).ok();
+            })
+        } else {
+            backend.complete(&request)
+        };
+        sender.send(Event::Done(result)).ok();
+    });
+
+    Pending {
+        events,
+        cancel,
+        timeout,
+        deadline: Instant::now() + timeout,
+    }
+}
+
+impl Pending {
+    /// Wait for the reply, handing streamed text to `on_text` as it comes.
+    /// Ctrl-C or the timeout cancels the request. The interrupt is left
+    /// pending, so a script that was running the request stops too.
+    pub fn wait(self, on_text: &mut dyn FnMut(&str)) -> Result<String, AiError> {
+        loop {
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(Event::Text(text)) => on_text(&text),
+                Ok(Event::Done(result)) => return result.map_err(AiError::Failed),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(AiError::Failed("AI backend crashed".to_string()))
+                }
+            }
+            if signals::interrupted() {
+                self.cancel.cancel();
+                return Err(AiError::Cancelled);
+            }
+            if Instant::now() >= self.deadline {
+                self.cancel.cancel();
+                return Err(AiError::TimedOut(self.timeout));
+            }
+        }
+    }
+
+    /// Give up on the request without waiting for it.
+    pub fn cancel(self) {
+        self.cancel.cancel();
+    }
+}
+
+/// Build the backend `settings` describe. `$CLAUDESH_MOCK_AI` names a
+/// fixture file and overrides the configured backend with the mock, with
+/// `$CLAUDESH_MOCK_AI_RECORD` as its record file.
+pub fn from_settings(settings: &AiSettings) -> Result<Arc<dyn AiBackend>, String> {
+    if let Some(fixtures) = env::var_os("CLAUDESH_MOCK_AI") {
+        let record = env::var_os("CLAUDESH_MOCK_AI_RECORD").map(PathBuf::from);
+        return Ok(Arc::new(Mock::load(Path::new(&fixtures), record)?));
+    }
+
+    let backend = settings.backend.as_deref().unwrap_or("claude-cli");
+    match backend {
+        "claude-cli" => Ok(Arc::new(ClaudeCli {
+            model: settings.model.clone(),
+        })),
+        "anthropic" => Ok(Arc::new(AnthropicApi {
+            base_url: settings
+                .base_url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ANTHROPIC_URL.to_string()),
+            api_key: settings
+                .api_key
+                .clone()
+                .or_else(|| env::var("ANTHROPIC_API_KEY").ok()),
+            model: settings
+                .model
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ANTHROPIC_MODEL.to_string()),
+            max_tokens: settings.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            temperature: settings.temperature,
+        })),
+        "openai" => {
+            let base_url = settings
+                .base_url
+                .clone()
+                .ok_or("ai.base_url is required for the openai backend")?;
+            let model = settings
+                .model
+                .clone()
+                .ok_or("ai.model is required for the openai backend")?;
+            Ok(Arc::new(OpenAiCompatible {
+                base_url,
+                // Local servers usually don't want a key at all
+                api_key: settings
+                    .api_key
+                    .clone()
+                    .or_else(|| env::var("OPENAI_API_KEY").ok()),
+                model,
+                max_tokens: settings.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+                temperature: settings.temperature,
+            }))
+        }
+        "mock" => {
+            let fixtures = settings
+                .fixtures
+                .as_deref()
+                .ok_or("ai.fixtures is required for the mock backend")?;
+            Ok(Arc::new(Mock::load(fixtures, settings.record.clone())?))
+        }
+        other => Err(format!(
+            "unknown ai.backend '{}' (expected claude-cli, anthropic, openai or mock)",
+            other
+        )),
+    }
+}
+
+// ─── Claude CLI ──────────────────────────────────────────────────────────────
+
+/// Shells out to `claude --print`, using whatever account the CLI is logged
+/// in with.
+struct ClaudeCli {
+    model: Option<String>,
+}
+
+impl ClaudeCli {
+    /// Run `claude --print` with `args`, handing each line of its output to
+    /// `on_line`. The CLI gets a process group of its own, so Ctrl-C reaches
+    /// claudesh rather than the CLI, and cancelling the request kills it.
+    fn run(
+        &self,
+        request: &Request,
+        args: &[&str],
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<(), String> {
+        let mut command = Command::new("claude");
+        command
+            .arg("--print")
+            .arg("--system-prompt")
+            .arg(&request.system);
+        if let Some(model) = &self.model {
+            command.arg("--model").arg(model);
+        }
+        let mut child = command
+            .args(args)
+            .arg(&request.message)
+            .current_dir(&request.cwd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| format!("failed to run claude: {}", e))?;
+        request.cancel.track(&child);
+
+        // Drain stderr on the side so a chatty CLI can't block on it
+        let mut stderr = child.stderr.take().expect("piped stderr");
+        let stderr_reader = thread::spawn(move || {
+            let mut err = String::new();
+            stderr.read_to_string(&mut err).ok();
+            err
+        });
+
+        let stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+        for line in stdout.lines().map_while(Result::ok) {
+            on_line(&line);
+        }
+
+        request.cancel.untrack();
+        let status = child.wait().map_err(|e| format!("claude error: {}", e))?;
+        let err = stderr_reader.join().unwrap_or_default();
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("claude error: {}", err.trim()))
+        }
+    }
+}
+
+impl AiBackend for ClaudeCli {
+    fn complete(&self, request: &Request) -> Result<String, String> {
+        let mut text = String::new();
+        self.run(request, &[], &mut |line| {
+            text.push_str(line);
+            text.push('\n');
+        })?;
+        Ok(text)
+    }
+
+    /// Reads the CLI's stream-json output: one JSON event per line, with the
+    /// text arriving in `content_block_delta` events.
+    fn stream(&self, request: &Request, on_text: &mut dyn FnMut(&str)) -> Result<String, String> {
+        let args = [
+            "--output-format",
+            "stream-json",
+            "--verbose",
+            "--include-partial-messages",
+        ];
+        let mut text = String::new();
+        let mut failure = None;
+        let result = self.run(request, &args, &mut |line| {
+            let Ok(event) = serde_json::from_str::<Value>(line) else {
+                return;
+            };
+            match event["type"].as_str() {
+                Some("stream_event") => {
+                    if let Some(delta) = text_delta(&event["event"]) {
+                        text.push_str(delta);
+                        on_text(delta);
+                    }
+                }
+                Some("result") if event["is_error"] == true => {
+                    failure = event["result"].as_str().map(str::to_string);
+                }
+                // Without partial messages, the whole reply comes at the end
+                Some("result") if text.is_empty() => {
+                    if let Some(result) = event["result"].as_str() {
+                        text.push_str(result);
+                        on_text(result);
+                    }
+                }
+                _ => {}
+            }
+        });
+        match failure {
+            Some(message) => Err(format!("claude error: {}", message)),
+            None => result.map(|()| text),
+        }
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        match which::which("claude") {
+            Ok(_) => None,
+            Err(_) => Some("'claude' CLI not found in PATH".to_string()),
+        }
+    }
+}
+
+// ─── Anthropic Messages API ──────────────────────────────────────────────────
+
+struct AnthropicApi {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    max_tokens: u32,
+    temperature: Option<f32>,
+}
+
+impl AnthropicApi {
+    fn post(&self, request: &Request) -> Result<ureq::Response, String> {
+        let api_key = self.api_key.as_deref().ok_or("no Anthropic API key")?;
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "system": request.system,
+            "messages": [{ "role": "user", "content": request.message }],
+            "stream": true,
+        });
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+
+        let http = agent()
+            .post(&format!(
+                "{}/v1/messages",
+                self.base_url.trim_end_matches('/')
+            ))
+            .set("x-api-key", api_key)
+            .set("anthropic-version", ANTHROPIC_VERSION);
+        post(http, &body)
+    }
+}
+
+impl AiBackend for AnthropicApi {
+    fn complete(&self, request: &Request) -> Result<String, String> {
+        self.stream(request, &mut |_| {})
+    }
+
+    fn stream(&self, request: &Request, on_text: &mut dyn FnMut(&str)) -> Result<String, String> {
+        let mut text = String::new();
+        read_sse(request, self.post(request)?, &mut |event| {
+            match event["type"].as_str() {
+                Some("error") => {
+                    let message = event["error"]["message"].as_str().unwrap_or("stream error");
+                    return Err(format!("AI error: {}", message));
+                }
+                Some("message_stop") => return Ok(false),
+                _ => {}
+            }
+            if let Some(delta) = text_delta(&event) {
+                text.push_str(delta);
+                on_text(delta);
+            }
+            Ok(true)
+        })?;
+        Ok(text)
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        match self.api_key {
+            Some(_) => None,
+            None => Some("no Anthropic API key (set ANTHROPIC_API_KEY or ai.api_key)".to_string()),
+        }
+    }
+}
+
+// ─── OpenAI-compatible ───────────────────────────────────────────────────────
+
+/// Any server speaking the OpenAI chat completions protocol, which covers
+/// most ways of running a model locally.
+struct OpenAiCompatible {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    max_tokens: u32,
+    temperature: Option<f32>,
+}
+
+impl OpenAiCompatible {
+    fn post(&self, request: &Request) -> Result<ureq::Response, String> {
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "messages": [
+                { "role": "system", "content": request.system },
+                { "role": "user", "content": request.message },
+            ],
+            "stream": true,
+        });
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+
+        let mut http = agent().post(&format!(
+            "{}/chat/completions",
+            self.base_url.trim_end_matches('/')
+        ));
+        if let Some(api_key) = &self.api_key {
+            http = http.set("Authorization", &format!("Bearer {}", api_key));
+        }
+        post(http, &body)
+    }
+}
+
+impl AiBackend for OpenAiCompatible {
+    fn complete(&self, request: &Request) -> Result<String, String> {
+        self.stream(request, &mut |_| {})
+    }
+
+    fn stream(&self, request: &Request, on_text: &mut dyn FnMut(&str)) -> Result<String, String> {
+        let mut text = String::new();
+        read_sse(request, self.post(request)?, &mut |event| {
+            if let Some(message) = event["error"]["message"].as_str() {
+                return Err(format!("AI error: {}", message));
+            }
+            if let Some(delta) = event["choices"][0]["delta"]["content"].as_str() {
+                text.push_str(delta);
+                on_text(delta);
+            }
+            Ok(true)
+        })?;
+        Ok(text)
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        None
+    }
+}
+
+// ─── Mock ────────────────────────────────────────────────────────────────────
+
+/// Offline backend answering from a fixture file, for testing the REPL
+/// without a network or an account. The fixture file is TOML:
+///
+/// ```toml
+/// [[response]]
+/// kind = "generate"         # optional: generate, script, explain, ask, fix, judgy, history, hint
+/// input = "*disk usage*"    # optional: `*` matches anything, otherwise exact
+/// reply = "du -sh ."
+/// ```
+///
+/// The first matching response wins; a request nothing matches is an error.
+struct Mock {
+    responses: Vec<MockResponse>,
+    record: Option<Mutex<fs::File>>,
+}
+
+#[derive(Deserialize)]
+struct MockFixtures {
+    #[serde(default)]
+    response: Vec<MockResponse>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockResponse {
+    kind: Option<String>,
+    input: Option<String>,
+    reply: String,
+    /// Seconds to take over the reply, for exercising timeouts and Ctrl-C.
+    delay: Option<f64>,
+}
+
+impl Mock {
+    fn load(fixtures: &Path, record: Option<PathBuf>) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(fixtures).map_err(|e| format!("{}: {}", fixtures.display(), e))?;
+        let parsed: MockFixtures =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", fixtures.display(), e))?;
+        let record = match record {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?,
+            )),
+            None => None,
+        };
+        Ok(Mock {
+            responses: parsed.response,
+            record,
+        })
+    }
+}
+
+impl AiBackend for Mock {
+    fn complete(&self, request: &Request) -> Result<String, String> {
+        if let Some(record) = &self.record {
+            let entry = json!({
+                "kind": request.kind.name(),
+                "input": &request.input,
+                "system": request.system,
+                "message": request.message,
+                "cwd": request.cwd,
+            });
+            let mut file = record.lock().unwrap();
+            writeln!(file, "{}", entry).ok();
+        }
+
+        let response = self
+            .responses
+            .iter()
+            .find(|r| {
+                r.kind.as_deref().is_none_or(|k| k == request.kind.name())
+                    && r.input
+                        .as_deref()
+                        .is_none_or(|p| wildcard_match(p, &request.input))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "mock AI: no fixture for {} input '{}'",
+                    request.kind.name(),
+                    request.input
+                )
+            })?;
+
+        if let Some(delay) = response.delay {
+            let until = Instant::now() + Duration::from_secs_f64(delay);
+            while Instant::now() < until && !request.is_cancelled() {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        Ok(response.reply.clone())
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+// ─── HTTP ────────────────────────────────────────────────────────────────────
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_read(HTTP_IDLE_TIMEOUT)
+        .build()
+}
+
+/// POST `body`, turning HTTP and API errors into something readable.
+fn post(request: ureq::Request, body: &Value) -> Result<ureq::Response, String> {
+    match request.send_json(body) {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(code, response)) => {
+            let detail = response
+                .into_json::<Value>()
+                .ok()
+                .and_then(|v| v["error"]["message"].as_str().map(str::to_string))
+                .unwrap_or_default();
+            Err(format!("AI error: HTTP {} {}", code, detail)
+                .trim_end()
+                .to_string())
+        }
+        Err(e) => Err(format!("AI error: {}", e)),
+    }
+}
+
+/// Feed each `data:` payload of a server-sent event stream to `on_event`
+/// until the stream ends, `[DONE]` arrives or `on_event` returns false.
+/// Once `request` is cancelled the response is dropped, closing the
+/// connection so the server stops generating.
+fn read_sse(
+    request: &Request,
+    response: ureq::Response,
+    on_event: &mut dyn FnMut(Value) -> Result<bool, String>,
+) -> Result<(), String> {
+    let reader = BufReader::new(response.into_reader());
+    for line in reader.lines() {
+        if request.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        let line = line.map_err(|e| format!("AI error: {}", e))?;
+        let Some(data) = line.strip_prefix("data:") else {
+            continue;
+        };
+        let data = data.trim();
+        if data == "[DONE]" {
+            break;
+        }
+        let Ok(event) = serde_json::from_str(data) else {
+            continue;
+        };
+        if !on_event(event)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The text in a Messages API `content_block_delta` event.
+fn text_delta(event: &Value) -> Option<&str> {
+    if event["type"] == "content_block_delta" && event["delta"]["type"] == "text_delta" {
+        event["delta"]["text"].as_str()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_patterns() {
+        for (pattern, text) in [
+            ("ls", "ls"),
+            ("*", ""),
+            ("*", "anything"),
+            ("list *", "list files"),
+            ("*files", "list files"),
+            ("*big*", "show big files"),
+            ("a*b*c", "abc"),
+            ("a*b*c", "a-b-b-c"),
+        ] {
+            assert!(wildcard_match(pattern, text), "{} ~ {}", pattern, text);
+        }
+        for (pattern, text) in [
+            ("ls", "ls -l"),
+            ("list *", "show files"),
+            ("*files", "files here"),
+            ("a*b*c", "acb"),
+            ("ab*ba", "aba"),
+        ] {
+            assert!(!wildcard_match(pattern, text), "{} !~ {}", pattern, text);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Completions from the system's bash-completion scripts.
+//!
+//! A helper bash loads bash-completion once and then answers requests on
+//! its stdin: for each one it sets `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE`
+//! and `COMP_POINT`, runs the command's completion spec and prints
+//! `COMPREPLY` back. Whether a command has a spec at all is remembered, so
+//! commands without one never wait on the helper again.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+/// How long a completion function may take before the helper is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Printed by the helper after each reply.
+const END: &str = "\x1eclaudesh-end";
+
+/// Set up the helper: load bash-completion if it's installed, and define
+/// the functions the requests call.
+const SETUP: &str = r#"
+for f in /usr/share/bash-completion/bash_completion \
+         /usr/local/share/bash-completion/bash_completion \
+         /opt/homebrew/share/bash-completion/bash_completion \
+         /etc/bash_completion; do
+    [ -r "$f" ] && { . "$f"; break; }
+done >/dev/null 2>&1 </dev/null
+
+# Find the command's spec the way bash-completion would, or by sourcing its
+# file directly when only the per-command files are installed
+__claudesh_load() {
+    complete -p -- "$1" && return
+    if declare -F _comp_load; then
+        _comp_load -- "$1"
+    elif declare -F __load_completion; then
+        __load_completion "$1"
+    elif declare -F _completion_loader; then
+        _completion_loader "$1"
+    else
+        local dir
+        for dir in "${BASH_COMPLETION_USER_DIR:-${XDG_DATA_HOME:-$HOME/.local/share}/bash-completion}/completions" \
+                   /usr/share/bash-completion/completions \
+                   /usr/local/share/bash-completion/completions \
+                   /opt/homebrew/share/bash-completion/completions; do
+            [ -r "$dir/$1" ] && { . "$dir/$1"; return; }
+        done
+    fi
+} >/dev/null 2>&1 </dev/null
+
+# __claudesh_complete DIR CWORD LINE WORD...
+# Prints the spec (empty if there is none), then one candidate per line
+__claudesh_complete() {
+    cd -- "$1" 2>/dev/null
+    COMP_CWORD=$2
+    COMP_LINE=$3
+    COMP_POINT=${#3}
+    shift 3
+    COMP_WORDS=("$@")
+    COMP_TYPE=9
+    COMP_KEY=9
+    COMPREPLY=()
+
+    __claudesh_load "$1"
+    local spec
+    spec=$(complete -p -- "$1" 2>/dev/null)
+    printf '%s\n' "$spec"
+    [ -n "$spec" ] || return
+
+    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}
+    local func=
+    [[ $spec =~ \ -F\ ([^ ]+) ]] && func=${BASH_REMATCH[1]}
+    if [ -n "$func" ]; then
+        "$func" "$1" "$cur" "$prev"
+    else
+        # Other actions (-W, -d, -A ...) work the same through compgen
+        local actions=${spec#complete }
+        actions=${actions% *}
+        mapfile -t COMPREPLY < <(eval "compgen $actions -- \"\$cur\"")
+    fi >/dev/null 2>&1 </dev/null
+    printf '%s\n' "${COMPREPLY[@]}"
+}
+"#;
+
+/// Completions for a word, as bash-completion produced them.
+pub struct Reply {
+    pub candidates: Vec<String>,
+    /// The spec asked for no space after a completed word (`-o nospace`).
+    pub nospace: bool,
+    /// Candidates are file names (`-o filenames`), so directories get a
+    /// trailing slash and special characters need escaping.
+    pub filenames: bool,
+}
+
+#[derive(Default)]
+pub struct BashCompletion {
+    helper: Option<Helper>,
+    /// Whether each command asked about so far has a completion spec.
+    has_spec: HashMap<String, bool>,
+    /// Set once the helper couldn't be started.
+    broken: bool,
+}
+
+struct Helper {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl BashCompletion {
+    /// Complete the last of `words`, the words of the simple command `line`
+    /// up to the cursor, as typed. `None` means bash-completion has nothing
+    /// to say and the caller should complete on its own.
+    pub fn complete(&mut self, line: &str, words: &[&str], cwd: &Path) -> Option<Reply> {
+        let command = words.first()?;
+        if self.broken || self.has_spec.get(*command) == Some(&false) {
+            return None;
+        }
+        if self.helper.is_none() {
+            self.helper = Helper::spawn();
+            self.broken = self.helper.is_none();
+        }
+        let helper = self.helper.as_mut()?;
+
+        let mut request = format!(
+            "__claudesh_complete {} {} {}",
+            quote(&cwd.to_string_lossy()),
+            words.len() - 1,
+            quote(line)
+        );
+        for word in words {
+            request.push(' ');
+            request.push_str(&quote(word));
+        }
+        request.push_str(&format!("; printf '%s\\n' {}\n", quote(END)));
+
+        let Some(mut lines) = helper.ask(&request) else {
+            // The completion function hung or the helper died. Start afresh
+            // next time, but leave this command to the caller from now on
+            self.helper = None;
+            self.has_spec.insert(command.to_string(), false);
+            return None;
+        };
+
+        let spec = if lines.is_empty() {
+            String::new()
+        } else {
+            lines.remove(0)
+        };
+        self.has_spec.insert(command.to_string(), !spec.is_empty());
+        lines.retain(|line| !line.is_empty());
+        if !spec.contains("-o nosort") {
+            lines.sort_unstable();
+            lines.dedup();
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        Some(Reply {
+            candidates: lines,
+            nospace: spec.contains("-o nospace"),
+            filenames: spec.contains("-o filenames"),
+        })
+    }
+}
+
+impl Helper {
+    fn spawn() -> Option<Helper> {
+        let mut child = Command::new("bash")
+            .args(["--norc", "--noprofile"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            // Keep it out of the terminal's way, and Ctrl-C's
+            .process_group(0)
+            .spawn()
+            .ok()?;
+        let mut stdin = child.stdin.take()?;
+        let stdout = child.stdout.take()?;
+        stdin.write_all(SETUP.as_bytes()).ok()?;
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Helper {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    /// Send `request` and collect the lines printed before `END`.
+    fn ask(&mut self, request: &str) -> Option<Vec<String>> {
+        self.stdin.write_all(request.as_bytes()).ok()?;
+        self.stdin.flush().ok()?;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.lines.recv_timeout(TIMEOUT).ok()?;
+            if line == END {
+                return Some(lines);
+            }
+            lines.push(line);
+        }
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// `text` as a single-quoted bash word.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
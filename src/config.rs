@@ -0,0 +1,262 @@
+//! The settings file, `~/.claudesh/config.toml`.
+//!
+//! Every section is optional, and so is every setting in it: anything left
+//! out keeps its default. Unknown sections and settings are errors, so a
+//! typo gets reported instead of quietly doing nothing.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::ai::AiSettings;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub ai: AiSettings,
+    #[serde(default)]
+    pub ui: UiSettings,
+    #[serde(default)]
+    pub safety: SafetySettings,
+    #[serde(default)]
+    pub history: HistorySettings,
+    #[serde(default)]
+    pub prompts: PromptSettings,
+    #[serde(default)]
+    pub context: ContextSettings,
+}
+
+/// The `[ui]` section.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UiSettings {
+    /// Ask the AI to predict the line being typed, shown as a hint.
+    #[serde(default)]
+    pub ai_hints: bool,
+    /// Prompt templates; see `prompt`.
+    pub prompt: Option<String>,
+    pub right_prompt: Option<String>,
+    /// Color the line as it's typed. On by default.
+    pub highlight: Option<bool>,
+    /// What plain English is colored as it's typed, by a name templates
+    /// take: blue by default.
+    pub ai_color: Option<String>,
+    /// Offer AI help when a command fails. On by default.
+    pub error_help: Option<bool>,
+    /// Start in judgy mode.
+    #[serde(default)]
+    pub judgy: bool,
+}
+
+/// The `[safety]` section.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SafetySettings {
+    /// Start in yolo mode.
+    #[serde(default)]
+    pub yolo: bool,
+    /// Offer to retry with sudo after a permission error. On by default.
+    pub sudo_retry: Option<bool>,
+}
+
+/// The `[history]` section.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HistorySettings {
+    /// Lines kept for recall with the arrow keys.
+    pub size: Option<usize>,
+    /// Leave out lines that start with a space, as bash's `ignorespace` does.
+    #[serde(default)]
+    pub ignore_space: bool,
+    /// Record commands in history.jsonl. On by default.
+    pub record: Option<bool>,
+}
+
+/// The `[context]` section: the session transcript sent along with requests.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ContextSettings {
+    /// Recent commands included, 0 to send none.
+    pub commands: Option<usize>,
+    /// Characters of each command's error output included.
+    pub output: Option<usize>,
+}
+
+/// The `[prompts]` section: where the AI prompts are read from. Relative
+/// paths are taken from ~/.claudesh.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PromptSettings {
+    /// Directory of prompt files, `prompts` by default.
+    pub dir: Option<PathBuf>,
+    /// Files for single prompts, instead of the one in `dir`.
+    pub generate: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    pub explain: Option<PathBuf>,
+    pub ask: Option<PathBuf>,
+    pub fix: Option<PathBuf>,
+    pub judgy: Option<PathBuf>,
+    pub history: Option<PathBuf>,
+    pub hint: Option<PathBuf>,
+    pub personality: Option<PathBuf>,
+}
+
+impl ConfigFile {
+    /// Read and check the file at `path`. No file means all defaults.
+    pub fn load(path: &Path) -> Result<ConfigFile, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ConfigFile::default()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let file: ConfigFile = toml::from_str(&text).map_err(|e| e.to_string())?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    /// Catch values that parse but make no sense.
+    fn validate(&self) -> Result<(), String> {
+        if self.ai.timeout == Some(0) {
+            return Err("ai.timeout must be at least 1 second".to_string());
+        }
+        if self.ai.max_tokens == Some(0) {
+            return Err("ai.max_tokens must be at least 1".to_string());
+        }
+        if let Some(temperature) = self.ai.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(format!(
+                    "ai.temperature must be between 0 and 2, not {}",
+                    temperature
+                ));
+            }
+        }
+        if self.history.size == Some(0) {
+            return Err("history.size must be at least 1".to_string());
+        }
+        if let Some(color) = &self.ui.ai_color {
+            if crate::prompt::color(color).is_none() {
+                return Err(format!(
+                    "ui.ai_color must be a color like blue or green, not {}",
+                    color
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PromptSettings {
+    /// Where to read the prompt `name` (`generate`, `personality`, ...) from,
+    /// and whether the path was set explicitly. The personality lives in
+    /// `config_dir` itself rather than the prompts directory.
+    pub fn path(&self, config_dir: &Path, name: &str) -> (PathBuf, bool) {
+        let file = match name {
+            "generate" => &self.generate,
+            "script" => &self.script,
+            "explain" => &self.explain,
+            "ask" => &self.ask,
+            "fix" => &self.fix,
+            "judgy" => &self.judgy,
+            "history" => &self.history,
+            "hint" => &self.hint,
+            "personality" => &self.personality,
+            _ => &None,
+        };
+        if let Some(file) = file {
+            return (resolve(config_dir, file), true);
+        }
+        if name == "personality" {
+            return (config_dir.join(name), false);
+        }
+        let dir = match &self.dir {
+            Some(dir) => resolve(config_dir, dir),
+            None => config_dir.join("prompts"),
+        };
+        (dir.join(format!("{}.txt", name)), false)
+    }
+}
+
+/// `path` with `~` expanded, relative to `config_dir`.
+fn resolve(config_dir: &Path, path: &Path) -> PathBuf {
+    config_dir.join(crate::shellexpand_tilde(&path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<ConfigFile, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    fn error(text: &str) -> String {
+        parse(text).err().unwrap()
+    }
+
+    #[test]
+    fn sections_and_defaults() {
+        let file =
+            parse("[ui]\nai_hints = true\nai_color = \"green\"\n[history]\nsize = 50\n").unwrap();
+        assert!(file.ui.ai_hints);
+        assert_eq!(file.ui.ai_color.as_deref(), Some("green"));
+        assert_eq!(file.history.size, Some(50));
+        assert!(!file.safety.yolo);
+        assert!(parse("").is_ok());
+    }
+
+    #[test]
+    fn unknown_settings_are_errors() {
+        assert!(error("[ui]\nhighlite = false\n").contains("unknown field `highlite`"));
+        assert!(error("[colors]\nai = \"blue\"\n").contains("unknown field `colors`"));
+    }
+
+    #[test]
+    fn timeout_must_be_positive() {
+        assert_eq!(
+            error("[ai]\ntimeout = 0\n"),
+            "ai.timeout must be at least 1 second"
+        );
+        assert!(parse("[ai]\ntimeout = 1\n").is_ok());
+    }
+
+    #[test]
+    fn max_tokens_must_be_positive() {
+        assert_eq!(
+            error("[ai]\nmax_tokens = 0\n"),
+            "ai.max_tokens must be at least 1"
+        );
+    }
+
+    #[test]
+    fn temperature_must_be_in_range() {
+        assert_eq!(
+            error("[ai]\ntemperature = 2.5\n"),
+            "ai.temperature must be between 0 and 2, not 2.5"
+        );
+        assert!(error("[ai]\ntemperature = -1.0\n").starts_with("ai.temperature"));
+        assert!(parse("[ai]\ntemperature = 0.0\n").is_ok());
+        assert!(parse("[ai]\ntemperature = 2.0\n").is_ok());
+    }
+
+    #[test]
+    fn history_size_must_be_positive() {
+        assert_eq!(
+            error("[history]\nsize = 0\n"),
+            "history.size must be at least 1"
+        );
+    }
+
+    #[test]
+    fn ai_color_must_be_a_color() {
+        assert_eq!(
+            error("[ui]\nai_color = \"teal\"\n"),
+            "ui.ai_color must be a color like blue or green, not teal"
+        );
+        assert!(parse("[ui]\nai_color = \"magenta\"\n").is_ok());
+    }
+}
@@ -0,0 +1,394 @@
+//! Facts about the system and the current directory for the AI, so that it
+//! suggests `dnf` on Fedora, BSD flags on macOS and `pnpm` where a project
+//! uses it.
+//!
+//! What doesn't change while the shell runs is worked out once, on first
+//! use. What kind of project a directory holds is worked out the first time
+//! a request is made from it and remembered for the session; the git state
+//! is read afresh each time, since that's cheap and changes as the user works.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+/// Package managers in the order they're looked for. Where a system has
+/// several, the one listed first is the one it's built around.
+const PACKAGE_MANAGERS: &[&str] = &[
+    "apt",
+    "dnf",
+    "yum",
+    "pacman",
+    "zypper",
+    "apk",
+    "emerge",
+    "xbps-install",
+    "nix-env",
+    "brew",
+    "port",
+    "pkg",
+];
+
+/// Tools whose options differ between the GNU and BSD versions.
+const FLAVORED_TOOLS: &[&str] = &["ls", "sed", "grep", "find", "date", "stat", "tar", "xargs"];
+
+/// Tools worth knowing about when suggesting a command.
+const KEY_TOOLS: &[&str] = &[
+    "git",
+    "gh",
+    "docker",
+    "podman",
+    "kubectl",
+    "systemctl",
+    "launchctl",
+    "python3",
+    "pip3",
+    "uv",
+    "node",
+    "npm",
+    "pnpm",
+    "yarn",
+    "bun",
+    "deno",
+    "cargo",
+    "go",
+    "java",
+    "make",
+    "cmake",
+    "rg",
+    "fd",
+    "fdfind",
+    "jq",
+    "yq",
+    "fzf",
+    "bat",
+    "eza",
+    "curl",
+    "wget",
+    "rsync",
+    "ssh",
+    "tmux",
+    "ffmpeg",
+    "magick",
+    "convert",
+    "sqlite3",
+    "psql",
+    "mysql",
+    "zstd",
+    "7z",
+    "unzip",
+    "ip",
+    "ss",
+    "lsof",
+    "aws",
+    "terraform",
+];
+
+/// Files that say what kind of project a directory holds. Only the first
+/// match for each kind counts, so lock files come before the manifest.
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "Rust (cargo)"),
+    ("pnpm-lock.yaml", "Node.js (pnpm)"),
+    ("yarn.lock", "Node.js (yarn)"),
+    ("bun.lockb", "Node.js (bun)"),
+    ("package-lock.json", "Node.js (npm)"),
+    ("package.json", "Node.js"),
+    ("deno.json", "Deno"),
+    ("uv.lock", "Python (uv)"),
+    ("poetry.lock", "Python (poetry)"),
+    ("pyproject.toml", "Python"),
+    ("requirements.txt", "Python (pip)"),
+    ("go.mod", "Go"),
+    ("Gemfile", "Ruby (bundler)"),
+    ("pom.xml", "Java (maven)"),
+    ("build.gradle", "Java (gradle)"),
+    ("build.gradle.kts", "Kotlin (gradle)"),
+    ("mix.exs", "Elixir (mix)"),
+    ("composer.json", "PHP (composer)"),
+    ("CMakeLists.txt", "C/C++ (cmake)"),
+    ("meson.build", "Meson"),
+    ("Makefile", "Make"),
+    ("flake.nix", "Nix flake"),
+    ("Dockerfile", "Docker"),
+    ("compose.yaml", "Docker Compose"),
+    ("docker-compose.yml", "Docker Compose"),
+];
+
+#[derive(Default)]
+pub struct Collector {
+    system: OnceLock<System>,
+    /// `KEY_TOOLS` that are on `$PATH`.
+    tools: Mutex<Vec<&'static str>>,
+    /// Project kinds by directory.
+    projects: Mutex<HashMap<PathBuf, Vec<&'static str>>>,
+}
+
+struct System {
+    distro: String,
+    package_manager: String,
+    /// Each of `FLAVORED_TOOLS` that's installed, with its flavor.
+    flavors: Vec<(&'static str, &'static str)>,
+}
+
+impl Collector {
+    /// Note which `KEY_TOOLS` are among `path_commands`, the commands on
+    /// `$PATH`.
+    pub fn set_tools(&self, path_commands: &HashSet<String>) {
+        let tools = KEY_TOOLS
+            .iter()
+            .copied()
+            .filter(|tool| path_commands.contains(*tool))
+            .collect();
+        *self.tools.lock().unwrap() = tools;
+    }
+
+    /// The distribution's name, empty where there isn't one.
+    pub fn distro(&self) -> &str {
+        &self.system().distro
+    }
+
+    /// The system's package manager, empty if none was found.
+    pub fn package_manager(&self) -> &str {
+        &self.system().package_manager
+    }
+
+    /// Everything known about the system and `cwd`, a line per fact.
+    pub fn describe(&self, cwd: &Path) -> String {
+        let system = self.system();
+        let mut lines = Vec::new();
+        if !system.distro.is_empty() {
+            lines.push(format!("Distribution: {}", system.distro));
+        }
+        if !system.package_manager.is_empty() {
+            lines.push(format!("Package manager: {}", system.package_manager));
+        }
+        if !system.flavors.is_empty() {
+            let flavors: Vec<String> = system
+                .flavors
+                .iter()
+                .map(|(tool, flavor)| format!("{} {}", flavor, tool))
+                .collect();
+            lines.push(format!("Core tools: {}", flavors.join(", ")));
+        }
+        let tools = self.tools.lock().unwrap().join(", ");
+        if !tools.is_empty() {
+            lines.push(format!("Also installed: {}", tools));
+        }
+        if let Some(git) = git_state(cwd) {
+            lines.push(format!("Git: {}", git));
+        }
+        let mut projects = self.projects.lock().unwrap();
+        let kinds = projects
+            .entry(cwd.to_path_buf())
+            .or_insert_with(|| project_kinds(cwd));
+        if !kinds.is_empty() {
+            lines.push(format!("Project: {}", kinds.join(", ")));
+        }
+        lines.join("\n")
+    }
+
+    fn system(&self) -> &System {
+        self.system.get_or_init(System::detect)
+    }
+}
+
+impl System {
+    fn detect() -> System {
+        System {
+            distro: distro(),
+            package_manager: package_manager(),
+            flavors: FLAVORED_TOOLS
+                .iter()
+                .filter_map(|&tool| Some((tool, flavor(tool)?)))
+                .collect(),
+        }
+    }
+}
+
+/// The distribution's name from `/etc/os-release`, e.g. "Fedora Linux 40
+/// (Workstation Edition)". Empty where there's no such file, as on macOS.
+fn distro() -> String {
+    let Ok(text) = fs::read_to_string("/etc/os-release") else {
+        return String::new();
+    };
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .map(|value| value.trim().trim_matches('"').to_string())
+    };
+    field("PRETTY_NAME")
+        .or_else(|| field("NAME"))
+        .unwrap_or_default()
+}
+
+/// The system's package manager, the first of `PACKAGE_MANAGERS` on `$PATH`.
+fn package_manager() -> String {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let dirs: Vec<_> = env::split_paths(&path).collect();
+    PACKAGE_MANAGERS
+        .iter()
+        .find(|name| dirs.iter().any(|dir| is_executable(&dir.join(name))))
+        .map(|name| name.to_string())
+        .unwrap_or_default()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Which version of `tool` is installed, going by what `--version` says.
+fn flavor(tool: &str) -> Option<&'static str> {
+    let output = Command::new(tool)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).to_string()
+        + &String::from_utf8_lossy(&output.stderr);
+    Some(classify(&text))
+}
+
+/// The flavor a tool's `--version` output points to. The BSD tools don't
+/// know the option, so they fail or say nothing useful.
+fn classify(text: &str) -> &'static str {
+    if text.contains("BusyBox") {
+        "BusyBox"
+    } else if text.contains("uutils") {
+        "uutils"
+    } else if text.contains("GNU") || text.contains("Free Software Foundation") {
+        "GNU"
+    } else {
+        "BSD"
+    }
+}
+
+/// The repository containing `cwd`, the branch checked out, and anything
+/// left half done.
+fn git_state(cwd: &Path) -> Option<String> {
+    let (root, git_dir) = crate::prompt::find_git_dir(cwd)?;
+    let branch = crate::prompt::git_branch(cwd)?;
+    let mut state = format!("repository at {}, on {}", root.display(), branch);
+    let underway = [
+        ("MERGE_HEAD", "merge"),
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+        ("BISECT_LOG", "bisect"),
+    ]
+    .into_iter()
+    .find(|(file, _)| git_dir.join(file).exists());
+    if let Some((_, operation)) = underway {
+        state.push_str(&format!(", {} in progress", operation));
+    }
+    Some(state)
+}
+
+/// What kinds of project `cwd` is part of, from the marker files in it and
+/// the directories above it, up to the top of the git repository.
+fn project_kinds(cwd: &Path) -> Vec<&'static str> {
+    let top = crate::prompt::find_git_dir(cwd).map(|(root, _)| root);
+    let language = |kind: &str| kind.split(" (").next().unwrap_or(kind).to_string();
+    let mut kinds: Vec<&'static str> = Vec::new();
+    for dir in cwd.ancestors() {
+        for &(file, kind) in PROJECT_MARKERS {
+            let known = kinds.iter().any(|k| language(k) == language(kind));
+            if !known && dir.join(file).exists() {
+                kinds.push(kind);
+            }
+        }
+        if top.as_deref().is_none_or(|top| dir == top) {
+            break;
+        }
+    }
+    kinds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory `name` holding `files`, those ending in `/` being
+    /// directories.
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let root =
+            env::temp_dir().join(format!("claudesh-context-{}-{}", std::process::id(), name));
+        fs::remove_dir_all(&root).ok();
+        for file in files {
+            let path = root.join(file);
+            if file.ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, "").unwrap();
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn markers_count_in_order_once_per_language() {
+        let root = tree(
+            "order",
+            &["Makefile", "package.json", "pnpm-lock.yaml", "Cargo.toml"],
+        );
+        assert_eq!(
+            project_kinds(&root),
+            ["Rust (cargo)", "Node.js (pnpm)", "Make"]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn markers_are_looked_for_up_to_the_repository_top() {
+        let root = tree(
+            "repo",
+            &[
+                "Makefile",
+                "repo/.git/",
+                "repo/go.mod",
+                "repo/package.json",
+                "repo/web/package-lock.json",
+                "repo/web/src/",
+            ],
+        );
+        // The nearest Node.js marker wins, and the Makefile is outside
+        assert_eq!(
+            project_kinds(&root.join("repo/web/src")),
+            ["Node.js (npm)", "Go"]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn outside_a_repository_only_the_directory_counts() {
+        let root = tree("plain", &["Cargo.toml", "sub/"]);
+        assert_eq!(project_kinds(&root), ["Rust (cargo)"]);
+        assert!(project_kinds(&root.join("sub")).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn flavors() {
+        for (text, flavor) in [
+            (
+                "ls (GNU coreutils) 9.4\nCopyright (C) 2023 Free Software Foundation",
+                "GNU",
+            ),
+            ("find (GNU findutils) 4.9.0", "GNU"),
+            ("BusyBox v1.36.1 (2023-11-07) multi-call binary.", "BusyBox"),
+            ("ls (uutils coreutils) 0.0.27", "uutils"),
+            ("bsdtar 3.5.3 - libarchive 3.5.3", "BSD"),
+            (
+                "ls: unrecognized option `--version'\nusage: ls [-@ABC]",
+                "BSD",
+            ),
+            ("", "BSD"),
+        ] {
+            assert_eq!(classify(text), flavor, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,705 @@
+//! Line editor integration.
+//!
+//! `ShellHelper` is the rustyline helper for the interactive REPL. It knows
+//! the same command names the classifier does, so it can tell an unfinished
+//! shell construct apart from a natural-language request that merely
+//! contains an apostrophe. The same knowledge drives tab completion, which
+//! stays out of the way of plain-English requests, and the highlighting
+//! that shows as you type whether a line will run in bash or go to the AI.
+//! Hints come from history, or from the AI when that's turned on.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::{DefaultHistory, History, SearchDirection};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Context, Editor, Event, EventContext, Helper, RepeatCount,
+};
+
+use crate::bash_completion::{BashCompletion, Reply};
+use crate::hints::AiHints;
+use crate::{
+    InputKind, COLOR_AI, COLOR_CYAN, COLOR_DIM, COLOR_GREEN, COLOR_MAGENTA, COLOR_RED, COLOR_RESET,
+    COLOR_YELLOW,
+};
+
+pub type LineEditor = Editor<ShellHelper, DefaultHistory>;
+
+/// Prompt shown while a statement is still open, like bash's PS2.
+pub const CONTINUATION_PROMPT: &str = "> ";
+
+/// Words after which the next word is a command again.
+const COMMAND_WRAPPERS: &[&str] = &[
+    "sudo", "doas", "then", "do", "else", "time", "nohup", "exec", "command", "builtin", "xargs",
+    "watch", "nice",
+];
+
+/// Characters that end a word when highlighting.
+const WORD_BREAKS: &str = "|&;<>()'\"$";
+
+/// Characters that need a backslash in a completed word.
+const SPECIAL_CHARS: &str = " \t'\"\\()&;|<>$`!*?[]{}#";
+
+#[derive(Default)]
+pub struct ShellHelper {
+    pub path_commands: HashSet<String>,
+    pub shell_names: HashSet<String>,
+    /// The session's working directory, which relative paths complete from.
+    pub cwd: PathBuf,
+    bash_completion: Mutex<BashCompletion>,
+    /// Predictions of the line being typed, when `ui.ai_hints` is on.
+    pub ai_hints: Option<AiHints>,
+    /// Leave the line uncolored as it's typed.
+    pub plain: bool,
+    /// What plain English is colored, if not `COLOR_AI`.
+    pub ai_color: Option<&'static str>,
+    /// Drawn at the right edge while the line leaves room for it.
+    pub right_prompt: Option<RightPrompt>,
+    /// Set when the line was submitted by a `SubmitHandler`.
+    submitted: Arc<Mutex<Option<Submit>>>,
+}
+
+/// A prompt for the right edge of the first line, like zsh's `RPROMPT`.
+pub struct RightPrompt {
+    pub text: String,
+    /// Columns taken up by the last line of the left prompt.
+    pub prompt_width: usize,
+}
+
+/// Why a line was submitted, when it wasn't simply to run it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Submit {
+    /// Alt-Enter: put a command generated from the line in its place.
+    Generate,
+    /// Ctrl-X Ctrl-E: open the line in `$EDITOR` and run what's saved.
+    Editor,
+}
+
+impl ShellHelper {
+    /// Whether `input` is shell code that needs more lines to be complete.
+    pub fn is_incomplete(&self, input: &str) -> bool {
+        crate::is_incomplete_command(input, &self.path_commands, &self.shell_names)
+    }
+
+    /// A handler to bind to the key that submits the line for `submit`.
+    pub fn submit_handler(&self, submit: Submit) -> SubmitHandler {
+        SubmitHandler {
+            submit,
+            submitted: Arc::clone(&self.submitted),
+        }
+    }
+
+    /// Why the line being read was submitted, if not to run it.
+    pub fn submitted(&self) -> Option<Submit> {
+        *self.submitted.lock().unwrap()
+    }
+
+    /// Like `submitted`, but clears it for the next line.
+    pub fn take_submitted(&self) -> Option<Submit> {
+        self.submitted.lock().unwrap().take()
+    }
+}
+
+/// Submits the line at once, noting why for `ShellHelper::take_submitted`.
+pub struct SubmitHandler {
+    submit: Submit,
+    submitted: Arc<Mutex<Option<Submit>>>,
+}
+
+impl ConditionalEventHandler for SubmitHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        // There's nothing to generate from, but an empty editor is fine
+        if self.submit == Submit::Generate && ctx.line().trim().is_empty() {
+            return Some(Cmd::Noop);
+        }
+        *self.submitted.lock().unwrap() = Some(self.submit);
+        Some(Cmd::AcceptLine)
+    }
+}
+
+impl Helper for ShellHelper {}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    /// Complete the word before the cursor: a command name in command
+    /// position, a `$VAR` from the environment, whatever the command's
+    /// bash-completion spec offers, or else a directory after `cd` or a
+    /// path. Nothing is offered once the line reads as plain English.
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        // `! cmd` and `? cmd` still take shell code, `?? question` doesn't
+        let offset = if line.starts_with('!') || (line.starts_with('?') && !line.starts_with("??"))
+        {
+            1 + line[1..].len() - line[1..].trim_start().len()
+        } else {
+            0
+        };
+        let text = &line[offset..];
+        let start = word_start(text);
+        let before = text[..start].trim();
+        let word = &text[start..];
+
+        if !before.is_empty()
+            && crate::is_natural_language(before, &self.path_commands, &self.shell_names)
+        {
+            return Ok((pos, Vec::new()));
+        }
+
+        if let Some(dollar) = variable_start(word) {
+            return Ok((offset + start + dollar, complete_variable(&word[dollar..])));
+        }
+        if is_command_position(before) && !word.contains('/') {
+            return Ok((offset + start, self.complete_command(word)));
+        }
+
+        let command = &text[segment_start(text)..];
+        let mut words = split_words(&command[..command.len() - word.len()]);
+        words.push(word);
+        let reply =
+            self.bash_completion
+                .lock()
+                .unwrap()
+                .complete(command.trim_start(), &words, &self.cwd);
+        if let Some(reply) = reply {
+            let (skip, candidates) = bash_candidates(word, reply, &self.cwd);
+            return Ok((offset + start + skip, candidates));
+        }
+
+        let dirs_only = matches!(words[0], "cd" | "pushd");
+        Ok((offset + start, complete_path(word, &self.cwd, dirs_only)))
+    }
+}
+
+impl ShellHelper {
+    /// Programs on PATH, aliases and functions, bash builtins and keywords,
+    /// and claudesh's own builtins.
+    fn complete_command(&self, prefix: &str) -> Vec<Pair> {
+        let mut names: Vec<&str> = self
+            .path_commands
+            .iter()
+            .chain(&self.shell_names)
+            .map(String::as_str)
+            .chain(crate::SHELL_BUILTINS.iter().copied())
+            .chain(crate::CLAUDESH_BUILTINS.iter().copied())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| Pair {
+                display: name.to_string(),
+                replacement: format!("{} ", name),
+            })
+            .collect()
+    }
+}
+
+/// Byte offset where the word ending at the end of `line` begins, honoring
+/// quotes and backslash escapes.
+fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (q, '\\') if q != Some('\'') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, c) if c.is_whitespace() || "|;&<>()".contains(c) => start = i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    start
+}
+
+/// Byte offset where the simple command at the end of `line` begins.
+fn segment_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (q, '\\') if q != Some('\'') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, c) if "|;&()".contains(c) => start = i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    start
+}
+
+/// The words of `text` as typed, quotes and escapes included.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = text.trim_end();
+    while !rest.is_empty() {
+        let start = word_start(rest);
+        words.push(&rest[start..]);
+        rest = rest[..start].trim_end();
+    }
+    words.reverse();
+    words
+}
+
+/// Turn a bash-completion reply for `word` into candidates, and say how far
+/// into `word` they start. Like bash, a word such as `--format=` or
+/// `host:path` is only completed after its last `=` or `:` when that's what
+/// the candidates continue from.
+fn bash_candidates(word: &str, reply: Reply, cwd: &Path) -> (usize, Vec<Pair>) {
+    let skip = if reply.candidates.iter().all(|c| c.starts_with(word)) {
+        0
+    } else {
+        word.rfind(['=', ':']).map_or(0, |i| i + 1)
+    };
+
+    let candidates = reply
+        .candidates
+        .into_iter()
+        .map(|candidate| {
+            let is_dir = reply.filenames && cwd.join(crate::shellexpand_tilde(&candidate)).is_dir();
+            let replacement = if reply.filenames {
+                escape(&candidate)
+            } else {
+                candidate.clone()
+            };
+            let suffix = if is_dir {
+                "/"
+            } else if reply.nospace || candidate.ends_with([' ', '/', '=', ':']) {
+                ""
+            } else {
+                " "
+            };
+            Pair {
+                display: format!("{}{}", candidate.trim_end(), if is_dir { "/" } else { "" }),
+                replacement: format!("{}{}", replacement, suffix),
+            }
+        })
+        .collect();
+    (skip, candidates)
+}
+
+/// Whether a word following `before` names a command to run.
+fn is_command_position(before: &str) -> bool {
+    match before.chars().last() {
+        None => true,
+        Some(c) if "|;&({!".contains(c) => true,
+        _ => before
+            .split_whitespace()
+            .last()
+            .is_some_and(|word| COMMAND_WRAPPERS.contains(&word)),
+    }
+}
+
+/// Where a `$NAME` or `${NAME` being typed starts within `word`.
+fn variable_start(word: &str) -> Option<usize> {
+    let dollar = word.rfind('$')?;
+    let name = &word[dollar + 1..];
+    let name = name.strip_prefix('{').unwrap_or(name);
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        .then_some(dollar)
+}
+
+fn complete_variable(word: &str) -> Vec<Pair> {
+    let (open, prefix) = match word.strip_prefix("${") {
+        Some(prefix) => ("${", prefix),
+        None => ("$", &word[1..]),
+    };
+    let close = if open == "${" { "}" } else { "" };
+    let mut names: Vec<String> = env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    names.sort_unstable();
+    names
+        .into_iter()
+        .map(|name| Pair {
+            replacement: format!("{}{}{}", open, name, close),
+            display: name,
+        })
+        .collect()
+}
+
+/// Files and directories matching `word`, relative to `cwd` unless it is
+/// absolute or starts with `~`. Hidden entries are offered only once a dot
+/// has been typed.
+fn complete_path(word: &str, cwd: &Path, dirs_only: bool) -> Vec<Pair> {
+    let word = unescape(word);
+    let (dir, prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word.as_str()),
+    };
+    let search = match dir {
+        "" => cwd.to_path_buf(),
+        dir => cwd.join(crate::shellexpand_tilde(dir)),
+    };
+    let Ok(entries) = fs::read_dir(&search) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<Pair> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // Follow symlinks, so a link to a directory completes like one
+            let is_dir = fs::metadata(entry.path()).is_ok_and(|m| m.is_dir());
+            if dirs_only && !is_dir {
+                return None;
+            }
+            let (display, suffix) = if is_dir {
+                (format!("{}/", name), "/")
+            } else {
+                (name.clone(), " ")
+            };
+            Some(Pair {
+                display,
+                replacement: format!("{}{}{}", escape(dir), escape(&name), suffix),
+            })
+        })
+        .collect();
+    candidates.sort_unstable_by(|a, b| a.display.cmp(&b.display));
+    candidates
+}
+
+/// `word` with quotes and backslash escapes removed.
+fn unescape(word: &str) -> String {
+    let mut out = String::new();
+    let mut quote = None;
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\\') | (Some('"'), '\\') => out.extend(chars.next()),
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (_, c) => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if SPECIAL_CHARS.contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    /// Suggest the rest of the line, fish-style: the most recent history
+    /// entry that starts with it, or else the AI's prediction. Only shell
+    /// code gets AI hints; plain English is already going to the AI.
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() || line.trim().is_empty() {
+            return None;
+        }
+        if let Some(rest) = history_hint(ctx.history(), line) {
+            return Some(rest);
+        }
+
+        let ai_hints = self.ai_hints.as_ref()?;
+        if crate::is_natural_language(line.trim(), &self.path_commands, &self.shell_names) {
+            return None;
+        }
+        ai_hints.hint(line, &self.cwd)
+    }
+}
+
+/// The rest of the most recent history entry that starts with `line`.
+fn history_hint(history: &dyn History, line: &str) -> Option<String> {
+    let mut index = history.len().checked_sub(1)?;
+    while let Ok(Some(found)) = history.starts_with(line, index, SearchDirection::Reverse) {
+        if found.entry.len() > line.len() && !found.entry.contains('\n') {
+            return Some(found.entry[line.len()..].to_string());
+        }
+        match found.idx.checked_sub(1) {
+            Some(i) => index = i,
+            None => break,
+        }
+    }
+    None
+}
+
+impl Highlighter for ShellHelper {
+    /// Color the line the way it will be handled: plain English in the AI
+    /// color, shell code with its commands, strings, variables and
+    /// operators picked out. After `!`, a command that doesn't exist is
+    /// shown as an error.
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let right_prompt = self.draw_right_prompt(line);
+        let input = line.trim_start();
+        if input.is_empty() || self.plain {
+            return match right_prompt {
+                Some(right_prompt) => Cow::Owned(format!("{}{}", line, right_prompt)),
+                None => Cow::Borrowed(line),
+            };
+        }
+        let indent = &line[..line.len() - input.len()];
+        let kind = crate::classify_input(input.trim_end(), &self.path_commands, &self.shell_names);
+        let ai_color = self.ai_color.unwrap_or(COLOR_AI);
+
+        let highlighted = match kind {
+            InputKind::NaturalLanguage(_) | InputKind::HistoryAsk(_) => {
+                format!("{}{}{}", ai_color, input, COLOR_RESET)
+            }
+            InputKind::Ask(_) => {
+                let question = &input[2..];
+                format!("{}??{}{}{}", COLOR_YELLOW, ai_color, question, COLOR_RESET)
+            }
+            InputKind::Explain(_) => format!(
+                "{}?{}{}",
+                COLOR_YELLOW,
+                COLOR_RESET,
+                self.highlight_shell(&input[1..], false)
+            ),
+            InputKind::ForceBash(_) => format!(
+                "{}!{}{}",
+                COLOR_YELLOW,
+                COLOR_RESET,
+                self.highlight_shell(&input[1..], true)
+            ),
+            InputKind::Comment => format!("{}{}{}", COLOR_DIM, input, COLOR_RESET),
+            _ => self.highlight_shell(input, false),
+        };
+        Cow::Owned(format!(
+            "{}{}{}",
+            indent,
+            highlighted,
+            right_prompt.unwrap_or_default()
+        ))
+    }
+
+    /// A hint would run into the right prompt, so clear it away first.
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        let clear = if self.right_prompt.is_some() {
+            "\x1b[K"
+        } else {
+            ""
+        };
+        Cow::Owned(format!("{}{}{}{}", clear, COLOR_DIM, hint, COLOR_RESET))
+    }
+
+    /// Classification can change with every character, so redraw on each
+    /// edit. Moving the cursor changes nothing.
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+}
+
+impl ShellHelper {
+    /// The right prompt, drawn at the right edge and the cursor put back
+    /// where it was, if `line` leaves room for it. The editor lays the line
+    /// out from the plain text, so this doesn't move anything.
+    fn draw_right_prompt(&self, line: &str) -> Option<String> {
+        let right_prompt = self.right_prompt.as_ref()?;
+        let width = crate::prompt::visible_width(&right_prompt.text);
+        let used = right_prompt.prompt_width + crate::prompt::visible_width(line);
+        let columns = crate::prompt::terminal_width()?;
+        if line.contains('\n') || used + 1 + width >= columns {
+            return None;
+        }
+        Some(format!(
+            "\x1b7\x1b[{}G{}{}\x1b8",
+            columns - width + 1,
+            right_prompt.text,
+            COLOR_RESET
+        ))
+    }
+
+    /// Shell colors for `code`: known commands green, strings yellow,
+    /// variables cyan, operators magenta and comments dim. With `strict`,
+    /// a command that isn't known is red.
+    fn highlight_shell(&self, code: &str, strict: bool) -> String {
+        let mut out = String::with_capacity(code.len() * 2);
+        let mut rest = code;
+        let mut command_next = true;
+
+        while let Some(c) = rest.chars().next() {
+            let len = match c {
+                c if c.is_whitespace() => {
+                    command_next |= c == '\n';
+                    out.push(c);
+                    rest = &rest[c.len_utf8()..];
+                    continue;
+                }
+                '#' => {
+                    let len = rest.find('\n').unwrap_or(rest.len());
+                    paint(&mut out, COLOR_DIM, &rest[..len]);
+                    len
+                }
+                '\'' | '"' => {
+                    let len = quoted_len(rest);
+                    paint(&mut out, COLOR_YELLOW, &rest[..len]);
+                    command_next = false;
+                    len
+                }
+                '$' => {
+                    let len = variable_len(rest);
+                    paint(&mut out, COLOR_CYAN, &rest[..len]);
+                    command_next = false;
+                    len
+                }
+                c if "|&;<>()".contains(c) => {
+                    let len = rest
+                        .find(|c: char| !"|&;<>()".contains(c))
+                        .unwrap_or(rest.len());
+                    let operator = &rest[..len];
+                    paint(&mut out, COLOR_MAGENTA, operator);
+                    // After a redirect comes a file name, not a command
+                    command_next = operator.contains(['|', '&', ';', '(', ')']);
+                    len
+                }
+                _ => {
+                    let len = rest
+                        .find(|c: char| c.is_whitespace() || WORD_BREAKS.contains(c))
+                        .unwrap_or(rest.len());
+                    let word = &rest[..len];
+                    if command_next && !word.contains('=') {
+                        if self.is_known_command(word) {
+                            paint(&mut out, COLOR_GREEN, word);
+                        } else if strict && !word.contains('/') {
+                            paint(&mut out, COLOR_RED, word);
+                        } else {
+                            out.push_str(word);
+                        }
+                        command_next = COMMAND_WRAPPERS.contains(&word);
+                    } else {
+                        out.push_str(word);
+                    }
+                    len
+                }
+            };
+            rest = &rest[len..];
+        }
+        out
+    }
+
+    fn is_known_command(&self, word: &str) -> bool {
+        self.path_commands.contains(word)
+            || self.shell_names.contains(word)
+            || crate::SHELL_BUILTINS.contains(&word)
+            || crate::CLAUDESH_BUILTINS.contains(&word)
+    }
+}
+
+fn paint(out: &mut String, color: &str, text: &str) {
+    out.push_str(color);
+    out.push_str(text);
+    out.push_str(COLOR_RESET);
+}
+
+/// Length of the quoted string `text` starts with, up to and including the
+/// closing quote, or all of `text` if it's still open.
+fn quoted_len(text: &str) -> usize {
+    let quote = text.chars().next().unwrap_or('"');
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote == '"' {
+            escaped = true;
+        } else if c == quote {
+            return i + 1;
+        }
+    }
+    text.len()
+}
+
+/// Length of the `$NAME`, `${...}` or special parameter `text` starts with.
+/// A bare `$` or the `$` of `$(...)` is just the one character.
+fn variable_len(text: &str) -> usize {
+    let after = &text[1..];
+    if after.starts_with('{') {
+        return after.find('}').map_or(text.len(), |i| i + 2);
+    }
+    match after.chars().next() {
+        Some(c) if c.is_ascii_digit() || "?!#$*@-".contains(c) => 2,
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            1 + after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len())
+        }
+        _ => 1,
+    }
+}
+
+impl Validator for ShellHelper {
+    /// Single lines are always accepted so the REPL can ask for the rest
+    /// with the continuation prompt. A buffer that already spans several
+    /// lines (a paste, or a block recalled from history) is edited in place
+    /// until it parses as complete.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.contains('\n') && self.is_incomplete(input.trim()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> DefaultHistory {
+        let mut history = DefaultHistory::new();
+        for entry in entries {
+            history.add(entry).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn history_hint_finds_the_latest_longer_entry() {
+        let history = history(&["git status", "ls", "git log", "git"]);
+        assert_eq!(history_hint(&history, "git").as_deref(), Some(" log"));
+        assert_eq!(history_hint(&history, "l").as_deref(), Some("s"));
+    }
+
+    #[test]
+    fn history_hint_gives_up_without_a_match() {
+        assert_eq!(history_hint(&history(&[]), "git"), None);
+        // The search reaches the first entry without finding anything longer
+        assert_eq!(history_hint(&history(&["git"]), "git"), None);
+        assert_eq!(history_hint(&history(&["ls", "make"]), "cargo"), None);
+    }
+}
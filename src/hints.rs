@@ -0,0 +1,148 @@
+//! AI-predicted completions of the line being typed.
+//!
+//! Asking the AI takes far longer than a keystroke, so `AiHints::hint` only
+//! ever answers from what has already come back. Once typing pauses, the
+//! line is sent off on a worker thread, one request at a time, and when the
+//! prediction arrives the line editor is made to redraw so the hint shows up.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rustyline::ExternalPrinter;
+
+use crate::ai::{self, PromptKind};
+use crate::Config;
+
+/// How long typing has to pause before the AI is asked.
+const DELAY: Duration = Duration::from_millis(500);
+
+/// Lines shorter than this don't say enough to predict from.
+const MIN_LENGTH: usize = 3;
+
+/// The longest a prediction may take, if `ai.timeout` isn't shorter. Later
+/// hints wait for it, and by then the line has usually moved on anyway.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Printed through the line editor to make it redraw the line. The editor
+/// clears the line, prints this and a newline, then redraws, so moving up
+/// first leaves everything where it was.
+const REDRAW: &str = "\x1b[A";
+
+pub struct AiHints {
+    config: Arc<Config>,
+    state: Arc<Mutex<State>>,
+    printer: Arc<Mutex<Box<dyn ExternalPrinter + Send>>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The line being typed, as of the last keystroke.
+    line: String,
+    /// Bumped on every keystroke, so the worker can tell typing hasn't
+    /// paused yet.
+    generation: u64,
+    working: bool,
+    /// The last full command line the AI predicted.
+    prediction: Option<String>,
+}
+
+impl AiHints {
+    /// Requests are made like any other, from `config`. `printer` is used
+    /// to make the line editor redraw when a prediction arrives.
+    pub fn new(config: Arc<Config>, printer: Box<dyn ExternalPrinter + Send>) -> Self {
+        AiHints {
+            config,
+            state: Arc::default(),
+            printer: Arc::new(Mutex::new(printer)),
+        }
+    }
+
+    /// The rest of `line` as last predicted, if the prediction still fits.
+    /// Otherwise a new prediction is asked for once typing pauses.
+    pub fn hint(&self, line: &str, cwd: &Path) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(rest) = state
+            .prediction
+            .as_deref()
+            .and_then(|p| p.strip_prefix(line))
+            .filter(|rest| !rest.is_empty())
+        {
+            return Some(rest.to_string());
+        }
+        if state.line == line {
+            return None;
+        }
+
+        state.line = line.to_string();
+        state.generation += 1;
+        if line.trim().len() >= MIN_LENGTH && !state.working {
+            state.working = true;
+            self.start_worker(cwd.to_path_buf());
+        }
+        None
+    }
+
+    /// Forget the current line once it has been entered, so a prediction
+    /// that arrives later isn't drawn over a running command.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.line.clear();
+        state.generation += 1;
+        state.prediction = None;
+    }
+
+    /// Predict the line each time typing pauses, until it's entered or
+    /// too short to bother with.
+    fn start_worker(&self, cwd: PathBuf) {
+        let config = Arc::clone(&self.config);
+        let state = Arc::clone(&self.state);
+        let printer = Arc::clone(&self.printer);
+
+        thread::spawn(move || loop {
+            let line = loop {
+                let generation = state.lock().unwrap().generation;
+                thread::sleep(DELAY);
+                let mut state = state.lock().unwrap();
+                if state.line.trim().len() < MIN_LENGTH {
+                    state.working = false;
+                    return;
+                }
+                if state.generation == generation {
+                    break state.line.clone();
+                }
+            };
+
+            let request = config.request(PromptKind::Hint, &line, &cwd);
+            let timeout = config.ai_timeout.min(TIMEOUT);
+            let prediction = ai::start(&config.ai, request, timeout, false)
+                .wait(&mut |_| {})
+                .ok()
+                .map(|reply| crate::strip_code_fences(&reply))
+                .and_then(|reply| reply.lines().next().map(str::to_string))
+                .filter(|p| p.starts_with(&line) && p.len() > line.len());
+
+            let mut state = state.lock().unwrap();
+            let redraw = prediction
+                .as_ref()
+                .is_some_and(|p| !state.line.is_empty() && p.starts_with(&state.line));
+            if prediction.is_some() {
+                state.prediction = prediction;
+            }
+            // If the line changed while the AI was thinking, go again
+            let done = state.line == line || state.line.trim().len() < MIN_LENGTH;
+            if done {
+                state.working = false;
+            }
+            drop(state);
+
+            if redraw {
+                printer.lock().unwrap().print(REDRAW.to_string()).ok();
+            }
+            if done {
+                return;
+            }
+        });
+    }
+}
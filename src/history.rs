@@ -0,0 +1,453 @@
+//! Structured command history.
+//!
+//! Every command claudesh runs interactively is appended to
+//! `~/.claudesh/history.jsonl`, one JSON object per line, with where and
+//! when it ran, how it ended, and where it came from. The line editor keeps
+//! its own plain history for recall; this one is for looking back.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::policy::normalize;
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch when the command started.
+    pub time: u64,
+    pub session: String,
+    pub cwd: PathBuf,
+    pub command: String,
+    pub exit_code: i32,
+    pub duration_ms: u64,
+    pub origin: Origin,
+}
+
+/// How a command came to be run.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Origin {
+    /// Typed at the prompt.
+    Typed,
+    /// Generated from a plain-English request and run as is.
+    Ai { request: String },
+    /// Generated from a request, then edited before running.
+    Edited { request: String },
+    /// Suggested after `failed` went wrong: a fix or a sudo retry.
+    Fix { failed: String },
+}
+
+impl Origin {
+    pub fn is_ai(&self) -> bool {
+        !matches!(self, Origin::Typed)
+    }
+}
+
+pub struct History {
+    path: PathBuf,
+    session: String,
+    file: Mutex<Option<File>>,
+}
+
+impl History {
+    /// History kept at `path`, opened on first use.
+    pub fn new(path: PathBuf) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        History {
+            path,
+            session: format!("{:x}-{}", started, std::process::id()),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Append an entry for a command that just finished.
+    pub fn record(
+        &self,
+        command: &str,
+        cwd: &Path,
+        started: SystemTime,
+        duration: Duration,
+        exit_code: i32,
+        origin: Origin,
+    ) {
+        let entry = Entry {
+            time: started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            session: self.session.clone(),
+            cwd: cwd.to_path_buf(),
+            command: command.to_string(),
+            exit_code,
+            duration_ms: duration.as_millis() as u64,
+            origin,
+        };
+        let Ok(line) = serde_json::to_string(&entry) else {
+            return;
+        };
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .ok();
+        }
+        if let Some(file) = file.as_mut() {
+            writeln!(file, "{}", line).ok();
+        }
+    }
+
+    /// Every recorded entry, oldest first. Lines that don't parse are
+    /// skipped.
+    pub fn entries(&self) -> Vec<Entry> {
+        fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+}
+
+/// Which entries `history` shows.
+#[derive(Default)]
+pub struct Filter {
+    pub cwd: Option<PathBuf>,
+    pub failed: bool,
+    pub ai: bool,
+    /// Seconds since the Unix epoch.
+    pub since: Option<u64>,
+    /// Show only the last this many.
+    pub count: Option<usize>,
+}
+
+impl Filter {
+    /// Parse `history` arguments. `--cwd` without a directory means `cwd`,
+    /// and a bare number is how many entries to show.
+    pub fn parse(args: &[&str], cwd: &Path) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        let mut args = args.iter().peekable();
+        while let Some(&arg) = args.next() {
+            match arg {
+                "--cwd" => {
+                    let dir = args.next_if(|a| !a.starts_with("--"));
+                    filter.cwd = Some(match dir {
+                        Some(dir) => normalize(&cwd.join(crate::shellexpand_tilde(dir))),
+                        None => cwd.to_path_buf(),
+                    });
+                }
+                "--failed" => filter.failed = true,
+                "--ai" => filter.ai = true,
+                "--since" => {
+                    let when = args
+                        .next()
+                        .ok_or("--since needs a time, like 2h or 2024-05-01")?;
+                    filter.since = Some(parse_since(when)?);
+                }
+                _ if arg.parse::<usize>().is_ok() => filter.count = arg.parse().ok(),
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Whether every entry matches, leaving at most a count.
+    pub fn selects_all(&self) -> bool {
+        self.cwd.is_none() && !self.failed && !self.ai && self.since.is_none()
+    }
+
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.cwd.as_ref().is_none_or(|cwd| entry.cwd == *cwd)
+            && (!self.failed || entry.exit_code != 0)
+            && (!self.ai || entry.origin.is_ai())
+            && self.since.is_none_or(|since| entry.time >= since)
+    }
+}
+
+/// A point in time given as an age (`30m`, `2h`, `3d`, `1w`) or a local date
+/// (`2024-05-01`).
+fn parse_since(when: &str) -> Result<u64, String> {
+    let bad = || format!("bad time '{}': use e.g. 30m, 2h, 3d or 2024-05-01", when);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if let Some(unit) = when.chars().last().filter(char::is_ascii_alphabetic) {
+        let count: u64 = when[..when.len() - 1].parse().map_err(|_| bad())?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(bad()),
+        };
+        return Ok(now.saturating_sub(count * seconds));
+    }
+
+    let parts: Vec<i32> = when
+        .split('-')
+        .map(|p| p.parse().map_err(|_| bad()))
+        .collect::<Result<_, _>>()?;
+    let [year, month, day] = parts[..] else {
+        return Err(bad());
+    };
+    // SAFETY: mktime only reads and normalizes the struct we own.
+    let time = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tm.tm_year = year - 1900;
+        tm.tm_mon = month - 1;
+        tm.tm_mday = day;
+        tm.tm_isdst = -1;
+        libc::mktime(&mut tm)
+    };
+    if time < 0 {
+        return Err(bad());
+    }
+    Ok(time as u64)
+}
+
+/// `time` as a local `YYYY-MM-DD HH:MM`.
+pub fn format_time(time: u64) -> String {
+    // SAFETY: localtime_r writes only to the struct we own.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        let time = time as libc::time_t;
+        libc::localtime_r(&time, &mut tm);
+        tm
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+/// Words too common to say anything about which command was meant.
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "the", "to", "of", "in", "on", "for", "with", "that", "this", "it", "i",
+    "me", "my", "we", "used", "use", "ran", "run", "command", "commands", "did", "do", "how",
+    "what", "which", "when", "where", "was", "last", "ago", "from", "one", "some",
+];
+
+/// Up to `limit` entries most likely to be the command `question` describes,
+/// by how many of its words appear in the command, the request it was
+/// generated from, or its directory. Each command appears once, at its most
+/// recent run. When nothing matches, the most recent commands are returned so
+/// the AI still has something to go on.
+pub fn search<'a>(entries: &'a [Entry], question: &str, limit: usize) -> Vec<&'a Entry> {
+    let terms: Vec<String> = question
+        .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+        .map(str::to_lowercase)
+        .filter(|w| w.len() > 1 && !STOPWORDS.contains(&w.as_str()))
+        .collect();
+
+    let mut seen = std::collections::HashSet::new();
+    let mut scored: Vec<(usize, &Entry)> = entries
+        .iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.command.as_str()))
+        .map(|entry| (score(entry, &terms), entry))
+        .collect();
+    if scored.iter().any(|(score, _)| *score > 0) {
+        scored.retain(|(score, _)| *score > 0);
+    }
+    // Stable, so equal scores stay most recent first
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().take(limit).map(|(_, e)| e).collect()
+}
+
+fn score(entry: &Entry, terms: &[String]) -> usize {
+    let command = entry.command.to_lowercase();
+    let words: Vec<&str> = command
+        .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+        .collect();
+    let context = match &entry.origin {
+        Origin::Ai { request } | Origin::Edited { request } => request.to_lowercase(),
+        _ => String::new(),
+    } + " "
+        + &entry.cwd.to_string_lossy().to_lowercase();
+
+    terms
+        .iter()
+        .map(|term| {
+            if words.contains(&term.as_str()) {
+                3
+            } else if command.contains(term.as_str()) {
+                2
+            } else if context.contains(term.as_str()) {
+                1
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn entry(command: &str, cwd: &str, exit_code: i32, origin: Origin) -> Entry {
+        Entry {
+            time: now(),
+            session: "test".to_string(),
+            cwd: PathBuf::from(cwd),
+            command: command.to_string(),
+            exit_code,
+            duration_ms: 0,
+            origin,
+        }
+    }
+
+    #[test]
+    fn filter_options() {
+        let cwd = Path::new("/work/proj");
+        let filter = Filter::parse(&["--failed", "--ai", "--cwd"], cwd).unwrap();
+        assert!(filter.failed && filter.ai);
+        assert_eq!(filter.cwd.as_deref(), Some(cwd));
+
+        let filter = Filter::parse(&["--cwd", "src", "--failed"], cwd).unwrap();
+        assert_eq!(filter.cwd, Some(PathBuf::from("/work/proj/src")));
+        assert!(filter.failed);
+
+        let filter = Filter::parse(&["--cwd", "/etc"], cwd).unwrap();
+        assert_eq!(filter.cwd, Some(PathBuf::from("/etc")));
+
+        let filter = Filter::parse(&["--since", "1h"], cwd).unwrap();
+        let since = filter.since.unwrap();
+        assert!(since <= now() - 3600 && since + 60 > now() - 3600);
+    }
+
+    #[test]
+    fn filter_cwd_is_normalized() {
+        let cwd = Path::new("/work/proj/src");
+        for (dir, expected) in [
+            ("..", "/work/proj"),
+            ("./", "/work/proj/src"),
+            ("../../other/./x", "/work/other/x"),
+            ("/etc/../var", "/var"),
+        ] {
+            let filter = Filter::parse(&["--cwd", dir], cwd).unwrap();
+            assert_eq!(filter.cwd, Some(PathBuf::from(expected)), "{}", dir);
+        }
+    }
+
+    #[test]
+    fn filter_count() {
+        let cwd = Path::new("/work/proj");
+        let filter = Filter::parse(&["20"], cwd).unwrap();
+        assert_eq!(filter.count, Some(20));
+        assert!(filter.selects_all());
+
+        let filter = Filter::parse(&["--failed", "5"], cwd).unwrap();
+        assert_eq!(filter.count, Some(5));
+        assert!(!filter.selects_all());
+
+        assert!(Filter::parse(&[], cwd).unwrap().selects_all());
+        assert!(Filter::parse(&["-5"], cwd).is_err());
+    }
+
+    #[test]
+    fn filter_errors() {
+        let cwd = Path::new("/work/proj");
+        assert!(Filter::parse(&["--since"], cwd).is_err());
+        assert!(Filter::parse(&["--since", "soon"], cwd).is_err());
+        assert_eq!(
+            Filter::parse(&["--bogus"], cwd).err().unwrap(),
+            "unknown option: --bogus"
+        );
+    }
+
+    #[test]
+    fn filter_matches() {
+        let filter = Filter::parse(&["--failed", "--ai"], Path::new("/")).unwrap();
+        let request = "list files".to_string();
+        assert!(filter.matches(&entry("ls", "/", 1, Origin::Ai { request })));
+        assert!(!filter.matches(&entry("ls", "/", 0, Origin::Typed)));
+        assert!(!filter.matches(&entry("ls", "/", 1, Origin::Typed)));
+
+        let filter = Filter::parse(&["--cwd"], Path::new("/work")).unwrap();
+        assert!(filter.matches(&entry("ls", "/work", 0, Origin::Typed)));
+        assert!(!filter.matches(&entry("ls", "/work/sub", 0, Origin::Typed)));
+    }
+
+    #[test]
+    fn since_ages() {
+        for (when, seconds) in [
+            ("30s", 30),
+            ("5m", 300),
+            ("2h", 7200),
+            ("3d", 259200),
+            ("1w", 604800),
+        ] {
+            let since = parse_since(when).unwrap();
+            let expected = now() - seconds;
+            assert!(since <= expected && since + 60 > expected, "{}", when);
+        }
+    }
+
+    #[test]
+    fn since_dates() {
+        let may = parse_since("2024-05-01").unwrap();
+        let next = parse_since("2024-05-02").unwrap();
+        assert_eq!(next - may, 24 * 60 * 60);
+        assert!(format_time(may).starts_with("2024-05-01 00:00"));
+    }
+
+    #[test]
+    fn since_rejects_nonsense() {
+        for when in ["", "h", "2x", "yesterday", "2024-05", "2024/05/01"] {
+            assert!(parse_since(when).is_err(), "{}", when);
+        }
+    }
+
+    #[test]
+    fn search_ranks_by_matching_words() {
+        let entries = vec![
+            entry("cargo build", "/work", 0, Origin::Typed),
+            entry("du -sh * | sort -h", "/work", 0, Origin::Typed),
+            entry(
+                "find . -size +100M",
+                "/work",
+                0,
+                Origin::Ai {
+                    request: "big files".to_string(),
+                },
+            ),
+            entry("cargo build", "/work", 0, Origin::Typed),
+        ];
+        let found = search(&entries, "the command for big files", 5);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].command, "find . -size +100M");
+
+        // Nothing matches, so the most recent commands come back, once each
+        let found: Vec<&str> = search(&entries, "kubernetes", 5)
+            .iter()
+            .map(|e| e.command.as_str())
+            .collect();
+        assert_eq!(
+            found,
+            ["cargo build", "find . -size +100M", "du -sh * | sort -h"]
+        );
+    }
+}
@@ -0,0 +1,480 @@
+//! The interactive session's job table.
+//!
+//! Command lines run with `&` and ones stopped with Ctrl-Z become jobs: each
+//! a process group led by a child of claudesh, which watches the leader to
+//! see the job stop, carry on or finish. Jobs are numbered, marked current
+//! (`+`) and previous (`-`), named by job specs and announced the way bash
+//! does it.
+
+use std::thread;
+use std::time::Duration;
+
+use crate::signals;
+
+/// How often to look again at a job that isn't claudesh's child.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a job is doing, as of the last look.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Running,
+    /// Stopped by the signal.
+    Stopped(i32),
+    /// Exited with the status.
+    Exited(i32),
+    /// Killed by the signal, and whether it dumped core.
+    Killed(i32, bool),
+}
+
+impl State {
+    /// Decode a status from `waitpid`.
+    pub fn from_wait(status: i32) -> State {
+        if libc::WIFSTOPPED(status) {
+            State::Stopped(libc::WSTOPSIG(status))
+        } else if libc::WIFSIGNALED(status) {
+            State::Killed(libc::WTERMSIG(status), libc::WCOREDUMP(status))
+        } else if libc::WIFEXITED(status) {
+            State::Exited(libc::WEXITSTATUS(status))
+        } else {
+            State::Running
+        }
+    }
+
+    pub fn is_stopped(self) -> bool {
+        matches!(self, State::Stopped(_))
+    }
+
+    pub fn is_finished(self) -> bool {
+        matches!(self, State::Exited(_) | State::Killed(..))
+    }
+
+    /// The status bash gives for it: 128+N after signal N.
+    pub fn status(self) -> i32 {
+        match self {
+            State::Running => 0,
+            State::Exited(status) => status,
+            State::Stopped(sig) | State::Killed(sig, _) => 128 + sig,
+        }
+    }
+
+    /// As bash's `jobs` puts it: "Running", "Stopped (tty input)", "Exit 2"
+    /// or "Segmentation fault (core dumped)".
+    pub fn describe(self) -> String {
+        match self {
+            State::Running => "Running".to_string(),
+            State::Stopped(libc::SIGSTOP) => "Stopped (signal)".to_string(),
+            State::Stopped(libc::SIGTTIN) => "Stopped (tty input)".to_string(),
+            State::Stopped(libc::SIGTTOU) => "Stopped (tty output)".to_string(),
+            State::Stopped(_) => "Stopped".to_string(),
+            State::Exited(0) => "Done".to_string(),
+            State::Exited(status) => format!("Exit {}", status),
+            State::Killed(sig, core) => {
+                let name = match sig {
+                    libc::SIGHUP => "Hangup".to_string(),
+                    libc::SIGINT => "Interrupt".to_string(),
+                    libc::SIGQUIT => "Quit".to_string(),
+                    libc::SIGABRT => "Aborted".to_string(),
+                    libc::SIGKILL => "Killed".to_string(),
+                    libc::SIGSEGV => "Segmentation fault".to_string(),
+                    libc::SIGPIPE => "Broken pipe".to_string(),
+                    libc::SIGTERM => "Terminated".to_string(),
+                    _ => signals::name(sig).map_or_else(|| format!("Signal {}", sig), String::from),
+                };
+                if core {
+                    format!("{} (core dumped)", name)
+                } else {
+                    name
+                }
+            }
+        }
+    }
+}
+
+pub struct Job {
+    pub number: u32,
+    /// The process group, and the process ID of its leader.
+    pub pgid: libc::pid_t,
+    pub command: String,
+    pub state: State,
+    /// The terminal modes the job left behind when it stopped, for `fg` to
+    /// put back.
+    pub modes: Option<libc::termios>,
+}
+
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Job numbers, the one most recently started, stopped or resumed last.
+    recent: Vec<u32>,
+}
+
+impl JobTable {
+    /// Jobs in number order.
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.number == number)
+    }
+
+    pub fn get_mut(&mut self, number: u32) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.number == number)
+    }
+
+    /// Add a job, numbered one past the highest in use, and make it current.
+    pub fn add(&mut self, pgid: libc::pid_t, command: &str, state: State) -> u32 {
+        let number = self.jobs.last().map_or(1, |job| job.number + 1);
+        self.jobs.push(Job {
+            number,
+            pgid,
+            command: command.trim().to_string(),
+            state,
+            modes: None,
+        });
+        self.touch(number);
+        number
+    }
+
+    pub fn remove(&mut self, number: u32) -> Option<Job> {
+        self.recent.retain(|&n| n != number);
+        let index = self.jobs.iter().position(|job| job.number == number)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// Make a job the most recent one, as when it is stopped or resumed.
+    pub fn touch(&mut self, number: u32) {
+        self.recent.retain(|&n| n != number);
+        self.recent.push(number);
+    }
+
+    /// The current and previous jobs. A stopped job comes before a running
+    /// one, and otherwise the most recent first.
+    fn current(&self) -> (Option<u32>, Option<u32>) {
+        let stopped = |n: &&u32| self.get(**n).is_some_and(|job| job.state.is_stopped());
+        let latest = self.recent.iter().rev();
+        let mut ranked = latest
+            .clone()
+            .filter(stopped)
+            .chain(latest.filter(|n| !stopped(n)))
+            .copied();
+        (ranked.next(), ranked.next())
+    }
+
+    /// `+` for the current job, `-` for the previous one, otherwise a space.
+    pub fn marker(&self, number: u32) -> char {
+        match self.current() {
+            (Some(current), _) if current == number => '+',
+            (_, Some(previous)) if previous == number => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Resolve a job spec to a job number: `%N` or `N`, `%+`, `%%`, `%` or
+    /// nothing for the current job, `%-` for the previous one, `%name` for
+    /// the job whose command starts with `name` and `%?text` for the one
+    /// whose command contains `text`.
+    pub fn find(&self, spec: &str) -> Result<u32, String> {
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let (current, previous) = self.current();
+        let found = match name {
+            "" | "+" | "%" => current,
+            "-" => previous.or(current),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => name
+                .parse()
+                .ok()
+                .filter(|&number| self.get(number).is_some()),
+            _ if spec.starts_with('%') => {
+                let matches: Vec<u32> = match name.strip_prefix('?') {
+                    Some(text) => self.matching(|command| command.contains(text)),
+                    None => self.matching(|command| command.starts_with(name)),
+                };
+                if matches.len() > 1 {
+                    return Err(format!("{}: ambiguous job spec", spec));
+                }
+                matches.first().copied()
+            }
+            _ => None,
+        };
+        found.ok_or_else(|| match spec {
+            "" => "current: no such job".to_string(),
+            _ => format!("{}: no such job", spec),
+        })
+    }
+
+    fn matching(&self, test: impl Fn(&str) -> bool) -> Vec<u32> {
+        self.jobs
+            .iter()
+            .filter(|job| test(&job.command))
+            .map(|job| job.number)
+            .collect()
+    }
+
+    /// A job's line in bash's layout, e.g. "[2]-  Running   make &".
+    pub fn format(&self, job: &Job) -> String {
+        let background = if job.state == State::Running {
+            " &"
+        } else {
+            ""
+        };
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.number,
+            self.marker(job.number),
+            job.state.describe(),
+            job.command,
+            background
+        )
+    }
+
+    /// Look at every job without blocking. Returns a line for each one that
+    /// has stopped or finished since the last look; finished jobs are then
+    /// dropped from the table.
+    pub fn poll(&mut self) -> Vec<String> {
+        let mut notices = Vec::new();
+        let mut finished = Vec::new();
+        for index in 0..self.jobs.len() {
+            let Some(state) = check(self.jobs[index].pgid) else {
+                continue;
+            };
+            if state == self.jobs[index].state {
+                continue;
+            }
+            self.jobs[index].state = state;
+            if state.is_stopped() {
+                let number = self.jobs[index].number;
+                self.touch(number);
+            }
+            if state != State::Running {
+                notices.push(self.format(&self.jobs[index]));
+            }
+            if state.is_finished() {
+                finished.push(self.jobs[index].number);
+            }
+        }
+        for number in finished {
+            self.remove(number);
+        }
+        notices
+    }
+}
+
+/// Look at a job's leader without blocking. None if nothing has changed.
+pub fn check(pid: libc::pid_t) -> Option<State> {
+    let mut status = 0;
+    let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+    // SAFETY: waitpid on a single process ID.
+    match unsafe { libc::waitpid(pid, &mut status, flags) } {
+        0 => None,
+        rc if rc == pid => Some(State::from_wait(status)),
+        _ => gone(pid),
+    }
+}
+
+/// Wait for a job's leader to stop or finish.
+pub fn wait(pid: libc::pid_t) -> State {
+    loop {
+        let mut status = 0;
+        // SAFETY: waitpid on a single process ID.
+        let rc = unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) };
+        if rc == pid {
+            return State::from_wait(status);
+        }
+        if io_error() != libc::EINTR {
+            break;
+        }
+    }
+    loop {
+        if let Some(state) = gone(pid) {
+            return state;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// For a leader that isn't claudesh's child, where orphans can't be taken
+/// in: whether it is gone is all there is to tell.
+fn gone(pid: libc::pid_t) -> Option<State> {
+    // SAFETY: signal 0 only checks that the process exists.
+    let alive = unsafe { libc::kill(pid, 0) } == 0 || io_error() == libc::EPERM;
+    (!alive).then_some(State::Exited(0))
+}
+
+fn io_error() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use std::time::Instant;
+
+    fn table(commands: &[&str]) -> JobTable {
+        let mut table = JobTable::default();
+        for (i, command) in commands.iter().enumerate() {
+            table.add(1000 + i as i32, command, State::Running);
+        }
+        table
+    }
+
+    #[test]
+    fn decodes_wait_statuses() {
+        assert_eq!(State::from_wait(0), State::Exited(0));
+        assert_eq!(State::from_wait(2 << 8), State::Exited(2));
+        assert_eq!(
+            State::from_wait(libc::SIGTERM),
+            State::Killed(libc::SIGTERM, false)
+        );
+        assert_eq!(
+            State::from_wait(libc::SIGSEGV | 0x80),
+            State::Killed(libc::SIGSEGV, true)
+        );
+        assert_eq!(
+            State::from_wait(libc::SIGTSTP << 8 | 0x7f),
+            State::Stopped(libc::SIGTSTP)
+        );
+        assert_eq!(State::from_wait(0xffff), State::Running);
+    }
+
+    #[test]
+    fn describes_states_like_bash() {
+        assert_eq!(State::Exited(0).describe(), "Done");
+        assert_eq!(State::Exited(3).describe(), "Exit 3");
+        assert_eq!(State::Stopped(libc::SIGTSTP).describe(), "Stopped");
+        assert_eq!(
+            State::Stopped(libc::SIGTTIN).describe(),
+            "Stopped (tty input)"
+        );
+        assert_eq!(
+            State::Killed(libc::SIGSEGV, true).describe(),
+            "Segmentation fault (core dumped)"
+        );
+        assert_eq!(State::Killed(libc::SIGUSR1, false).describe(), "SIGUSR1");
+        assert_eq!(State::Killed(libc::SIGINT, false).status(), 130);
+        assert_eq!(State::Stopped(libc::SIGTSTP).status(), 148);
+    }
+
+    #[test]
+    fn numbers_follow_the_highest_in_use() {
+        let mut table = table(&["sleep 1", "sleep 2", "sleep 3"]);
+        table.remove(2);
+        assert_eq!(table.add(2000, "make", State::Running), 4);
+        table.remove(3);
+        table.remove(4);
+        assert_eq!(table.add(2001, "make", State::Running), 2);
+    }
+
+    #[test]
+    fn stopped_jobs_are_current_before_running_ones() {
+        let mut table = table(&["vim notes", "make", "sleep 9"]);
+        assert_eq!(table.marker(3), '+');
+        assert_eq!(table.marker(2), '-');
+        assert_eq!(table.marker(1), ' ');
+
+        table.get_mut(1).unwrap().state = State::Stopped(libc::SIGTSTP);
+        assert_eq!(table.marker(1), '+');
+        assert_eq!(table.marker(3), '-');
+
+        // Resuming one makes it the most recent of the running jobs
+        table.touch(2);
+        assert_eq!(table.marker(2), '-');
+    }
+
+    #[test]
+    fn finds_jobs_by_spec() {
+        let mut table = table(&["vim notes", "make -j8", "make test"]);
+        table.get_mut(1).unwrap().state = State::Stopped(libc::SIGTSTP);
+        assert_eq!(table.find(""), Ok(1));
+        assert_eq!(table.find("%%"), Ok(1));
+        assert_eq!(table.find("%+"), Ok(1));
+        assert_eq!(table.find("%-"), Ok(3));
+        assert_eq!(table.find("%2"), Ok(2));
+        assert_eq!(table.find("2"), Ok(2));
+        assert_eq!(table.find("%vim"), Ok(1));
+        assert_eq!(table.find("%?test"), Ok(3));
+        assert_eq!(
+            table.find("%make"),
+            Err("%make: ambiguous job spec".to_string())
+        );
+        assert_eq!(table.find("%5"), Err("%5: no such job".to_string()));
+        assert_eq!(table.find("vim"), Err("vim: no such job".to_string()));
+        assert_eq!(
+            JobTable::default().find(""),
+            Err("current: no such job".to_string())
+        );
+    }
+
+    #[test]
+    fn formats_lines_like_bash() {
+        let mut table = table(&["sleep 100", "vim notes"]);
+        table.get_mut(2).unwrap().state = State::Stopped(libc::SIGTSTP);
+        let lines: Vec<String> = table.iter().map(|job| table.format(job)).collect();
+        assert_eq!(
+            lines,
+            [
+                "[1]-  Running                 sleep 100 &",
+                "[2]+  Stopped                 vim notes",
+            ]
+        );
+    }
+
+    /// Poll until there is something to announce.
+    fn notices(table: &mut JobTable) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let notices = table.poll();
+            if !notices.is_empty() || Instant::now() > deadline {
+                return notices;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Start a job leader; the table reaps it by process ID.
+    #[allow(clippy::zombie_processes)]
+    fn spawn(program: &str, args: &[&str]) -> libc::pid_t {
+        let child = Command::new(program)
+            .args(args)
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        child.id() as libc::pid_t
+    }
+
+    #[test]
+    fn announces_stops_and_endings_once() {
+        let mut table = JobTable::default();
+        let sleeper = spawn("sleep", &["30"]);
+        table.add(sleeper, "sleep 30", State::Running);
+        let failing = spawn("sh", &["-c", "exit 3"]);
+        table.add(failing, "sh -c 'exit 3'", State::Running);
+
+        assert_eq!(
+            notices(&mut table),
+            ["[2]+  Exit 3                  sh -c 'exit 3'"]
+        );
+        assert_eq!(table.iter().count(), 1);
+
+        // SAFETY: signalling the child started above.
+        unsafe { libc::kill(sleeper, libc::SIGSTOP) };
+        assert_eq!(
+            notices(&mut table),
+            ["[1]+  Stopped (signal)        sleep 30"]
+        );
+        assert!(table.poll().is_empty());
+
+        // Carrying on isn't announced, only how it ends
+        unsafe { libc::kill(sleeper, libc::SIGCONT) };
+        while table.get(1).unwrap().state != State::Running {
+            table.poll();
+        }
+        unsafe { libc::kill(sleeper, libc::SIGKILL) };
+        assert_eq!(
+            notices(&mut table),
+            ["[1]+  Killed                  sleep 30"]
+        );
+        assert_eq!(table.iter().count(), 0);
+    }
+}
@@ -1,19 +1,41 @@
+mod ai;
+mod bash_completion;
+mod config;
+mod context;
+mod editor;
+mod hints;
+mod history;
+mod jobs;
+mod parse;
+mod policy;
+mod project;
+mod prompt;
+mod safety;
+mod session;
+mod signals;
+mod template;
+mod transcript;
+
+use ai::{AiBackend, AiError, PromptKind};
+use config::ConfigFile;
+use editor::{LineEditor, RightPrompt, ShellHelper, Submit, CONTINUATION_PROMPT};
+use history::{History, Origin};
+use policy::{Policy, Verdict};
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::{Event, EventHandler, KeyCode, KeyEvent, Modifiers};
+use safety::Risk;
+use session::{BashSession, RunResult};
 use std::collections::HashSet;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, BufRead, IsTerminal, Read as _, Write};
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitCode, Stdio};
+use std::process::{Command, ExitCode};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-
-/// Max bytes of stderr to capture for error analysis (1 MB).
-/// Prevents unbounded memory growth from noisy commands.
-const STDERR_CAPTURE_LIMIT: usize = 1024 * 1024;
+use std::time::{Duration, Instant, SystemTime};
 
 // ─── Default prompts (overridable via ~/.claudesh/prompts/) ──────────────────
 
@@ -23,17 +45,28 @@ const DEFAULT_PROMPT_ASK: &str = include_str!("../defaults/prompts/ask.txt");
 const DEFAULT_PROMPT_FIX: &str = include_str!("../defaults/prompts/fix.txt");
 const DEFAULT_PROMPT_SCRIPT: &str = include_str!("../defaults/prompts/script.txt");
 const DEFAULT_PROMPT_JUDGY: &str = include_str!("../defaults/prompts/judgy.txt");
+const DEFAULT_PROMPT_HISTORY: &str = include_str!("../defaults/prompts/history.txt");
+const DEFAULT_PROMPT_HINT: &str = include_str!("../defaults/prompts/hint.txt");
 const DEFAULT_PERSONALITY: &str = include_str!("../defaults/personality");
+const DEFAULT_CONFIG: &str = include_str!("../defaults/config.toml");
+const DEFAULT_POLICY: &str = include_str!("../defaults/policy.toml");
 
 /// Shell builtins and keywords that should always be treated as commands, not
-/// natural language. Note: cd, exit, export, unset, source are handled as
-/// claudesh builtins before this list is checked.
+/// natural language. Note: exit and source are handled as claudesh builtins
+/// before this list is checked.
 const SHELL_BUILTINS: &[&str] = &[
-    "alias", "unalias", "set", "shopt", "type", "hash", "ulimit", "umask", "wait", "jobs",
-    "fg", "bg", "disown", "builtin", "command", "declare", "local", "readonly", "typeset",
-    "let", "eval", "exec", "trap", "return", "shift", "getopts", "read", "mapfile",
-    "readarray", "printf", "echo", "test", "true", "false", "for", "while", "if", "case",
-    "select", "until", "do", "done", "then", "else", "elif", "fi", "esac", "in",
+    "cd", "pushd", "popd", "dirs", "export", "unset", "alias", "unalias", "set", "shopt",
+    "type", "hash", "ulimit", "umask", "wait", "jobs", "fg", "bg", "disown", "builtin",
+    "command", "declare", "local", "readonly", "typeset", "let", "eval", "exec", "trap",
+    "return", "shift", "getopts", "read", "mapfile", "readarray", "printf", "echo", "test",
+    "true", "false", "for", "while", "if", "case", "select", "until", "do", "done", "then",
+    "else", "elif", "fi", "esac", "in", "function",
+];
+
+/// Builtins claudesh handles itself rather than passing to bash.
+const CLAUDESH_BUILTINS: &[&str] = &[
+    "exit", "quit", "logout", "help", "history", "jobs", "judgy", "yolo", "trust", "context",
+    "source",
 ];
 
 const COMMAND_PREFIXES: &[&str] = &[
@@ -45,10 +78,13 @@ const COLOR_BOLD: &str = "\x1b[1m";
 const COLOR_DIM: &str = "\x1b[2m";
 const COLOR_GREEN: &str = "\x1b[32m";
 const COLOR_YELLOW: &str = "\x1b[33m";
+const COLOR_BLUE: &str = "\x1b[34m";
 const COLOR_MAGENTA: &str = "\x1b[35m";
 const COLOR_CYAN: &str = "\x1b[36m";
 const COLOR_RED: &str = "\x1b[31m";
 const COLOR_ITALIC: &str = "\x1b[3m";
+/// Input that will be sent to the AI rather than run.
+const COLOR_AI: &str = COLOR_BLUE;
 
 /// Loaded configuration from ~/.claudesh/
 struct Config {
@@ -58,23 +94,109 @@ struct Config {
     prompt_fix: String,
     prompt_script: String,
     prompt_judgy: String,
+    prompt_history: String,
+    prompt_hint: String,
     personality: String,
     config_dir: PathBuf,
     yolo: bool,
     judgy: bool,
+    ai: Arc<dyn AiBackend>,
+    ai_timeout: Duration,
+    policy: Policy,
+    history: History,
+    /// Whether to record commands in `history`.
+    record_history: bool,
+    /// Lines the line editor keeps for recall.
+    history_size: Option<usize>,
+    history_ignore_space: bool,
+    ai_hints: bool,
+    prompt: prompt::PromptFormat,
+    highlight: bool,
+    /// What plain English is colored as it's typed, if not `COLOR_AI`.
+    ai_color: Option<&'static str>,
+    error_help: bool,
+    sudo_retry: bool,
+    /// Settings from the project's `.claudesh/`, once it's trusted.
+    projects: project::Projects,
+    /// Recent commands, for the AI to refer back to.
+    transcript: Arc<Mutex<transcript::Transcript>>,
+    /// What the AI is told about the system when it writes commands.
+    context: context::Collector,
 }
 
-/// Result of running a bash command
-struct RunResult {
-    exit_code: i32,
-    captured_stderr: String,
+impl Config {
+    /// A request for `kind` about `input`, with the current project's own
+    /// prompt and personality if it has them.
+    fn request(&self, kind: PromptKind, input: &str, cwd: &Path) -> ai::Request {
+        let project = self.projects.current();
+        let own = |name| project.as_ref().and_then(|p| p.prompt(name));
+        let prompt = own(kind.name()).unwrap_or(match kind {
+            PromptKind::Generate => &self.prompt_generate,
+            PromptKind::Script => &self.prompt_script,
+            PromptKind::Explain => &self.prompt_explain,
+            PromptKind::Ask => &self.prompt_ask,
+            PromptKind::Fix => &self.prompt_fix,
+            PromptKind::Judgy => &self.prompt_judgy,
+            PromptKind::History => &self.prompt_history,
+            PromptKind::Hint => &self.prompt_hint,
+        });
+        // Only requests for commands need to know how the system works
+        let environment = matches!(
+            kind,
+            PromptKind::Generate | PromptKind::Script | PromptKind::Fix
+        )
+        .then(|| self.context.describe(cwd));
+        let transcript = self.transcript.lock().unwrap();
+        let vars = template::Vars {
+            cwd,
+            personality: own("personality").unwrap_or(&self.personality),
+            project_notes: project.as_ref().and_then(|p| p.notes.as_deref()),
+            transcript: &transcript,
+            environment: environment.as_deref(),
+            context: &self.context,
+        };
+        ai_request(kind, prompt, input, &vars)
+    }
+
+    /// Whether the generated `command` can run without asking, by the user's
+    /// policy and the current project's.
+    fn decide(&self, command: &str, cwd: &Path, yolo: bool) -> Verdict {
+        match self.projects.current().and_then(|p| p.policy.clone()) {
+            Some(policy) => policy.decide(command, cwd, yolo),
+            None => self.policy.decide(command, cwd, yolo),
+        }
+    }
+
+    /// Follow the shell into `cwd`, pointing out project settings that
+    /// haven't been trusted yet or have changed since.
+    fn enter_dir(&self, cwd: &Path) {
+        let (root, what) = match self.projects.enter(cwd, &self.policy) {
+            Some(project::Untrusted::New(root)) => {
+                (root, "has project settings; run `trust` to use them")
+            }
+            Some(project::Untrusted::Changed(root)) => (
+                root,
+                "has changed since you trusted it; run `trust` to use it again",
+            ),
+            None => return,
+        };
+        eprintln!(
+            "{}{} {}{}",
+            COLOR_DIM,
+            display_path(&root.join(".claudesh")),
+            what,
+            COLOR_RESET
+        );
+    }
 }
 
 fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
-    // Load config
-    let config = load_config();
+    signals::install();
+
+    // Load config, shared with the thread that asks for hints
+    let config = Arc::new(load_config());
 
     // Ensure config dir exists with defaults
     ensure_config_dir(&config);
@@ -109,7 +231,10 @@ fn main() -> ExitCode {
                 if login_shell {
                     source_profile(&cwd);
                 }
-                let result = run_bash(cmd, &cwd);
+                let Some(mut session) = start_session(&cwd, false) else {
+                    return ExitCode::from(127);
+                };
+                let result = session.run(cmd);
                 return ExitCode::from(result.exit_code as u8);
             }
             "--" => {
@@ -192,26 +317,77 @@ fn source_profile(cwd: &Path) {
     }
 }
 
+/// Start the bash session, reporting failure the way a missing command would.
+fn start_session(cwd: &Path, job_control: bool) -> Option<BashSession> {
+    match BashSession::spawn(cwd, job_control) {
+        Ok(session) => Some(session),
+        Err(e) => {
+            eprintln!("claudesh: failed to start bash: {}", e);
+            None
+        }
+    }
+}
+
+/// Rebuild the PATH command cache if the session changed $PATH.
+fn refresh_path_commands(path_commands: &mut HashSet<String>, last_path: &mut Option<OsString>) {
+    let path = env::var_os("PATH");
+    if path != *last_path {
+        *path_commands = build_path_command_set();
+        *last_path = path;
+    }
+}
+
 /// Run commands from piped stdin (non-interactive mode)
 fn run_piped(config: &Config) -> ExitCode {
-    let mut cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
     env::set_var("PWD", &cwd);
-    let path_commands = build_path_command_set();
-    let claude_available = which::which("claude").is_ok();
+    let Some(mut session) = start_session(&cwd, false) else {
+        return ExitCode::from(127);
+    };
+    let mut path_commands = build_path_command_set();
+    let mut last_path = env::var_os("PATH");
+    let ai_available = config.ai.unavailable_reason().is_none();
     let mut last_exit: i32 = 0;
 
+    // Lines accumulate until they form complete statements, so compound
+    // commands and heredocs can span several lines like they do in bash
+    let mut pending = String::new();
     let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(_) => break,
-        };
-        let input = line.trim();
-        if input.is_empty() || input.starts_with('#') {
-            continue;
+    let mut lines = stdin.lock().lines();
+    loop {
+        let line = lines.next().and_then(|l| l.ok());
+        let at_eof = line.is_none();
+        if let Some(line) = line {
+            pending.push_str(&line);
+            pending.push('\n');
+            if parse::is_incomplete(&pending) {
+                continue;
+            }
+        }
+
+        for statement in parse::split_statements(&pending) {
+            // Trusted projects apply here too, but untrusted ones aren't
+            // pointed out, since `trust` can't be run from a script
+            config.projects.enter(session.cwd(), &config.policy);
+            config.context.set_tools(&path_commands);
+            last_exit = execute_line(
+                &statement,
+                &mut session,
+                &path_commands,
+                ai_available,
+                config,
+                None,
+            );
+            refresh_path_commands(&mut path_commands, &mut last_path);
+            if signals::take_interrupt() && last_exit == 128 + libc::SIGINT {
+                return ExitCode::from(last_exit as u8);
+            }
         }
+        pending.clear();
 
-        last_exit = execute_line(input, &mut cwd, &path_commands, claude_available, config, None);
+        if at_eof {
+            break;
+        }
     }
 
     ExitCode::from(last_exit as u8)
@@ -227,19 +403,30 @@ fn run_script_file(path: &str, cwd: &Path) -> ExitCode {
         }
     };
 
-    let mut cwd = cwd.to_path_buf();
-    env::set_var("PWD", &cwd);
-    let path_commands = build_path_command_set();
-    let claude_available = which::which("claude").is_ok();
+    env::set_var("PWD", cwd);
+    let Some(mut session) = start_session(cwd, false) else {
+        return ExitCode::from(127);
+    };
+    let mut path_commands = build_path_command_set();
+    let mut last_path = env::var_os("PATH");
     let config = load_config();
+    let ai_available = config.ai.unavailable_reason().is_none();
     let mut last_exit: i32 = 0;
 
-    for line in contents.lines() {
-        let input = line.trim();
-        if input.is_empty() || input.starts_with('#') {
-            continue;
+    for statement in parse::split_statements(&contents) {
+        last_exit = execute_line(
+            &statement,
+            &mut session,
+            &path_commands,
+            ai_available,
+            &config,
+            None,
+        );
+        refresh_path_commands(&mut path_commands, &mut last_path);
+        // Like bash, give up on the script when a command dies from Ctrl-C
+        if signals::take_interrupt() && last_exit == 128 + libc::SIGINT {
+            break;
         }
-        last_exit = execute_line(input, &mut cwd, &path_commands, claude_available, &config, None);
     }
 
     ExitCode::from(last_exit as u8)
@@ -248,91 +435,99 @@ fn run_script_file(path: &str, cwd: &Path) -> ExitCode {
 /// Execute a single line of input, returns exit code
 fn execute_line(
     input: &str,
-    cwd: &mut PathBuf,
+    session: &mut BashSession,
     path_commands: &HashSet<String>,
-    claude_available: bool,
+    ai_available: bool,
     config: &Config,
-    editor: Option<&mut DefaultEditor>,
+    editor: Option<&mut LineEditor>,
 ) -> i32 {
-    match classify_input(input, path_commands) {
+    match classify_input(input, path_commands, session.names()) {
         InputKind::Exit(code) => std::process::exit(code.unwrap_or(0)),
         InputKind::Comment => 0,
         InputKind::Help => {
             print_help();
             0
         }
-        InputKind::Cd(dir) => handle_cd(&dir, cwd),
-        InputKind::Export(assignment) => {
-            if assignment.is_empty() {
-                // Bare export - run through bash to show all exported vars
-                let result = run_bash("export", cwd);
-                result.exit_code
-            } else {
-                handle_export(&assignment);
-                0
-            }
-        }
-        InputKind::Unset(name) => {
-            if name.is_empty() {
-                // Bare unset - run through bash (it's a no-op but keeps consistency)
-                let result = run_bash("unset", cwd);
-                result.exit_code
-            } else {
-                env::remove_var(&name);
-                0
-            }
-        }
         InputKind::Source(path) => {
-            handle_source(&path, cwd, path_commands, claude_available, config, editor)
+            handle_source(&path, session, path_commands, ai_available, config, editor)
         }
-        InputKind::History => {
-            if let Some(ed) = editor {
-                print_history(ed);
+        InputKind::History(args) => print_history(editor.as_deref(), &args, session.cwd(), config),
+        // Without a line to edit, just list the matches
+        InputKind::HistoryAsk(question) => {
+            if !ai_available {
+                eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                return 1;
+            }
+            match search_history(&question, session.cwd(), config) {
+                Ok(matches) => {
+                    for command in matches {
+                        println!("{}", command);
+                    }
+                    0
+                }
+                Err(code) => code,
             }
-            0
         }
+        // Scripts have no job control, so bash's own builtins will do
+        InputKind::Jobs
+        | InputKind::Fg(_)
+        | InputKind::Bg(_)
+        | InputKind::Wait(_)
+        | InputKind::Disown(_) => session.run(input).exit_code,
         InputKind::Judgy(_) | InputKind::Yolo(_) => {
             // Handled only in interactive mode; no-op in non-interactive
             0
         }
+        // A script mustn't be able to trust the project it came with
+        InputKind::Trust(_) => {
+            eprintln!("claudesh: trust: only available interactively");
+            1
+        }
+        InputKind::Context => print_context(config),
+        InputKind::ContextClear => {
+            config.transcript.lock().unwrap().clear();
+            0
+        }
         InputKind::ForceBash(cmd) => {
-            let result = run_bash(&cmd, cwd);
+            let result = session.run(&cmd);
             result.exit_code
         }
         InputKind::Explain(subject) => {
-            if claude_available {
-                explain_command(&subject, cwd, config);
+            if ai_available {
+                explain_command(&subject, session.cwd(), config)
             } else {
-                eprintln!("{}claude CLI not available{}", COLOR_RED, COLOR_RESET);
+                eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                0
             }
-            0
         }
         InputKind::Ask(question) => {
-            if claude_available {
-                ask_question(&question, cwd, config);
+            if ai_available {
+                ask_question(&question, session.cwd(), config)
             } else {
-                eprintln!("{}claude CLI not available{}", COLOR_RED, COLOR_RESET);
+                eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                0
             }
-            0
         }
         InputKind::ShellCommand(cmd) => {
-            let result = run_bash(&cmd, cwd);
+            let result = session.run(&cmd);
             result.exit_code
         }
         InputKind::NaturalLanguage(text) => {
-            if claude_available {
+            if ai_available {
                 // Non-interactive: just generate the command and print it.
                 // Apply personality so conversational responses are in character.
-                let prompt = build_system_prompt(&config.prompt_generate, &config.personality);
-                if let Some(cmd) = call_claude(&prompt, &text, cwd) {
-                    let cmd = strip_code_fences(&cmd);
-                    // Check if this is conversational (not a command)
-                    if let Some(message) = cmd.strip_prefix("CONVERSATIONAL:") {
-                        println!("{}", message.trim());
-                    } else if looks_like_conversation(&cmd) {
-                        println!("{}", cmd);
-                    } else {
-                        println!("{}", cmd);
+                match call_ai(config, PromptKind::Generate, &text, session.cwd()) {
+                    Ok(cmd) => {
+                        let cmd = strip_code_fences(&cmd);
+                        // Conversational replies are printed without the prefix
+                        match cmd.strip_prefix("CONVERSATIONAL:") {
+                            Some(message) => println!("{}", message.trim()),
+                            None => println!("{}", cmd),
+                        }
+                    }
+                    Err(e) => {
+                        report_ai_error(&e);
+                        return e.exit_status();
                     }
                 }
             } else {
@@ -345,29 +540,73 @@ fn execute_line(
 }
 
 /// Interactive REPL
-fn run_interactive(config: &Config) -> ExitCode {
-    let mut editor = DefaultEditor::new().expect("Failed to initialize line editor");
+fn run_interactive(config: &Arc<Config>) -> ExitCode {
+    // Tab lists the candidates like bash does, rather than cycling through them
+    let mut editor_config = rustyline::Config::builder()
+        .completion_type(rustyline::CompletionType::List)
+        .history_ignore_space(config.history_ignore_space);
+    if let Some(size) = config.history_size {
+        editor_config = editor_config
+            .max_history_size(size)
+            .expect("history size checked when loading config");
+    }
+    let editor_config = editor_config.build();
+    let mut editor =
+        LineEditor::with_config(editor_config).expect("Failed to initialize line editor");
+    let mut helper = ShellHelper::default();
+    helper.plain = !config.highlight;
+    helper.ai_color = config.ai_color;
+    // Alt-Enter puts a command for the line in its place, to edit before running
+    editor.bind_sequence(
+        KeyEvent(KeyCode::Enter, Modifiers::ALT),
+        EventHandler::Conditional(Box::new(helper.submit_handler(Submit::Generate))),
+    );
+    // Ctrl-X Ctrl-E opens the line in $EDITOR, as in bash
+    editor.bind_sequence(
+        Event::KeySeq(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]),
+        EventHandler::Conditional(Box::new(helper.submit_handler(Submit::Editor))),
+    );
+    editor.set_helper(Some(helper));
 
     let history_path = history_file_path();
     if let Some(ref path) = history_path {
         let _ = editor.load_history(path);
     }
 
-    let mut cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
     env::set_var("PWD", &cwd);
+    signals::ignore_job_control();
+    signals::watch_children();
+    let Some(mut session) = start_session(&cwd, true) else {
+        return ExitCode::from(127);
+    };
 
-    let path_commands = build_path_command_set();
+    let mut path_commands = build_path_command_set();
+    let mut last_path = env::var_os("PATH");
 
-    let claude_available = which::which("claude").is_ok();
-    if !claude_available {
+    let ai_unavailable = config.ai.unavailable_reason();
+    let ai_available = ai_unavailable.is_none();
+    if let Some(reason) = ai_unavailable {
         eprintln!(
-            "{}{}warning:{} 'claude' CLI not found in PATH. AI features disabled.",
-            COLOR_BOLD, COLOR_YELLOW, COLOR_RESET
+            "{}{}warning:{} {}. AI features disabled.",
+            COLOR_BOLD, COLOR_YELLOW, COLOR_RESET, reason
         );
     }
+    if config.ai_hints && ai_available {
+        if let Ok(printer) = editor.create_external_printer() {
+            let hints = hints::AiHints::new(Arc::clone(config), Box::new(printer));
+            if let Some(helper) = editor.helper_mut() {
+                helper.ai_hints = Some(hints);
+            }
+        }
+    }
 
     let is_root = is_user_root();
     let mut last_exit: i32 = 0;
+    // How long the last command took, for the prompt
+    let mut last_duration = Duration::ZERO;
+    // Set after warning about stopped jobs; exiting again right away leaves them
+    let mut warned_stopped_jobs = false;
 
     // Yolo mode state — initialized from config file, toggled by builtin
     let mut yolo_enabled = config.yolo;
@@ -375,24 +614,26 @@ fn run_interactive(config: &Config) -> ExitCode {
     let mut judgy_enabled = config.judgy;
     // Session history for judgy mode: records commands and AI commentary
     let mut session_history: Vec<String> = Vec::new();
+    // Text to start the next prompt with, e.g. a command found by `history ask`
+    let mut initial_line: Option<String> = None;
+    // The command Alt-Enter last put on the prompt, so running that line is
+    // recorded as generated rather than typed, and confirmed if destructive
+    let mut generated: Option<InPlace> = None;
 
     // Source ~/.claudeshrc if it exists
     let rc_path = config.config_dir.join("claudeshrc");
     if rc_path.exists() {
         if let Ok(contents) = fs::read_to_string(&rc_path) {
-            for line in contents.lines() {
-                let input = line.trim();
-                if input.is_empty() || input.starts_with('#') {
-                    continue;
-                }
+            for statement in parse::split_statements(&contents) {
                 last_exit = execute_line(
-                    input,
-                    &mut cwd,
+                    &statement,
+                    &mut session,
                     &path_commands,
-                    claude_available,
+                    ai_available,
                     config,
                     Some(&mut editor),
                 );
+                refresh_path_commands(&mut path_commands, &mut last_path);
             }
         }
     }
@@ -400,31 +641,114 @@ fn run_interactive(config: &Config) -> ExitCode {
     print_welcome(yolo_enabled, judgy_enabled);
 
     loop {
-        let prompt = format_prompt(&cwd, is_root, last_exit);
-        match editor.readline(&prompt) {
+        // Job status changes are announced before the prompt, as in bash
+        for notice in session.take_notices() {
+            eprintln!("{}", notice);
+        }
+        config.enter_dir(session.cwd());
+
+        let (prompt, right_prompt) = config.prompt.render(&prompt::Facts {
+            cwd: session.cwd(),
+            is_root,
+            last_exit,
+            duration: last_duration,
+            yolo: yolo_enabled,
+            judgy: judgy_enabled,
+        });
+        if let Some(helper) = editor.helper_mut() {
+            helper.path_commands.clone_from(&path_commands);
+            config.context.set_tools(&path_commands);
+            helper.shell_names.clone_from(session.names());
+            helper.cwd = session.cwd().to_path_buf();
+            helper.right_prompt = (prompt::visible_width(&right_prompt) > 0).then(|| RightPrompt {
+                text: right_prompt,
+                prompt_width: prompt::visible_width(prompt.rsplit('\n').next().unwrap_or_default()),
+            });
+        }
+        let initial = initial_line.take().unwrap_or_default();
+        let statement = read_statement(&mut editor, &prompt, &initial);
+        if let Some(hints) = editor.helper().and_then(|h| h.ai_hints.as_ref()) {
+            hints.reset();
+        }
+        let submitted = editor.helper().and_then(|h| h.take_submitted());
+        let from_request = generated.take();
+        last_duration = Duration::ZERO;
+        match statement {
             Ok(line) => {
+                // What's saved in the editor runs as if it had been typed
+                let line = if submitted == Some(Submit::Editor) {
+                    let Some(edited) = edit_in_editor(&line, &mut session) else {
+                        continue;
+                    };
+                    if !edited.trim().is_empty() {
+                        println!("{}", edited.trim());
+                    }
+                    edited
+                } else {
+                    line
+                };
                 let input = line.trim();
                 if input.is_empty() {
                     continue;
                 }
                 editor.add_history_entry(input).ok();
 
-                let kind = classify_input(input, &path_commands);
+                if submitted == Some(Submit::Generate) {
+                    if !ai_available {
+                        eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                        initial_line = Some(input.to_string());
+                        continue;
+                    }
+                    match generate_in_place(input, session.cwd(), config) {
+                        Ok(in_place) => {
+                            initial_line = Some(in_place.command.clone());
+                            generated = Some(in_place);
+                            last_exit = 0;
+                        }
+                        Err(code) => last_exit = code,
+                    }
+                    continue;
+                }
+
+                // Showing a destructive command on the prompt isn't agreeing to
+                // run it, so it takes the same `yes` as when it's offered, and
+                // so does whatever it was edited into
+                let high_risk = from_request
+                    .as_ref()
+                    .and_then(|_| safety::assess(input))
+                    .filter(|f| f.risk == Risk::High);
+                if let Some(finding) = high_risk {
+                    eprintln!("{}# {}{}", COLOR_RED, finding.reason, COLOR_RESET);
+                    if !confirm_high_risk() {
+                        eprintln!("{}skipped{}", COLOR_DIM, COLOR_RESET);
+                        last_exit = 0;
+                        continue;
+                    }
+                }
+
+                let kind = classify_input(input, &path_commands, session.names());
+                let started = Instant::now();
 
                 // Generate judgy commentary for Explain and Ask (NaturalLanguage handles its own)
                 // (skip for shell commands, builtins, meta commands, and NaturalLanguage)
-                if judgy_enabled && claude_available {
+                if judgy_enabled && ai_available {
                     let skip_judgy = matches!(
                         kind,
                         InputKind::ShellCommand(_)
                             | InputKind::ForceBash(_)
-                            | InputKind::Cd(_)
-                            | InputKind::Export(_)
-                            | InputKind::Unset(_)
                             | InputKind::Source(_)
-                            | InputKind::History
+                            | InputKind::History(_)
+                            | InputKind::HistoryAsk(_)
+                            | InputKind::Jobs
+                            | InputKind::Fg(_)
+                            | InputKind::Bg(_)
+                            | InputKind::Wait(_)
+                            | InputKind::Disown(_)
                             | InputKind::Judgy(_)
                             | InputKind::Yolo(_)
+                            | InputKind::Trust(_)
+                            | InputKind::Context
+                            | InputKind::ContextClear
                             | InputKind::Help
                             | InputKind::Comment
                             | InputKind::Exit(_)
@@ -432,16 +756,19 @@ fn run_interactive(config: &Config) -> ExitCode {
                     );
                     if !skip_judgy {
                         let _spinner = Spinner::new();
-                        if let Some(commentary) =
-                            generate_judgy_commentary(input, &session_history, &cwd, config)
-                        {
-                            drop(_spinner);
-                            eprintln!(
-                                "{}{}{}{}",
-                                COLOR_DIM, COLOR_ITALIC, commentary, COLOR_RESET
-                            );
-                            session_history
-                                .push(format!("[judgy]: {}", commentary));
+                        let commentary =
+                            generate_judgy_commentary(input, &session_history, session.cwd(), config);
+                        drop(_spinner);
+                        match commentary {
+                            Ok(commentary) => {
+                                eprintln!(
+                                    "{}{}{}{}",
+                                    COLOR_DIM, COLOR_ITALIC, commentary, COLOR_RESET
+                                );
+                                session_history
+                                    .push(format!("[judgy]: {}", commentary));
+                            }
+                            Err(e) => report_ai_error(&e),
                         }
                     }
                 }
@@ -451,6 +778,14 @@ fn run_interactive(config: &Config) -> ExitCode {
                     session_history.push(format!("[user]: {}", input));
                 }
 
+                let exiting = matches!(kind, InputKind::Exit(_));
+                if exiting && !warned_stopped_jobs && warn_stopped_jobs(&session) {
+                    warned_stopped_jobs = true;
+                    last_exit = 1;
+                    continue;
+                }
+                warned_stopped_jobs = false;
+
                 last_exit = match kind {
                     InputKind::Exit(code) => {
                         println!("{}bye{}", COLOR_DIM, COLOR_RESET);
@@ -462,39 +797,43 @@ fn run_interactive(config: &Config) -> ExitCode {
                         print_help();
                         0
                     }
-                    InputKind::Cd(dir) => handle_cd(&dir, &mut cwd),
-                    InputKind::Export(assignment) => {
-                        if assignment.is_empty() {
-                            // Bare export - run through bash to show all exported vars
-                            let result = run_bash("export", &cwd);
-                            result.exit_code
-                        } else {
-                            handle_export(&assignment);
-                            0
-                        }
-                    }
-                    InputKind::Unset(name) => {
-                        if name.is_empty() {
-                            // Bare unset - run through bash
-                            let result = run_bash("unset", &cwd);
-                            result.exit_code
-                        } else {
-                            env::remove_var(&name);
-                            0
-                        }
-                    }
                     InputKind::Source(path) => handle_source(
                         &path,
-                        &mut cwd,
+                        &mut session,
                         &path_commands,
-                        claude_available,
+                        ai_available,
                         config,
                         Some(&mut editor),
                     ),
-                    InputKind::History => {
-                        print_history(&editor);
+                    InputKind::History(args) => {
+                        print_history(Some(&editor), &args, session.cwd(), config)
+                    }
+                    InputKind::HistoryAsk(question) => {
+                        if ai_available {
+                            match search_history(&question, session.cwd(), config) {
+                                Ok(matches) => {
+                                    // The pick goes on the next prompt for editing
+                                    initial_line = choose_history_match(&matches);
+                                    0
+                                }
+                                Err(code) => code,
+                            }
+                        } else {
+                            eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                            1
+                        }
+                    }
+                    InputKind::Jobs => {
+                        for notice in session.take_notices() {
+                            eprintln!("{}", notice);
+                        }
+                        print_jobs(&session);
                         0
                     }
+                    InputKind::Fg(args) => session.fg(&args),
+                    InputKind::Bg(args) => session.bg(&args),
+                    InputKind::Wait(args) => session.wait(&args),
+                    InputKind::Disown(args) => session.disown(&args),
                     InputKind::Judgy(enable) => {
                         judgy_enabled = enable;
                         let judgy_file = config.config_dir.join("judgy");
@@ -535,41 +874,50 @@ fn run_interactive(config: &Config) -> ExitCode {
                         }
                         0
                     }
+                    InputKind::Trust(enable) => set_trust(enable, config),
+                    InputKind::Context => print_context(config),
+                    InputKind::ContextClear => {
+                        config.transcript.lock().unwrap().clear();
+                        eprintln!("{}context cleared{}", COLOR_DIM, COLOR_RESET);
+                        0
+                    }
                     InputKind::ForceBash(cmd) => {
-                        let result = run_bash(&cmd, &cwd);
-                        if result.exit_code != 0 && claude_available {
-                            offer_error_help(&cmd, &result, &cwd, &mut editor, config);
+                        let origin = typed_origin(&cmd, from_request);
+                        let result = run_recorded(&cmd, origin, &mut session, config);
+                        if result.exit_code != 0 && ai_available {
+                            offer_error_help(&cmd, &result, &mut session, &mut editor, config);
                         }
                         result.exit_code
                     }
                     InputKind::Explain(subject) => {
-                        if claude_available {
-                            explain_command(&subject, &cwd, config);
+                        if ai_available {
+                            explain_command(&subject, session.cwd(), config)
                         } else {
-                            eprintln!("{}claude CLI not available{}", COLOR_RED, COLOR_RESET);
+                            eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                            0
                         }
-                        0
                     }
                     InputKind::Ask(question) => {
-                        if claude_available {
-                            ask_question(&question, &cwd, config);
+                        if ai_available {
+                            ask_question(&question, session.cwd(), config)
                         } else {
-                            eprintln!("{}claude CLI not available{}", COLOR_RED, COLOR_RESET);
+                            eprintln!("{}AI backend not available{}", COLOR_RED, COLOR_RESET);
+                            0
                         }
-                        0
                     }
                     InputKind::ShellCommand(cmd) => {
-                        let result = run_bash(&cmd, &cwd);
-                        if result.exit_code != 0 && claude_available {
-                            offer_error_help(&cmd, &result, &cwd, &mut editor, config);
+                        let origin = typed_origin(&cmd, from_request);
+                        let result = run_recorded(&cmd, origin, &mut session, config);
+                        if result.exit_code != 0 && ai_available {
+                            offer_error_help(&cmd, &result, &mut session, &mut editor, config);
                         }
                         result.exit_code
                     }
                     InputKind::NaturalLanguage(text) => {
-                        if claude_available {
+                        if ai_available {
                             handle_natural_language_interactive(
                                 &text,
-                                &mut cwd,
+                                &mut session,
                                 &mut editor,
                                 config,
                                 yolo_enabled,
@@ -578,19 +926,25 @@ fn run_interactive(config: &Config) -> ExitCode {
                             )
                         } else {
                             eprintln!(
-                                "{}not a recognized command and claude CLI is unavailable{}",
+                                "{}not a recognized command and the AI backend is unavailable{}",
                                 COLOR_RED, COLOR_RESET
                             );
                             127
                         }
                     }
                 };
+                last_duration = started.elapsed();
+                refresh_path_commands(&mut path_commands, &mut last_path);
             }
             Err(ReadlineError::Interrupted) => {
                 println!();
                 continue;
             }
             Err(ReadlineError::Eof) => {
+                if !warned_stopped_jobs && warn_stopped_jobs(&session) {
+                    warned_stopped_jobs = true;
+                    continue;
+                }
                 println!("{}bye{}", COLOR_DIM, COLOR_RESET);
                 break;
             }
@@ -608,6 +962,38 @@ fn run_interactive(config: &Config) -> ExitCode {
     ExitCode::from(last_exit as u8)
 }
 
+/// Read one statement, prompting with `CONTINUATION_PROMPT` until any open
+/// quote, compound command, heredoc or trailing operator is closed. The first
+/// line starts out as `initial`. A line submitted with Alt-Enter or
+/// Ctrl-X Ctrl-E is returned as it stands, since it's going elsewhere first.
+fn read_statement(editor: &mut LineEditor, prompt: &str, initial: &str) -> rustyline::Result<String> {
+    let first = editor.readline_with_initial(prompt, (initial, ""));
+    // The right prompt belongs to the first line only
+    if let Some(helper) = editor.helper_mut() {
+        helper.right_prompt = None;
+    }
+    let mut block = first?;
+    while editor.helper().is_some_and(|helper| {
+        helper.submitted().is_none() && helper.is_incomplete(block.trim())
+    }) {
+        match editor.readline(CONTINUATION_PROMPT) {
+            Ok(line) => {
+                block.push('\n');
+                block.push_str(&line);
+            }
+            Err(ReadlineError::Eof) => {
+                eprintln!(
+                    "{}claudesh: unexpected end of file{}",
+                    COLOR_RED, COLOR_RESET
+                );
+                return Ok(String::new());
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(block)
+}
+
 // ─── Config ──────────────────────────────────────────────────────────────────
 
 fn load_config() -> Config {
@@ -615,17 +1001,47 @@ fn load_config() -> Config {
         .map(|h| h.join(".claudesh"))
         .unwrap_or_else(|| PathBuf::from(".claudesh"));
 
-    let prompts_dir = config_dir.join("prompts");
-
-    let prompt_generate = load_prompt_file(&prompts_dir, "generate.txt", DEFAULT_PROMPT_GENERATE);
-    let prompt_explain = load_prompt_file(&prompts_dir, "explain.txt", DEFAULT_PROMPT_EXPLAIN);
-    let prompt_ask = load_prompt_file(&prompts_dir, "ask.txt", DEFAULT_PROMPT_ASK);
-    let prompt_fix = load_prompt_file(&prompts_dir, "fix.txt", DEFAULT_PROMPT_FIX);
-    let prompt_script = load_prompt_file(&prompts_dir, "script.txt", DEFAULT_PROMPT_SCRIPT);
-    let prompt_judgy = load_prompt_file(&prompts_dir, "judgy.txt", DEFAULT_PROMPT_JUDGY);
-    let personality = load_prompt_file(&config_dir, "personality", DEFAULT_PERSONALITY);
-    let yolo = config_dir.join("yolo").exists();
-    let judgy = config_dir.join("judgy").exists();
+    let config_path = config_dir.join("config.toml");
+    let file = ConfigFile::load(&config_path).unwrap_or_else(|e| {
+        eprintln!("claudesh: {}: {}", config_path.display(), e);
+        ConfigFile::default()
+    });
+
+    let load = |name, default| load_prompt_file(&file.prompts, &config_dir, name, default);
+    let prompt_generate = load("generate", DEFAULT_PROMPT_GENERATE);
+    let prompt_explain = load("explain", DEFAULT_PROMPT_EXPLAIN);
+    let prompt_ask = load("ask", DEFAULT_PROMPT_ASK);
+    let prompt_fix = load("fix", DEFAULT_PROMPT_FIX);
+    let prompt_script = load("script", DEFAULT_PROMPT_SCRIPT);
+    let prompt_judgy = load("judgy", DEFAULT_PROMPT_JUDGY);
+    let prompt_history = load("history", DEFAULT_PROMPT_HISTORY);
+    let prompt_hint = load("hint", DEFAULT_PROMPT_HINT);
+    let personality = load("personality", DEFAULT_PERSONALITY);
+    // The marker files `yolo on` and `judgy on` leave behind still count
+    let yolo = file.safety.yolo || config_dir.join("yolo").exists();
+    let judgy = file.ui.judgy || config_dir.join("judgy").exists();
+
+    let ai = ai::from_settings(&file.ai).unwrap_or_else(|e| {
+        eprintln!("claudesh: {}: {}", config_path.display(), e);
+        ai::from_settings(&ai::AiSettings::default()).expect("default AI backend")
+    });
+    let ai_timeout = file.ai.timeout();
+    let prompt = prompt::PromptFormat::new(
+        file.ui.prompt.as_deref().unwrap_or(prompt::DEFAULT_PROMPT),
+        file.ui.right_prompt.as_deref().unwrap_or_default(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("claudesh: {}: {}", config_path.display(), e);
+        prompt::PromptFormat::default()
+    });
+
+    let policy_path = config_dir.join("policy.toml");
+    let policy = Policy::load(&policy_path).unwrap_or_else(|e| {
+        eprintln!("claudesh: {}: {}", policy_path.display(), e);
+        Policy::default()
+    });
+    let history = History::new(config_dir.join("history.jsonl"));
+    let projects = project::Projects::new(&config_dir);
 
     Config {
         prompt_generate,
@@ -634,19 +1050,60 @@ fn load_config() -> Config {
         prompt_fix,
         prompt_script,
         prompt_judgy,
+        prompt_history,
+        prompt_hint,
         personality,
         config_dir,
         yolo,
         judgy,
+        ai,
+        ai_timeout,
+        policy,
+        history,
+        record_history: file.history.record.unwrap_or(true),
+        history_size: file.history.size,
+        history_ignore_space: file.history.ignore_space,
+        ai_hints: file.ui.ai_hints,
+        prompt,
+        highlight: file.ui.highlight.unwrap_or(true),
+        ai_color: file.ui.ai_color.as_deref().and_then(prompt::color),
+        error_help: file.ui.error_help.unwrap_or(true),
+        sudo_retry: file.safety.sudo_retry.unwrap_or(true),
+        projects,
+        transcript: Arc::new(Mutex::new(transcript::Transcript::new(
+            file.context.commands.unwrap_or(transcript::DEFAULT_COMMANDS),
+            file.context.output.unwrap_or(transcript::DEFAULT_OUTPUT),
+        ))),
+        context: context::Collector::default(),
     }
 }
 
-fn load_prompt_file(dir: &Path, filename: &str, default: &str) -> String {
-    let path = dir.join(filename);
-    fs::read_to_string(&path)
-        .unwrap_or_else(|_| default.to_string())
+/// The prompt `name`, from where `[prompts]` says or the prompts directory.
+/// A file that was set explicitly but can't be read is reported, and so are
+/// placeholders that aren't known.
+fn load_prompt_file(
+    prompts: &config::PromptSettings,
+    config_dir: &Path,
+    name: &str,
+    default: &str,
+) -> String {
+    let (path, explicit) = prompts.path(config_dir, name);
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|e| {
+            if explicit {
+                eprintln!("claudesh: {}: {}", path.display(), e);
+            }
+            default.to_string()
+        })
         .trim()
-        .to_string()
+        .to_string();
+    // The personality is filled into prompts, not filled in itself
+    if name != "personality" {
+        if let Err(e) = template::check(&text) {
+            eprintln!("claudesh: {}: {}", path.display(), e);
+        }
+    }
+    text
 }
 
 /// Create ~/.claudesh/ with default files if it doesn't exist
@@ -659,12 +1116,16 @@ fn ensure_config_dir(config: &Config) {
 
         // Write default files
         write_default(dir, "personality", DEFAULT_PERSONALITY);
+        write_default(dir, "config.toml", DEFAULT_CONFIG);
+        write_default(dir, "policy.toml", DEFAULT_POLICY);
         write_default(&prompts_dir, "generate.txt", DEFAULT_PROMPT_GENERATE);
         write_default(&prompts_dir, "explain.txt", DEFAULT_PROMPT_EXPLAIN);
         write_default(&prompts_dir, "ask.txt", DEFAULT_PROMPT_ASK);
         write_default(&prompts_dir, "fix.txt", DEFAULT_PROMPT_FIX);
         write_default(&prompts_dir, "script.txt", DEFAULT_PROMPT_SCRIPT);
         write_default(&prompts_dir, "judgy.txt", DEFAULT_PROMPT_JUDGY);
+        write_default(&prompts_dir, "history.txt", DEFAULT_PROMPT_HISTORY);
+        write_default(&prompts_dir, "hint.txt", DEFAULT_PROMPT_HINT);
     }
 }
 
@@ -689,22 +1150,33 @@ fn build_system_prompt(base_prompt: &str, personality: &str) -> String {
 enum InputKind {
     Exit(Option<i32>),
     Help,
-    Cd(String),
-    Export(String),
-    Unset(String),
     Source(String),
-    History,
+    History(String),
+    HistoryAsk(String),
+    Jobs,
+    /// `fg`, `bg`, `wait` and `disown`, with their arguments.
+    Fg(String),
+    Bg(String),
+    Wait(String),
+    Disown(String),
     Comment,
     ForceBash(String),
     Explain(String),
     Ask(String),
     Judgy(bool),
     Yolo(bool),
+    Trust(bool),
+    Context,
+    ContextClear,
     ShellCommand(String),
     NaturalLanguage(String),
 }
 
-fn classify_input(input: &str, path_commands: &HashSet<String>) -> InputKind {
+fn classify_input(
+    input: &str,
+    path_commands: &HashSet<String>,
+    shell_names: &HashSet<String>,
+) -> InputKind {
     // Comments — skip silently
     if input.starts_with('#') {
         return InputKind::Comment;
@@ -723,7 +1195,27 @@ fn classify_input(input: &str, path_commands: &HashSet<String>) -> InputKind {
         return InputKind::Help;
     }
     if input == "history" {
-        return InputKind::History;
+        return InputKind::History(String::new());
+    }
+    if let Some(question) = input.strip_prefix("history ask ") {
+        return InputKind::HistoryAsk(question.trim().to_string());
+    }
+    if let Some(args) = input.strip_prefix("history ") {
+        return InputKind::History(args.trim().to_string());
+    }
+    if input == "jobs" {
+        return InputKind::Jobs;
+    }
+    // Job builtins with anything more to them than job specs are bash's
+    if let Some((name, args)) = split_job_builtin(input) {
+        let args = args.to_string();
+        match name {
+            "fg" => return InputKind::Fg(args),
+            "bg" => return InputKind::Bg(args),
+            "wait" => return InputKind::Wait(args),
+            "disown" => return InputKind::Disown(args),
+            _ => {}
+        }
     }
 
     // judgy on/off builtin
@@ -742,6 +1234,22 @@ fn classify_input(input: &str, path_commands: &HashSet<String>) -> InputKind {
         return InputKind::Yolo(false);
     }
 
+    // trust/trust off builtin
+    if input == "trust" {
+        return InputKind::Trust(true);
+    }
+    if input == "trust off" {
+        return InputKind::Trust(false);
+    }
+
+    // context/context clear builtin
+    if input == "context" {
+        return InputKind::Context;
+    }
+    if input == "context clear" {
+        return InputKind::ContextClear;
+    }
+
     // ! prefix: force bash execution
     if let Some(cmd) = input.strip_prefix("! ").or_else(|| input.strip_prefix("!")) {
         let cmd = cmd.trim();
@@ -766,30 +1274,6 @@ fn classify_input(input: &str, path_commands: &HashSet<String>) -> InputKind {
         }
     }
 
-    // cd builtin
-    if input == "cd" {
-        return InputKind::Cd(String::new());
-    }
-    if let Some(dir) = input.strip_prefix("cd ") {
-        return InputKind::Cd(dir.trim().to_string());
-    }
-
-    // export builtin
-    if input == "export" {
-        return InputKind::Export(String::new());
-    }
-    if let Some(assignment) = input.strip_prefix("export ") {
-        return InputKind::Export(assignment.trim().to_string());
-    }
-
-    // unset builtin
-    if input == "unset" {
-        return InputKind::Unset(String::new());
-    }
-    if let Some(name) = input.strip_prefix("unset ") {
-        return InputKind::Unset(name.trim().to_string());
-    }
-
     // source / . builtin
     if let Some(path) = input.strip_prefix("source ") {
         return InputKind::Source(path.trim().to_string());
@@ -799,14 +1283,51 @@ fn classify_input(input: &str, path_commands: &HashSet<String>) -> InputKind {
     }
 
     // Check if it looks like a shell command
-    if is_shell_command(input, path_commands) {
+    if is_shell_command(input, path_commands, shell_names) {
         InputKind::ShellCommand(input.to_string())
     } else {
         InputKind::NaturalLanguage(input.to_string())
     }
 }
 
-fn is_shell_command(input: &str, path_commands: &HashSet<String>) -> bool {
+/// Split `fg %1`-style input into the builtin's name and its arguments.
+fn split_job_builtin(input: &str) -> Option<(&str, &str)> {
+    let (name, args) = input.split_once(' ').unwrap_or((input, ""));
+    let plain = !args.contains(|c| ";&|<>()$`'\"\\\n*?[{".contains(c));
+    (plain && matches!(name, "fg" | "bg" | "wait" | "disown")).then(|| (name, args.trim()))
+}
+
+/// Whether `input` would run as shell code that is not yet a complete
+/// statement. Natural-language input is never incomplete, even when it
+/// contains an unbalanced apostrophe.
+fn is_incomplete_command(
+    input: &str,
+    path_commands: &HashSet<String>,
+    shell_names: &HashSet<String>,
+) -> bool {
+    match classify_input(input, path_commands, shell_names) {
+        InputKind::ShellCommand(cmd) | InputKind::ForceBash(cmd) => parse::is_incomplete(&cmd),
+        _ => false,
+    }
+}
+
+/// Whether `input` reads as a request for the AI rather than shell code.
+fn is_natural_language(
+    input: &str,
+    path_commands: &HashSet<String>,
+    shell_names: &HashSet<String>,
+) -> bool {
+    matches!(
+        classify_input(input, path_commands, shell_names),
+        InputKind::NaturalLanguage(_) | InputKind::Ask(_) | InputKind::HistoryAsk(_)
+    )
+}
+
+fn is_shell_command(
+    input: &str,
+    path_commands: &HashSet<String>,
+    shell_names: &HashSet<String>,
+) -> bool {
     let first_char = input.chars().next().unwrap_or(' ');
 
     // Shell syntax characters
@@ -830,6 +1351,18 @@ fn is_shell_command(input: &str, path_commands: &HashSet<String>) -> bool {
         }
     }
 
+    // Function definition: name() { ... }
+    if let Some(paren) = input.find("()") {
+        let name = input[..paren].trim_end();
+        if !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return true;
+        }
+    }
+
     // Command prefixes: sudo, env, nohup, etc.
     for prefix in COMMAND_PREFIXES {
         if input.starts_with(prefix) {
@@ -853,6 +1386,11 @@ fn is_shell_command(input: &str, path_commands: &HashSet<String>) -> bool {
         return true;
     }
 
+    // Aliases and functions defined in the bash session
+    if shell_names.contains(first_token) {
+        return true;
+    }
+
     // Path to executable
     if first_token.contains('/') {
         return true;
@@ -877,152 +1415,182 @@ fn build_path_command_set() -> HashSet<String> {
     commands
 }
 
-// ─── Bash Execution ──────────────────────────────────────────────────────────
-
-/// Run a command via bash with inherited stdin/stdout.
-/// Stderr is tee'd via raw byte forwarding: displayed in real-time AND
-/// captured for error analysis. Raw bytes preserve \r progress bars,
-/// ANSI color codes, and other terminal sequences.
-fn run_bash(cmd: &str, cwd: &Path) -> RunResult {
-    let child = Command::new("bash")
-        .arg("-c")
-        .arg(cmd)
-        .current_dir(cwd)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::piped())
-        .spawn();
-
-    match child {
-        Ok(mut child) => {
-            let mut stderr_pipe = child.stderr.take().unwrap();
-            let stderr_thread = std::thread::spawn(move || {
-                let mut captured = Vec::new();
-                let mut buf = [0u8; 4096];
-                let mut stderr_out = io::stderr();
-                loop {
-                    match stderr_pipe.read(&mut buf) {
-                        Ok(0) => break,
-                        Ok(n) => {
-                            // Forward raw bytes to terminal
-                            stderr_out.write_all(&buf[..n]).ok();
-                            stderr_out.flush().ok();
-                            // Capture for error analysis (bounded)
-                            if captured.len() < STDERR_CAPTURE_LIMIT {
-                                let remaining = STDERR_CAPTURE_LIMIT - captured.len();
-                                captured.extend_from_slice(&buf[..n.min(remaining)]);
-                            }
-                        }
-                        Err(_) => break,
-                    }
-                }
-                String::from_utf8_lossy(&captured).to_string()
-            });
-
-            let status = child.wait();
-            let captured_stderr = stderr_thread.join().unwrap_or_default();
-
-            let exit_code = match status {
-                Ok(s) => s.code().unwrap_or(1),
-                Err(_) => 1,
-            };
+// ─── Builtins ────────────────────────────────────────────────────────────────
 
-            RunResult {
-                exit_code,
-                captured_stderr,
-            }
-        }
-        Err(e) => {
-            let msg = format!("failed to execute: {}", e);
-            eprintln!("{}{}{}", COLOR_RED, msg, COLOR_RESET);
-            RunResult {
-                exit_code: 127,
-                captured_stderr: msg,
-            }
-        }
+/// List the session's jobs in the same layout as bash's `jobs`.
+fn print_jobs(session: &BashSession) {
+    let jobs = session.jobs();
+    for job in jobs.iter() {
+        let color = if job.state.is_stopped() { COLOR_YELLOW } else { COLOR_GREEN };
+        let background = if job.state.is_stopped() { "" } else { " &" };
+        println!(
+            "[{}]{}  {}{:<24}{}{}{}",
+            job.number,
+            jobs.marker(job.number),
+            color,
+            job.state.describe(),
+            COLOR_RESET,
+            job.command,
+            background
+        );
     }
 }
 
-// ─── Builtins ────────────────────────────────────────────────────────────────
+/// Warn, like bash does, before exiting with stopped jobs. Returns true if
+/// there were any.
+fn warn_stopped_jobs(session: &BashSession) -> bool {
+    if !session.jobs().iter().any(|job| job.state.is_stopped()) {
+        return false;
+    }
+    eprintln!(
+        "{}There are stopped jobs.{} Exit again to leave them behind.",
+        COLOR_YELLOW, COLOR_RESET
+    );
+    true
+}
 
-fn handle_cd(dir: &str, cwd: &mut PathBuf) -> i32 {
-    let dir = strip_shell_quotes(dir);
-    let target = if dir.is_empty() {
-        dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"))
-    } else if dir == "-" {
-        if let Ok(old) = env::var("OLDPWD") {
-            println!("{}", old);
-            PathBuf::from(old)
-        } else {
-            eprintln!("{}cd: OLDPWD not set{}", COLOR_RED, COLOR_RESET);
-            return 1;
-        }
-    } else {
-        let expanded = shellexpand_tilde(&dir);
-        let path = Path::new(&expanded);
-        if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            cwd.join(path)
+/// `history` on its own lists the line editor's history. With filters
+/// (`--cwd [DIR]`, `--failed`, `--ai`, `--since WHEN`) it lists entries from
+/// the structured history instead, with when, where and how they ran.
+fn print_history(editor: Option<&LineEditor>, args: &str, cwd: &Path, config: &Config) -> i32 {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let filter = match history::Filter::parse(&args, cwd) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{}history: {}{}", COLOR_RED, e, COLOR_RESET);
+            return 2;
         }
     };
 
-    match target.canonicalize() {
-        Ok(real_path) => {
-            if real_path.is_dir() {
-                env::set_var("OLDPWD", cwd.as_os_str());
-                *cwd = real_path.clone();
-                env::set_current_dir(&real_path).ok();
-                env::set_var("PWD", &real_path);
-                0
-            } else {
-                eprintln!(
-                    "{}cd: not a directory: {}{}",
-                    COLOR_RED,
-                    target.display(),
-                    COLOR_RESET
-                );
-                1
+    // Without filters it's the plain history, as in bash
+    if filter.selects_all() {
+        if let Some(editor) = editor {
+            let lines: Vec<_> = editor.history().iter().collect();
+            let skip = filter.count.map_or(0, |n| lines.len().saturating_sub(n));
+            for (i, entry) in lines.into_iter().enumerate().skip(skip) {
+                println!("  {}{:4}{} {}", COLOR_DIM, i + 1, COLOR_RESET, entry);
             }
         }
-        Err(_) => {
-            eprintln!(
-                "{}cd: no such directory: {}{}",
-                COLOR_RED,
-                target.display(),
-                COLOR_RESET
-            );
-            1
-        }
+        return 0;
     }
+
+    let entries = config.history.entries();
+    let matching: Vec<_> = entries.iter().filter(|e| filter.matches(e)).collect();
+    let skip = filter.count.map_or(0, |n| matching.len().saturating_sub(n));
+    for entry in matching.into_iter().skip(skip) {
+        let status = if entry.exit_code == 0 { COLOR_DIM } else { COLOR_RED };
+        let origin = match &entry.origin {
+            Origin::Typed => String::new(),
+            Origin::Ai { request } => format!("  # {}", request),
+            Origin::Edited { request } => format!("  # {} (edited)", request),
+            Origin::Fix { failed } => format!("  # fix for: {}", failed),
+        };
+        println!(
+            "  {}{}{} {}{:>3}{} {}{}{}  {}{}{}{}",
+            COLOR_DIM,
+            history::format_time(entry.time),
+            COLOR_RESET,
+            status,
+            entry.exit_code,
+            COLOR_RESET,
+            COLOR_MAGENTA,
+            display_path(&entry.cwd),
+            COLOR_RESET,
+            entry.command,
+            COLOR_DIM,
+            origin,
+            COLOR_RESET,
+        );
+    }
+    0
 }
 
-fn handle_export(assignment: &str) {
-    if let Some((key, value)) = assignment.split_once('=') {
-        let key = key.trim();
-        let value = value.trim();
-        // Check if single-quoted (literal — no expansion, matching bash behavior)
-        let is_single_quoted =
-            value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'');
-        // Strip surrounding quotes
-        let value = strip_shell_quotes(value);
-        // Expand ~ at start and $VAR/${VAR} references (skip for single-quoted)
-        let value = shellexpand_tilde(&value);
-        let value = if is_single_quoted {
-            value
-        } else {
-            expand_env_vars(&value)
+/// How many history entries `history ask` shows the AI.
+const HISTORY_ASK_CANDIDATES: usize = 40;
+
+/// `history ask QUESTION`: find past commands matching a description. The
+/// history is narrowed down by keyword, then the AI ranks what's left.
+/// Returns the matches, best first, or the exit status on failure.
+fn search_history(question: &str, cwd: &Path, config: &Config) -> Result<Vec<String>, i32> {
+    let entries = config.history.entries();
+    let candidates = history::search(&entries, question, HISTORY_ASK_CANDIDATES);
+    if candidates.is_empty() {
+        eprintln!("{}no history to search yet{}", COLOR_DIM, COLOR_RESET);
+        return Err(1);
+    }
+
+    let mut message = format!("Looking for: {}\n\nCandidates:\n", question);
+    for (i, entry) in candidates.iter().enumerate() {
+        message.push_str(&format!(
+            "{}. [{}, {}] {}",
+            i + 1,
+            history::format_time(entry.time),
+            display_path(&entry.cwd),
+            entry.command
+        ));
+        if let Origin::Ai { request } | Origin::Edited { request } = &entry.origin {
+            message.push_str(&format!(" (generated from: {})", request));
+        }
+        message.push('\n');
+    }
+
+    let _spinner = Spinner::new();
+    let reply = call_ai(config, PromptKind::History, &message, cwd);
+    drop(_spinner);
+
+    let reply = reply.map_err(|e| {
+        report_ai_error(&e);
+        e.exit_status()
+    })?;
+    let mut matches: Vec<String> = Vec::new();
+    for number in reply.split(|c: char| !c.is_ascii_digit()) {
+        let Some(entry) = number
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| candidates.get(n.wrapping_sub(1)))
+        else {
+            continue;
         };
-        env::set_var(key, &value);
-    } else {
-        // `export VAR` without = is a no-op since env is inherited
+        if !matches.contains(&entry.command) {
+            matches.push(entry.command.clone());
+        }
     }
+    if matches.is_empty() {
+        eprintln!("{}nothing in history matches that{}", COLOR_DIM, COLOR_RESET);
+        return Err(1);
+    }
+    Ok(matches)
 }
 
-fn print_history(editor: &DefaultEditor) {
-    for (i, entry) in editor.history().iter().enumerate() {
-        println!("  {}{:4}{} {}", COLOR_DIM, i + 1, COLOR_RESET, entry);
+/// List the matches from `history ask` and let the user pick one.
+fn choose_history_match(matches: &[String]) -> Option<String> {
+    for (i, command) in matches.iter().enumerate() {
+        println!("  {}{}{} {}", COLOR_DIM, i + 1, COLOR_RESET, command);
     }
+    if matches.len() == 1 {
+        eprint!("{}[enter] edit / [s]kip{} ", COLOR_DIM, COLOR_RESET);
+    } else {
+        eprint!(
+            "{}[enter] or 1-{} to edit / [s]kip{} ",
+            COLOR_DIM,
+            matches.len(),
+            COLOR_RESET
+        );
+    }
+    io::stderr().flush().ok();
+
+    let choice = read_single_line().trim().to_lowercase();
+    let index = match choice.as_str() {
+        "" | "e" | "edit" => 0,
+        n => match n.parse::<usize>() {
+            Ok(n) if (1..=matches.len()).contains(&n) => n - 1,
+            _ => {
+                eprintln!("{}skipped{}", COLOR_DIM, COLOR_RESET);
+                return None;
+            }
+        },
+    };
+    matches.get(index).cloned()
 }
 
 fn shellexpand_tilde(path: &str) -> String {
@@ -1038,79 +1606,22 @@ fn shellexpand_tilde(path: &str) -> String {
     path.to_string()
 }
 
-/// Strip surrounding quotes from a string: "foo" → foo, 'foo' → foo
-fn strip_shell_quotes(s: &str) -> String {
-    let s = s.trim();
-    if s.len() >= 2 {
-        if (s.starts_with('"') && s.ends_with('"'))
-            || (s.starts_with('\'') && s.ends_with('\''))
-        {
-            return s[1..s.len() - 1].to_string();
-        }
-    }
-    s.to_string()
-}
-
-/// Expand $VAR and ${VAR} references using the current environment.
-/// Single-quoted strings should NOT be passed through this (caller strips quotes first).
-fn expand_env_vars(s: &str) -> String {
-    let mut result = String::with_capacity(s.len());
-    let mut chars = s.chars().peekable();
-    while let Some(c) = chars.next() {
-        if c == '$' {
-            let mut var_name = String::new();
-            let braced = chars.peek() == Some(&'{');
-            if braced {
-                chars.next(); // consume '{'
-                while let Some(&ch) = chars.peek() {
-                    if ch == '}' {
-                        chars.next();
-                        break;
-                    }
-                    var_name.push(ch);
-                    chars.next();
-                }
-            } else {
-                while let Some(&ch) = chars.peek() {
-                    if ch.is_ascii_alphanumeric() || ch == '_' {
-                        var_name.push(ch);
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-            }
-            if var_name.is_empty() {
-                result.push('$');
-                if braced {
-                    result.push('{');
-                }
-            } else {
-                result.push_str(&env::var(&var_name).unwrap_or_default());
-            }
-        } else {
-            result.push(c);
-        }
-    }
-    result
-}
-
 /// Handle `source`/`.` builtin: run the file's commands in our shell context
 /// so that env changes (export, cd) propagate. Returns the exit code of the
 /// last command executed (matching bash behavior).
 fn handle_source(
     path_arg: &str,
-    cwd: &mut PathBuf,
+    session: &mut BashSession,
     path_commands: &HashSet<String>,
-    claude_available: bool,
+    ai_available: bool,
     config: &Config,
-    editor: Option<&mut DefaultEditor>,
+    editor: Option<&mut LineEditor>,
 ) -> i32 {
     let expanded = shellexpand_tilde(path_arg.trim());
     let file_path = if Path::new(&expanded).is_absolute() {
         PathBuf::from(&expanded)
     } else {
-        cwd.join(&expanded)
+        session.cwd().join(&expanded)
     };
 
     let contents = match fs::read_to_string(&file_path) {
@@ -1133,23 +1644,21 @@ fn handle_source(
     // reborrow on each iteration if we have one.
     match editor {
         Some(ed) => {
-            for line in contents.lines() {
-                let input = line.trim();
-                if input.is_empty() || input.starts_with('#') {
-                    continue;
-                }
-                last_exit =
-                    execute_line(input, cwd, path_commands, claude_available, config, Some(ed));
+            for statement in parse::split_statements(&contents) {
+                last_exit = execute_line(
+                    &statement,
+                    session,
+                    path_commands,
+                    ai_available,
+                    config,
+                    Some(ed),
+                );
             }
         }
         None => {
-            for line in contents.lines() {
-                let input = line.trim();
-                if input.is_empty() || input.starts_with('#') {
-                    continue;
-                }
+            for statement in parse::split_statements(&contents) {
                 last_exit =
-                    execute_line(input, cwd, path_commands, claude_available, config, None);
+                    execute_line(&statement, session, path_commands, ai_available, config, None);
             }
         }
     }
@@ -1161,7 +1670,7 @@ fn is_user_root() -> bool {
     #[cfg(unix)]
     {
         // Use libc geteuid() — works on both Linux and macOS
-        unsafe { geteuid() == 0 }
+        unsafe { libc::geteuid() == 0 }
     }
     #[cfg(not(unix))]
     {
@@ -1169,50 +1678,129 @@ fn is_user_root() -> bool {
     }
 }
 
-#[cfg(unix)]
-unsafe extern "C" {
-    fn geteuid() -> u32;
-}
-
 // ─── Claude Integration ──────────────────────────────────────────────────────
 
-fn call_claude(system_prompt: &str, user_message: &str, cwd: &Path) -> Option<String> {
-    let context = format!(
-        "Current directory: {}\nOS: {}\nShell: claudesh\nUser: {}\n\nUser input: {}",
-        cwd.display(),
+/// The message sent to the AI: where the user is and, for some requests,
+/// what the system is like and what they've run lately, then what they typed.
+fn ai_context(kind: PromptKind, user_message: &str, vars: &template::Vars) -> String {
+    let mut context = format!(
+        "Current directory: {}\nOS: {}\nShell: claudesh\nUser: {}",
+        vars.cwd.display(),
         std::env::consts::OS,
         env::var("USER").unwrap_or_else(|_| "unknown".into()),
-        user_message
     );
+    if let Some(environment) = vars.environment.filter(|e| !e.is_empty()) {
+        context.push('\n');
+        context.push_str(environment);
+    }
+    // Follow-ups like "delete those" only make sense with what came before
+    let transcript = match kind {
+        PromptKind::Generate
+        | PromptKind::Script
+        | PromptKind::Explain
+        | PromptKind::Ask
+        | PromptKind::Fix => vars.transcript.render(),
+        PromptKind::Judgy | PromptKind::History | PromptKind::Hint => String::new(),
+    };
+    if !transcript.is_empty() {
+        context.push_str("\n\nRecent commands, oldest first:\n");
+        context.push_str(&transcript);
+    }
+    format!("{}\n\nUser input: {}", context, user_message)
+}
 
-    let output = Command::new("claude")
-        .arg("--print")
-        .arg("--system-prompt")
-        .arg(system_prompt)
-        .arg(&context)
-        .current_dir(cwd)
-        .stdin(Stdio::null())
-        .output();
+/// The request for `kind` with the system prompt `prompt`. A template prompt
+/// is filled in from `vars` and `input` sent as it is. Otherwise the
+/// personality and project notes are appended to the prompt, and the
+/// environment goes before `input`.
+fn ai_request(kind: PromptKind, prompt: &str, input: &str, vars: &template::Vars) -> ai::Request {
+    if template::is_template(prompt) {
+        let system = template::render(prompt, vars);
+        return ai::Request::new(kind, &system, input.to_string(), input, vars.cwd);
+    }
+    // Fix replies are parsed, so they must keep to the format; history and
+    // hint replies aren't for reading
+    let mut system = match kind {
+        PromptKind::Fix | PromptKind::History | PromptKind::Hint => prompt.to_string(),
+        _ => build_system_prompt(prompt, vars.personality),
+    };
+    if let Some(notes) = vars.project_notes {
+        system.push_str(&format!("\n\nAbout this project:\n{}", notes));
+    }
+    let message = ai_context(kind, input, vars);
+    ai::Request::new(kind, &system, message, input, vars.cwd)
+}
 
-    match output {
-        Ok(out) => {
-            if out.status.success() {
-                let text = String::from_utf8_lossy(&out.stdout).trim().to_string();
-                if text.is_empty() {
-                    None
-                } else {
-                    Some(text)
-                }
-            } else {
-                let err = String::from_utf8_lossy(&out.stderr);
-                eprintln!("{}claude error: {}{}", COLOR_RED, err.trim(), COLOR_RESET);
-                None
-            }
+fn call_ai(
+    config: &Config,
+    kind: PromptKind,
+    user_message: &str,
+    cwd: &Path,
+) -> Result<String, AiError> {
+    let request = config.request(kind, user_message, cwd);
+    let reply = ai::start(&config.ai, request, config.ai_timeout, false).wait(&mut |_| {})?;
+    non_empty_reply(reply)
+}
+
+fn non_empty_reply(reply: String) -> Result<String, AiError> {
+    let reply = reply.trim();
+    if reply.is_empty() {
+        Err(AiError::Failed("AI returned an empty reply".to_string()))
+    } else {
+        Ok(reply.to_string())
+    }
+}
+
+/// Like `call_ai`, but print the reply in green as it arrives, with the
+/// spinner shown only until the first words come in.
+fn stream_ai(
+    config: &Config,
+    kind: PromptKind,
+    user_message: &str,
+    cwd: &Path,
+) -> Result<(), AiError> {
+    let request = config.request(kind, user_message, cwd);
+
+    let mut spinner = Some(Spinner::new());
+    let mut started = false;
+    // Whitespace is held back until more text follows, so the reply is
+    // trimmed at both ends just like a complete one
+    let mut pending = String::new();
+    let result = ai::start(&config.ai, request, config.ai_timeout, true).wait(&mut |chunk| {
+        let chunk = if started { chunk } else { chunk.trim_start() };
+        if chunk.is_empty() {
+            return;
         }
-        Err(e) => {
-            eprintln!("{}failed to run claude: {}{}", COLOR_RED, e, COLOR_RESET);
-            None
+        if !started {
+            spinner.take();
+            print!("{}", COLOR_GREEN);
+            started = true;
+        }
+        let text = chunk.trim_end();
+        if !text.is_empty() {
+            print!("{}{}", pending, text);
+            pending.clear();
+            io::stdout().flush().ok();
         }
+        pending.push_str(&chunk[text.len()..]);
+    });
+    drop(spinner);
+
+    if started {
+        println!("{}", COLOR_RESET);
+    }
+    match result {
+        Ok(_) if !started => Err(AiError::Failed("AI returned an empty reply".to_string())),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Tell the user why an AI request came to nothing.
+fn report_ai_error(e: &AiError) {
+    match e {
+        AiError::Cancelled => eprintln!("{}{}{}", COLOR_DIM, e, COLOR_RESET),
+        _ => eprintln!("{}{}{}", COLOR_RED, e, COLOR_RESET),
     }
 }
 
@@ -1223,9 +1811,7 @@ fn generate_judgy_commentary(
     session_history: &[String],
     cwd: &Path,
     config: &Config,
-) -> Option<String> {
-    let prompt = build_system_prompt(&config.prompt_judgy, &config.personality);
-
+) -> Result<String, AiError> {
     // Build the full session transcript for context
     let mut context = String::from("Session transcript so far:\n");
     for entry in session_history {
@@ -1234,66 +1820,110 @@ fn generate_judgy_commentary(
     }
     context.push_str(&format!("\nThe user just typed: {}", input));
 
-    let result = call_claude(&prompt, &context, cwd);
+    let result = call_ai(config, PromptKind::Judgy, &context, cwd);
     // Ensure we only return a single line
     result.map(|text| {
-        let text = text.trim().to_string();
         // Take only the first sentence/line if the model got verbose
         text.lines().next().unwrap_or(&text).to_string()
     })
 }
 
-/// Execute a command, handling builtins specially
-fn execute_generated_command(
-    cmd: &str,
-    cwd: &mut PathBuf,
-    editor: &mut DefaultEditor,
-    config: &Config,
-) -> i32 {
-    // Handle builtins specially (must affect claudesh's own process)
-    if cmd == "cd" || cmd.starts_with("cd ") {
-        let dir = cmd.strip_prefix("cd").unwrap_or("").trim();
-        return handle_cd(dir, cwd);
-    }
-    if cmd == "export" {
-        // Bare export - run through bash to show all exported vars
-        let result = run_bash(cmd, cwd);
-        if result.exit_code != 0 {
-            offer_error_help(cmd, &result, cwd, editor, config);
-        }
-        return result.exit_code;
-    }
-    if cmd.starts_with("export ") {
-        let assignment = cmd.strip_prefix("export ").unwrap().trim();
-        handle_export(assignment);
-        return 0;
+/// Show the recent commands sent along with AI requests.
+fn print_context(config: &Config) -> i32 {
+    let transcript = config.transcript.lock().unwrap().render();
+    if transcript.is_empty() {
+        eprintln!("{}no commands to send{}", COLOR_DIM, COLOR_RESET);
+    } else {
+        println!("{}", transcript);
     }
-    if cmd == "unset" {
-        // Bare unset - run through bash
-        let result = run_bash(cmd, cwd);
-        if result.exit_code != 0 {
-            offer_error_help(cmd, &result, cwd, editor, config);
+    0
+}
+
+/// Trust the project the shell is in, loading its settings, or stop
+/// trusting it.
+fn set_trust(enable: bool, config: &Config) -> i32 {
+    let result = if enable {
+        config.projects.trust(&config.policy)
+    } else {
+        config.projects.untrust()
+    };
+    match result {
+        Ok(Some(root)) => {
+            let root = display_path(&root);
+            if enable {
+                eprintln!("{}trusting {}; its settings are in use{}", COLOR_DIM, root, COLOR_RESET);
+            } else {
+                eprintln!("{}no longer trusting {}{}", COLOR_DIM, root, COLOR_RESET);
+            }
+            0
+        }
+        Ok(None) => {
+            eprintln!(
+                "{}trust: no .claudesh directory here or above{}",
+                COLOR_RED, COLOR_RESET
+            );
+            1
+        }
+        Err(e) => {
+            eprintln!("{}trust: {}{}", COLOR_RED, e, COLOR_RESET);
+            1
         }
-        return result.exit_code;
     }
-    if cmd.starts_with("unset ") {
-        let name = cmd.strip_prefix("unset ").unwrap().trim();
-        env::remove_var(name);
-        return 0;
+}
+
+/// Run `cmd` in the session and add it to the structured history and the
+/// transcript.
+fn run_recorded(cmd: &str, origin: Origin, session: &mut BashSession, config: &Config) -> RunResult {
+    let cwd = session.cwd().to_path_buf();
+    let started = SystemTime::now();
+    let clock = Instant::now();
+    let result = session.run(cmd);
+    let note = match &origin {
+        Origin::Typed => None,
+        Origin::Ai { request } => Some(format!("generated for: {}", request)),
+        Origin::Edited { request } => Some(format!("generated for: {}, then edited", request)),
+        Origin::Fix { failed } => Some(format!("suggested as a fix for: {}", failed)),
+    };
+    config
+        .transcript
+        .lock()
+        .unwrap()
+        .record(
+            cmd,
+            note,
+            result.exit_code,
+            &result.captured_stdout,
+            &result.captured_stderr,
+        );
+    if config.record_history {
+        config
+            .history
+            .record(cmd, &cwd, started, clock.elapsed(), result.exit_code, origin);
     }
+    result
+}
+
+/// Execute a generated command in the bash session. `source` stays a
+/// claudesh builtin so sourced files may contain plain English too.
+fn execute_generated_command(
+    cmd: &str,
+    origin: Origin,
+    session: &mut BashSession,
+    editor: &mut LineEditor,
+    config: &Config,
+) -> i32 {
     if cmd.starts_with("source ") || cmd.starts_with(". ") {
         let path = cmd
             .strip_prefix("source ")
             .or_else(|| cmd.strip_prefix(". "))
             .unwrap()
             .trim();
-        return handle_source(path, cwd, &HashSet::new(), true, config, Some(editor));
+        return handle_source(path, session, &HashSet::new(), true, config, Some(editor));
     }
 
-    // Regular command - run through bash
-    let result = run_bash(cmd, cwd);
+    let result = run_recorded(cmd, origin, session, config);
     if result.exit_code != 0 {
-        offer_error_help(cmd, &result, cwd, editor, config);
+        offer_error_help(cmd, &result, session, editor, config);
     }
     result.exit_code
 }
@@ -1341,38 +1971,21 @@ fn looks_like_conversation(text: &str) -> bool {
 
 fn handle_natural_language_interactive(
     text: &str,
-    cwd: &mut PathBuf,
-    editor: &mut DefaultEditor,
+    session: &mut BashSession,
+    editor: &mut LineEditor,
     config: &Config,
     yolo: bool,
     session_history: &mut Vec<String>,
     judgy_enabled: bool,
 ) -> i32 {
-    let lower = text.to_lowercase();
-    let is_complex = lower.contains(" and then ")
-        || lower.contains(" step by step")
-        || lower.contains("script")
-        || lower.contains("automate")
-        || lower.contains("set up")
-        || lower.contains("setup")
-        || lower.contains("install and configure")
-        || lower.contains("create a project");
-
-    let base_prompt = if is_complex {
-        &config.prompt_script
-    } else {
-        &config.prompt_generate
-    };
-
-    // Apply personality so conversational responses are in character.
+    // The personality keeps conversational responses in character.
     // Command output itself is unaffected (raw commands only).
-    let prompt = build_system_prompt(base_prompt, &config.personality);
+    let kind = generation_prompt(text);
 
     let _spinner = Spinner::new();
 
     // Run judgy commentary and command generation in parallel if judgy is enabled
     let (judgy_commentary, generated) = if judgy_enabled {
-        let judgy_prompt = build_system_prompt(&config.prompt_judgy, &config.personality);
         let mut judgy_context = String::from("Session transcript so far:\n");
         for entry in &*session_history {
             judgy_context.push_str(entry);
@@ -1380,27 +1993,23 @@ fn handle_natural_language_interactive(
         }
         judgy_context.push_str(&format!("\nThe user just typed: {}", text));
 
-        let cwd_clone = cwd.clone();
-        let cwd_clone2 = cwd.clone();
-        let prompt_clone = prompt.clone();
-        let judgy_prompt_clone = judgy_prompt.clone();
-        let text_str = text.to_string();
-        let judgy_context_clone = judgy_context.clone();
-
-        let judgy_handle = thread::spawn(move || {
-            call_claude(&judgy_prompt_clone, &judgy_context_clone, &cwd_clone)
-        });
-
-        let command_handle = thread::spawn(move || {
-            call_claude(&prompt_clone, &text_str, &cwd_clone2)
-        });
+        let judgy_request = config.request(PromptKind::Judgy, &judgy_context, session.cwd());
+        let command_request = config.request(kind, text, session.cwd());
+        let judgy_pending = ai::start(&config.ai, judgy_request, config.ai_timeout, false);
+        let command_pending = ai::start(&config.ai, command_request, config.ai_timeout, false);
 
-        let judgy_result = judgy_handle.join().ok().flatten();
-        let command_result = command_handle.join().ok().flatten();
+        let command_result = command_pending.wait(&mut |_| {}).and_then(non_empty_reply);
+        // Ctrl-C or a timeout abandons the commentary along with the command
+        let judgy_result = if command_result.is_ok() {
+            judgy_pending.wait(&mut |_| {}).and_then(non_empty_reply).ok()
+        } else {
+            judgy_pending.cancel();
+            None
+        };
 
         (judgy_result, command_result)
     } else {
-        (None, call_claude(&prompt, text, cwd))
+        (None, call_ai(config, kind, text, session.cwd()))
     };
 
     drop(_spinner); // Explicitly stop spinner
@@ -1417,7 +2026,7 @@ fn handle_natural_language_interactive(
     }
 
     match generated {
-        Some(cmd) => {
+        Ok(cmd) => {
             let cmd = strip_code_fences(&cmd);
 
             // Check if this is conversational (not a command)
@@ -1432,87 +2041,270 @@ fn handle_natural_language_interactive(
                 return 0;
             }
 
-            println!(
-                "{}{}>{} {}",
-                COLOR_BOLD, COLOR_CYAN, COLOR_RESET, cmd
-            );
+            let high_risk = print_generated_command(&cmd) == Some(Risk::High);
+            let origin = Origin::Ai {
+                request: text.to_string(),
+            };
 
-            // In yolo mode, execute immediately without confirmation
-            if yolo {
-                editor.add_history_entry(&cmd).ok();
-                return execute_generated_command(&cmd, cwd, editor, config);
+            // Yolo mode and the policy may skip confirmation, but never for
+            // a command that looks destructive
+            match config.decide(&cmd, session.cwd(), yolo) {
+                Verdict::Run if !high_risk => {
+                    editor.add_history_entry(&cmd).ok();
+                    return execute_generated_command(&cmd, origin, session, editor, config);
+                }
+                Verdict::Ask(Some(reason)) => {
+                    eprintln!("{}{}{}", COLOR_DIM, reason, COLOR_RESET);
+                }
+                _ => {}
             }
 
-            eprint!(
-                "{}[enter] run / [e]dit / [s]kip{} ",
-                COLOR_DIM, COLOR_RESET
-            );
+            if high_risk {
+                eprint!(
+                    "{}type yes to run / [e]dit / [s]kip{} ",
+                    COLOR_RED, COLOR_RESET
+                );
+            } else {
+                eprint!(
+                    "{}[enter] run / [e]dit / [s]kip{} ",
+                    COLOR_DIM, COLOR_RESET
+                );
+            }
             io::stderr().flush().ok();
 
             let choice = read_single_line().trim().to_lowercase();
             match choice.as_str() {
-                "" | "r" | "run" | "y" | "yes" => {
+                "yes" => {
+                    editor.add_history_entry(&cmd).ok();
+                    execute_generated_command(&cmd, origin, session, editor, config)
+                }
+                "" | "r" | "run" | "y" if !high_risk => {
                     editor.add_history_entry(&cmd).ok();
-                    execute_generated_command(&cmd, cwd, editor, config)
+                    execute_generated_command(&cmd, origin, session, editor, config)
                 }
-                "e" | "edit" => {
-                    eprint!("{}> {}", COLOR_YELLOW, COLOR_RESET);
-                    io::stderr().flush().ok();
-                    let edited = read_single_line();
-                    let edited = edited.trim();
-                    if !edited.is_empty() {
-                        editor.add_history_entry(edited).ok();
-                        execute_generated_command(edited, cwd, editor, config)
-                    } else {
+                "e" | "edit" => match edit_generated_command(&cmd, session, editor) {
+                    Some(edited) => {
+                        if !confirm_edited(&edited, &cmd, session.cwd(), config, yolo) {
+                            eprintln!("{}skipped{}", COLOR_DIM, COLOR_RESET);
+                            return 0;
+                        }
+                        editor.add_history_entry(&edited).ok();
+                        let origin = if edited == cmd.trim() {
+                            origin
+                        } else {
+                            Origin::Edited {
+                                request: text.to_string(),
+                            }
+                        };
+                        execute_generated_command(&edited, origin, session, editor, config)
+                    }
+                    None => {
+                        eprintln!("{}skipped{}", COLOR_DIM, COLOR_RESET);
                         0
                     }
-                }
+                },
                 _ => {
                     eprintln!("{}skipped{}", COLOR_DIM, COLOR_RESET);
                     0
                 }
             }
         }
-        None => {
-            eprintln!(
-                "{}couldn't generate a command for that{}",
-                COLOR_RED, COLOR_RESET
-            );
-            1
+        Err(e) => {
+            report_ai_error(&e);
+            if let AiError::Failed(_) = e {
+                eprintln!(
+                    "{}couldn't generate a command for that{}",
+                    COLOR_RED, COLOR_RESET
+                );
+            }
+            e.exit_status()
         }
     }
 }
 
-fn explain_command(subject: &str, cwd: &Path, config: &Config) {
-    let prompt = build_system_prompt(&config.prompt_explain, &config.personality);
-
-    let _spinner = Spinner::new();
-    let explanation = call_claude(&prompt, subject, cwd);
-    drop(_spinner);
+/// Let the user edit a generated command before it runs, on a prompt that
+/// starts out with it, or in `$EDITOR` with Ctrl-X Ctrl-E. `None` if they
+/// cleared it or pressed Ctrl-C.
+fn edit_generated_command(
+    cmd: &str,
+    session: &mut BashSession,
+    editor: &mut LineEditor,
+) -> Option<String> {
+    let prompt = format!("{}>{} ", COLOR_YELLOW, COLOR_RESET);
+    let edited = read_statement(editor, &prompt, cmd.trim());
+    let submitted = editor.helper().and_then(|h| h.take_submitted());
+    let mut edited = edited.ok()?;
+    if submitted == Some(Submit::Editor) {
+        edited = edit_in_editor(&edited, session)?;
+        print_generated_command(edited.trim());
+    }
+    let edited = edited.trim();
+    (!edited.is_empty()).then(|| edited.to_string())
+}
 
-    match explanation {
-        Some(text) => {
-            println!("{}{}{}", COLOR_GREEN, text, COLOR_RESET);
+/// Whether an edited generated command may run. The edit is what runs, so it
+/// gets the same checks as the original: one that looks destructive needs
+/// `yes` typed, and a change the policy wants asked about is asked about.
+fn confirm_edited(edited: &str, original: &str, cwd: &Path, config: &Config, yolo: bool) -> bool {
+    let finding = safety::assess(edited).filter(|f| f.risk == Risk::High);
+    let reason = match config.decide(edited, cwd, yolo) {
+        Verdict::Ask(Some(reason)) if edited != original.trim() => Some(reason),
+        _ => None,
+    };
+    if finding.is_none() && reason.is_none() {
+        return true;
+    }
+    if let Some(reason) = reason {
+        eprintln!("{}{}{}", COLOR_DIM, reason, COLOR_RESET);
+    }
+    match finding {
+        Some(finding) => {
+            eprintln!("{}# {}{}", COLOR_RED, finding.reason, COLOR_RESET);
+            confirm_high_risk()
         }
         None => {
-            eprintln!("{}couldn't explain that{}", COLOR_RED, COLOR_RESET);
+            eprint!("{}[enter] run / [s]kip{} ", COLOR_DIM, COLOR_RESET);
+            io::stderr().flush().ok();
+            let choice = read_single_line().trim().to_lowercase();
+            matches!(choice.as_str(), "" | "r" | "run" | "y" | "yes")
         }
     }
 }
 
-fn ask_question(question: &str, cwd: &Path, config: &Config) {
-    let prompt = build_system_prompt(&config.prompt_ask, &config.personality);
+/// Ask for `yes` to be typed before running a command that looks destructive.
+fn confirm_high_risk() -> bool {
+    eprint!("{}type yes to run / [s]kip{} ", COLOR_RED, COLOR_RESET);
+    io::stderr().flush().ok();
+    read_single_line().trim().eq_ignore_ascii_case("yes")
+}
+
+/// Open `text` in the session's `$VISUAL` or `$EDITOR` (vi if neither is
+/// set) and return what was saved. Running it through the session gives
+/// the editor the terminal the way any other command gets it.
+fn edit_in_editor(text: &str, session: &mut BashSession) -> Option<String> {
+    let path = env::temp_dir().join(format!("claudesh-edit-{}.sh", std::process::id()));
+    if let Err(e) = fs::write(&path, format!("{}\n", text.trim_end())) {
+        eprintln!("claudesh: {}: {}", path.display(), e);
+        return None;
+    }
+    let quoted = format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"));
+    let result = session.run(&format!("${{VISUAL:-${{EDITOR:-vi}}}} {}", quoted));
+    let edited = fs::read_to_string(&path);
+    fs::remove_file(&path).ok();
+    if result.exit_code != 0 {
+        eprintln!(
+            "{}editor exited with status {}; nothing run{}",
+            COLOR_RED, result.exit_code, COLOR_RESET
+        );
+        return None;
+    }
+    edited.ok()
+}
+
+/// Which prompt a request for a command goes to: multi-step tasks get a
+/// script, everything else a single command.
+fn generation_prompt(text: &str) -> PromptKind {
+    let lower = text.to_lowercase();
+    let is_complex = lower.contains(" and then ")
+        || lower.contains(" step by step")
+        || lower.contains("script")
+        || lower.contains("automate")
+        || lower.contains("set up")
+        || lower.contains("setup")
+        || lower.contains("install and configure")
+        || lower.contains("create a project");
+
+    if is_complex {
+        PromptKind::Script
+    } else {
+        PromptKind::Generate
+    }
+}
+
+/// A command Alt-Enter put on the prompt in place of a request.
+struct InPlace {
+    request: String,
+    command: String,
+}
 
-    let _spinner = Spinner::new();
-    let answer = call_claude(&prompt, question, cwd);
-    drop(_spinner);
+/// Generate a command for `text` to put on the prompt in its place (Alt-Enter).
+/// A reply that isn't a command is printed instead, and the error is the exit
+/// status to report.
+fn generate_in_place(text: &str, cwd: &Path, config: &Config) -> Result<InPlace, i32> {
+    let kind = generation_prompt(text);
 
-    match answer {
-        Some(text) => {
-            println!("{}{}{}", COLOR_GREEN, text, COLOR_RESET);
+    let spinner = Spinner::new();
+    let generated = call_ai(config, kind, text, cwd);
+    drop(spinner);
+
+    let cmd = match generated {
+        Ok(cmd) => strip_code_fences(&cmd),
+        Err(e) => {
+            report_ai_error(&e);
+            if let AiError::Failed(_) = e {
+                eprintln!(
+                    "{}couldn't generate a command for that{}",
+                    COLOR_RED, COLOR_RESET
+                );
+            }
+            return Err(e.exit_status());
         }
-        None => {
-            eprintln!("{}couldn't answer that{}", COLOR_RED, COLOR_RESET);
+    };
+    if let Some(message) = cmd.strip_prefix("CONVERSATIONAL:") {
+        println!("{}", message.trim());
+        return Err(0);
+    }
+    if looks_like_conversation(&cmd) {
+        println!("{}", cmd);
+        return Err(0);
+    }
+
+    // It won't be shown again before it runs, so say now if it's destructive
+    if let Some(finding) = safety::assess(&cmd) {
+        let color = match finding.risk {
+            Risk::High => COLOR_RED,
+            Risk::Medium => COLOR_YELLOW,
+        };
+        eprintln!("{}# {}{}", color, finding.reason, COLOR_RESET);
+    }
+    Ok(InPlace {
+        request: text.to_string(),
+        command: cmd,
+    })
+}
+
+/// How a line typed at the prompt came about: typed outright, or put there by
+/// Alt-Enter and then run as is or edited.
+fn typed_origin(cmd: &str, generated: Option<InPlace>) -> Origin {
+    match generated {
+        Some(g) if g.command.trim() == cmd.trim() => Origin::Ai { request: g.request },
+        Some(g) => Origin::Edited { request: g.request },
+        None => Origin::Typed,
+    }
+}
+
+fn explain_command(subject: &str, cwd: &Path, config: &Config) -> i32 {
+    match stream_ai(config, PromptKind::Explain, subject, cwd) {
+        Ok(()) => 0,
+        Err(e) => {
+            report_ai_error(&e);
+            if let AiError::Failed(_) = e {
+                eprintln!("{}couldn't explain that{}", COLOR_RED, COLOR_RESET);
+            }
+            e.exit_status()
+        }
+    }
+}
+
+fn ask_question(question: &str, cwd: &Path, config: &Config) -> i32 {
+    match stream_ai(config, PromptKind::Ask, question, cwd) {
+        Ok(()) => 0,
+        Err(e) => {
+            report_ai_error(&e);
+            if let AiError::Failed(_) = e {
+                eprintln!("{}couldn't answer that{}", COLOR_RED, COLOR_RESET);
+            }
+            e.exit_status()
         }
     }
 }
@@ -1521,13 +2313,18 @@ fn ask_question(question: &str, cwd: &Path, config: &Config) {
 fn offer_error_help(
     cmd: &str,
     result: &RunResult,
-    cwd: &Path,
-    editor: &mut DefaultEditor,
+    session: &mut BashSession,
+    editor: &mut LineEditor,
     config: &Config,
 ) {
     let stderr = &result.captured_stderr;
     let exit_code = result.exit_code;
 
+    // Nothing to diagnose when the user pressed Ctrl-C, or Ctrl-Z to suspend
+    if signals::is_user_interrupt(exit_code) || signals::is_stop(exit_code) {
+        return;
+    }
+
     // Quick-detect permission errors
     let is_permission_error = stderr.contains("Permission denied")
         || stderr.contains("permission denied")
@@ -1536,7 +2333,7 @@ fn offer_error_help(
         || stderr.contains("must be root")
         || stderr.contains("Access denied");
 
-    if is_permission_error && !cmd.starts_with("sudo ") {
+    if is_permission_error && config.sudo_retry && !cmd.starts_with("sudo ") {
         eprint!(
             "{}permission denied{} — retry with {}sudo{}? [y/N] ",
             COLOR_RED, COLOR_RESET, COLOR_YELLOW, COLOR_RESET,
@@ -1547,8 +2344,11 @@ fn offer_error_help(
         if choice == "y" || choice == "yes" {
             let sudo_cmd = format!("sudo {}", cmd);
             editor.add_history_entry(&sudo_cmd).ok();
-            let retry = run_bash(&sudo_cmd, cwd);
-            if retry.exit_code != 0 {
+            let origin = Origin::Fix {
+                failed: cmd.to_string(),
+            };
+            let retry = run_recorded(&sudo_cmd, origin, session, config);
+            if retry.exit_code != 0 && config.error_help {
                 eprint!(
                     "{}exit code {}{} — press {}f{} for AI help ",
                     COLOR_RED, retry.exit_code, COLOR_RESET, COLOR_YELLOW, COLOR_RESET
@@ -1556,76 +2356,120 @@ fn offer_error_help(
                 io::stderr().flush().ok();
                 let choice = read_single_line().trim().to_lowercase();
                 if choice == "f" {
-                    do_ai_error_analysis(cmd, &retry.captured_stderr, retry.exit_code, cwd, editor, config);
+                    do_ai_error_analysis(cmd, &retry, session, editor, config);
                 }
             }
             return;
         }
     }
 
+    if !config.error_help {
+        return;
+    }
+    let signal = result
+        .signal_description()
+        .map(|desc| format!(" {}", desc))
+        .unwrap_or_default();
     eprint!(
-        "{}exit {}{}{} — press {}f{} for AI help or enter to continue ",
-        COLOR_DIM, COLOR_RED, exit_code, COLOR_RESET, COLOR_YELLOW, COLOR_RESET
+        "{}exit {}{}{}{} — press {}f{} for AI help or enter to continue ",
+        COLOR_DIM, COLOR_RED, exit_code, signal, COLOR_RESET, COLOR_YELLOW, COLOR_RESET
     );
     io::stderr().flush().ok();
 
     let choice = read_single_line().trim().to_lowercase();
     if choice == "f" || choice == "fix" {
-        do_ai_error_analysis(cmd, stderr, exit_code, cwd, editor, config);
+        do_ai_error_analysis(cmd, result, session, editor, config);
     }
 }
 
 fn do_ai_error_analysis(
     cmd: &str,
-    stderr: &str,
-    exit_code: i32,
-    cwd: &Path,
-    editor: &mut DefaultEditor,
+    result: &RunResult,
+    session: &mut BashSession,
+    editor: &mut LineEditor,
     config: &Config,
 ) {
+    let exit_code = match result.signal_description() {
+        Some(signal) => format!("{} ({})", result.exit_code, signal),
+        None => result.exit_code.to_string(),
+    };
     let error_context = format!(
         "Command: {}\nExit code: {}\nStderr:\n{}",
-        cmd, exit_code, stderr
+        cmd, exit_code, result.captured_stderr
     );
 
-    // Don't apply personality to fix prompt — output must follow strict format for parsing.
-    let prompt = config.prompt_fix.clone();
-
     let _spinner = Spinner::new();
-    let help = call_claude(&prompt, &error_context, cwd);
+    let help = call_ai(config, PromptKind::Fix, &error_context, session.cwd());
     drop(_spinner);
 
-    if let Some(text) = help {
-        let text = strip_code_fences(&text);
-        // Try to split into explanation + suggested command
-        let parts: Vec<&str> = text.splitn(2, "\n\n").collect();
-        if parts.len() == 2 {
-            let explanation = parts[0].trim();
-            let suggested_cmd = parts[1].trim();
-
-            eprintln!("{}{}{}", COLOR_YELLOW, explanation, COLOR_RESET);
-            println!(
-                "{}{}>{} {}",
-                COLOR_BOLD, COLOR_CYAN, COLOR_RESET, suggested_cmd
-            );
-
+    let text = match help {
+        Ok(text) => text,
+        Err(e) => {
+            report_ai_error(&e);
+            return;
+        }
+    };
+    let text = strip_code_fences(&text);
+    // Try to split into explanation + suggested command
+    let parts: Vec<&str> = text.splitn(2, "\n\n").collect();
+    if parts.len() == 2 {
+        let explanation = parts[0].trim();
+        let suggested_cmd = parts[1].trim();
+
+        eprintln!("{}{}{}", COLOR_YELLOW, explanation, COLOR_RESET);
+        let high_risk = print_generated_command(suggested_cmd) == Some(Risk::High);
+
+        if high_risk {
+            eprint!("{}type yes to run / [s]kip{} ", COLOR_RED, COLOR_RESET);
+        } else {
             eprint!(
                 "{}[enter] run / [s]kip{} ",
                 COLOR_DIM, COLOR_RESET
             );
-            io::stderr().flush().ok();
+        }
+        io::stderr().flush().ok();
 
-            let choice = read_single_line().trim().to_lowercase();
-            if choice.is_empty() || choice == "r" || choice == "y" || choice == "run" {
-                editor.add_history_entry(suggested_cmd).ok();
-                run_bash(suggested_cmd, cwd);
-            }
-        } else {
-            eprintln!("{}{}{}", COLOR_YELLOW, text, COLOR_RESET);
+        let choice = read_single_line().trim().to_lowercase();
+        let run = match choice.as_str() {
+            "yes" => true,
+            "" | "r" | "y" | "run" => !high_risk,
+            _ => false,
+        };
+        if run {
+            editor.add_history_entry(suggested_cmd).ok();
+            let origin = Origin::Fix {
+                failed: cmd.to_string(),
+            };
+            run_recorded(suggested_cmd, origin, session, config);
         }
+    } else {
+        eprintln!("{}{}{}", COLOR_YELLOW, text, COLOR_RESET);
     }
 }
 
+/// Show an AI-generated command after the `>` marker, with the reason beside
+/// it when it looks destructive. Returns how risky it is.
+fn print_generated_command(cmd: &str) -> Option<Risk> {
+    let finding = safety::assess(cmd);
+    match &finding {
+        Some(finding) => {
+            let color = match finding.risk {
+                Risk::High => COLOR_RED,
+                Risk::Medium => COLOR_YELLOW,
+            };
+            println!(
+                "{}{}>{} {}  {}# {}{}",
+                COLOR_BOLD, COLOR_CYAN, COLOR_RESET, cmd, color, finding.reason, COLOR_RESET
+            );
+        }
+        None => println!(
+            "{}{}>{} {}",
+            COLOR_BOLD, COLOR_CYAN, COLOR_RESET, cmd
+        ),
+    }
+    finding.map(|f| f.risk)
+}
+
 fn strip_code_fences(s: &str) -> String {
     let s = s.trim();
 
@@ -1703,37 +2547,18 @@ fn read_single_line() -> String {
     line
 }
 
-fn format_prompt(cwd: &Path, is_root: bool, last_exit: i32) -> String {
+/// `path` with the home directory shown as `~`.
+fn display_path(path: &Path) -> String {
     let home = dirs::home_dir().unwrap_or_default();
-    let display_path = if let Ok(relative) = cwd.strip_prefix(&home) {
+    if let Ok(relative) = path.strip_prefix(&home) {
         if relative.as_os_str().is_empty() {
             "~".to_string()
         } else {
             format!("~/{}", relative.display())
         }
     } else {
-        cwd.display().to_string()
-    };
-
-    let sigil = if is_root { "#" } else { ">" };
-
-    // Show last exit code in red if non-zero
-    let status_indicator = if last_exit != 0 {
-        format!(" {}[{}]{}", COLOR_RED, last_exit, COLOR_RESET)
-    } else {
-        String::new()
-    };
-
-    format!(
-        "{}{}{} {}{}{}{} ",
-        COLOR_MAGENTA,
-        display_path,
-        status_indicator,
-        COLOR_CYAN,
-        COLOR_BOLD,
-        sigil,
-        COLOR_RESET,
-    )
+        path.display().to_string()
+    }
 }
 
 fn history_file_path() -> Option<PathBuf> {
@@ -1774,7 +2599,8 @@ fn print_help() {
 
   {b}Usage:{r}
     {g}any command{r}           run it directly via bash
-    {g}plain english{r}         AI generates a command, you confirm
+    {g}plain english{r}         AI generates a command, you confirm (shown in blue as you type)
+    {g}english{r} + {y}Alt-Enter{r}   AI replaces the line with a command to edit, then enter runs it
     {y}! command{r}             force bash execution (bypass heuristic)
     {y}? command{r}             explain what a command does
     {y}?? question{r}           ask the AI anything
@@ -1785,17 +2611,30 @@ fn print_help() {
 
   {b}After AI generates a command:{r}
     {y}enter{r}                 run it
-    {y}e{r}                     edit before running
+    {y}e{r}                     edit before running ({y}Ctrl-X Ctrl-E{r} for $EDITOR)
     {y}s{r} / anything else     skip
 
+  {b}Shell state:{r}
+    commands share one bash session, so {g}cd{r}, {g}export{r}, {g}alias{r}, functions,
+    {g}set -o{r} options and shell variables carry over from line to line
+    {g}cmd &{r}, Ctrl-Z, {g}fg{r}, {g}bg{r}, {g}wait{r}, {g}disown{r} and {g}kill %N{r} work on the session's jobs
+    Tab completes commands, paths, {g}$VARS{r} and {g}cd{r} directories, and uses
+    bash-completion scripts where installed ({g}git{r}, {g}kubectl{r}, {g}cargo{r}, ...)
+    → accepts the dim hint from history, or from the AI with {g}ai_hints{r} on
+
   {b}Builtins:{r}
-    {g}cd{r} {d}[dir]{r}              change directory ({g}cd -{r} for previous)
-    {g}export{r} {d}KEY=VALUE{r}      set environment variable ({d}$VAR{r} expanded)
-    {g}unset{r} {d}VAR{r}             remove environment variable
     {g}source{r} {d}FILE{r}           execute file in current shell context
     {g}history{r}               show command history
+    {g}history{r} {d}--cwd [DIR]{r}   commands run here (or in DIR), with status and origin
+    {g}history{r} {d}--failed{r}      commands that exited non-zero
+    {g}history{r} {d}--ai{r}          commands the AI generated or suggested as fixes
+    {g}history{r} {d}--since WHEN{r}  commands since 30m, 2h, 3d, 1w or 2024-05-01
+    {g}history ask{r} {d}QUESTION{r}  find a past command by description, then edit it
+    {g}jobs{r}                  list background and stopped jobs
     {g}judgy{r} {d}[on|off]{r}        toggle judgy mode (AI commentary on every command)
     {g}yolo{r} {d}[on|off]{r}         toggle yolo mode (skip AI command confirmation)
+    {g}trust{r} {d}[off]{r}           use this project's .claudesh/ settings (or stop)
+    {g}context{r} {d}[clear]{r}       recent commands sent to the AI (or forget them)
     {g}exit{r} {d}[N]{r}              exit with status N (default: last status)
     {g}help{r}                  this message
 
@@ -1811,8 +2650,10 @@ fn print_help() {
     {d}prompts/*.txt{r}          override AI system prompts
     {d}claudeshrc{r}             startup commands (like .bashrc)
     {d}history{r}                command history
+    {d}history.jsonl{r}          structured history (cwd, exit code, duration, origin)
     {d}yolo{r}                   touch to enable yolo mode on startup
     {d}judgy{r}                  touch to enable judgy mode on startup
+    {d}trusted{r}                projects whose .claudesh/ settings are used
 
   {b}Examples:{r}
     {d}$ ls -la{r}                                 {d}# just runs{r}
@@ -0,0 +1,895 @@
+//! Statement splitting for shell source.
+//!
+//! Scripts, sourced files and claudeshrc are executed one statement at a time
+//! so that plain-English lines can sit between shell commands. A statement is
+//! not a line: `if ... fi`, loops, functions, heredocs, quoted strings and
+//! backslash or `|`/`&&` continuations may span several lines. This scanner
+//! tracks just enough bash grammar to find where each complete statement ends.
+
+use std::ops::Range;
+
+/// Split shell source into complete statements, in order. Blank lines and
+/// comment-only lines are dropped. A trailing incomplete statement is still
+/// returned so bash can report the syntax error.
+pub fn split_statements(source: &str) -> Vec<String> {
+    let mut scanner = Scanner::new(source);
+    scanner.scan_list(false);
+
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for end in scanner.boundaries.into_iter().chain([source.len()]) {
+        let statement = source[start..end].trim();
+        start = end;
+        if !statement.is_empty() && !statement.starts_with('#') {
+            statements.push(statement.to_string());
+        }
+    }
+    statements
+}
+
+/// True when the source ends in the middle of a statement: an open quote,
+/// compound command, substitution or heredoc, a loop header still waiting
+/// for its `do`, a function still waiting for its body, or a trailing `|`,
+/// `&&`, `||` or backslash.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    scanner.scan_list(false);
+    scanner.incomplete
+}
+
+/// The top-level lists in a statement that run in the background, as byte
+/// ranges without the `&` that ends them. `a && b & c` yields `a && b`.
+/// Lists whose heredoc bodies follow on later lines are left out.
+pub fn background_lists(statement: &str) -> Vec<Range<usize>> {
+    let mut scanner = Scanner::new(statement);
+    scanner.scan_list(false);
+    scanner
+        .backgrounds
+        .into_iter()
+        .filter_map(|range| {
+            let text = &statement[range.clone()];
+            let start = range.start + (text.len() - text.trim_start().len());
+            let end = range.start + text.trim_end().len();
+            (start < end).then_some(start..end)
+        })
+        .collect()
+}
+
+/// Compound commands that must be closed before a statement can end.
+#[derive(Debug, PartialEq)]
+enum Block {
+    Brace,
+    Paren,
+    If,
+    /// A `for`, `while`, `until` or `select` header, up to its `do`
+    Loop,
+    Do,
+    Case(CaseState),
+    /// A function's name, up to the compound command that is its body
+    Function,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CaseState {
+    /// Between `case` and `in`
+    Subject,
+    /// Reading a pattern list up to `)`
+    Pattern,
+    /// Reading the commands for a pattern, up to `;;`
+    Body,
+}
+
+struct Heredoc {
+    delimiter: Vec<u8>,
+    strip_tabs: bool,
+}
+
+struct Scanner<'a> {
+    src: &'a [u8],
+    pos: usize,
+    /// Heredocs whose bodies start after the current line.
+    heredocs: Vec<Heredoc>,
+    /// Byte offsets just past the newline that ends each top-level statement.
+    boundaries: Vec<usize>,
+    /// Top-level lists ended by a single `&`.
+    backgrounds: Vec<Range<usize>>,
+    incomplete: bool,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(source: &'a str) -> Self {
+        Scanner {
+            src: source.as_bytes(),
+            pos: 0,
+            heredocs: Vec::new(),
+            boundaries: Vec::new(),
+            backgrounds: Vec::new(),
+            incomplete: false,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.src.get(self.pos + offset).copied()
+    }
+
+    fn at(&self, s: &[u8]) -> bool {
+        self.src[self.pos..].starts_with(s)
+    }
+
+    /// Scan a command list. At top level this records statement boundaries;
+    /// when `nested` (inside `$(...)` or `<(...)`) it returns after consuming
+    /// the closing paren.
+    fn scan_list(&mut self, nested: bool) {
+        let mut blocks: Vec<Block> = Vec::new();
+        // The next word is in command position, where keywords are recognized
+        let mut command_start = true;
+        // The line ended with `|`, `&&` or `||`, so the statement goes on
+        let mut continued = false;
+        // The previous word was `function`; the next one is its name
+        let mut expect_fname = false;
+        // The next word is a redirection target or heredoc delimiter
+        let mut redirect_target = false;
+        let mut heredoc_next: Option<bool> = None;
+        // Where the current top-level list began
+        let mut list_start = self.pos;
+
+        loop {
+            let Some(c) = self.peek() else {
+                if nested || !blocks.is_empty() || continued || redirect_target {
+                    self.incomplete = true;
+                }
+                if heredoc_next.is_some() || !self.heredocs.is_empty() {
+                    self.incomplete = true;
+                }
+                return;
+            };
+
+            match c {
+                b' ' | b'\t' => self.pos += 1,
+                b'\n' => {
+                    self.pos += 1;
+                    self.read_heredoc_bodies();
+                    if !nested && blocks.is_empty() && !continued {
+                        self.boundaries.push(self.pos);
+                        list_start = self.pos;
+                    }
+                    command_start = true;
+                }
+                b'#' => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                b'\\' if self.peek_at(1) == Some(b'\n') => self.skip_escape(),
+                b';' => {
+                    self.pos += 1;
+                    let case_end = if self.peek() == Some(b';') {
+                        self.pos += 1;
+                        if self.peek() == Some(b'&') {
+                            self.pos += 1;
+                        }
+                        true
+                    } else if self.peek() == Some(b'&') {
+                        self.pos += 1;
+                        true
+                    } else {
+                        false
+                    };
+                    if case_end {
+                        if let Some(Block::Case(state)) = blocks.last_mut() {
+                            *state = CaseState::Pattern;
+                        }
+                    } else if !nested && blocks.is_empty() {
+                        list_start = self.pos;
+                    }
+                    command_start = true;
+                    continued = false;
+                }
+                b'&' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'&') => {
+                            self.pos += 1;
+                            continued = true;
+                            command_start = true;
+                        }
+                        Some(b'>') => {
+                            // &> and &>> redirect both streams
+                            self.pos += 1;
+                            if self.peek() == Some(b'>') {
+                                self.pos += 1;
+                            }
+                            redirect_target = true;
+                        }
+                        _ => {
+                            let pending = heredoc_next.is_some() || !self.heredocs.is_empty();
+                            if !nested && blocks.is_empty() && !pending {
+                                self.backgrounds.push(list_start..self.pos - 1);
+                            }
+                            if !nested && blocks.is_empty() {
+                                list_start = self.pos;
+                            }
+                            continued = false;
+                            command_start = true;
+                        }
+                    }
+                }
+                b'|' => {
+                    self.pos += 1;
+                    if matches!(self.peek(), Some(b'|') | Some(b'&')) {
+                        self.pos += 1;
+                    }
+                    continued = true;
+                    command_start = true;
+                }
+                b'(' => {
+                    self.pos += 1;
+                    if blocks.last() == Some(&Block::Case(CaseState::Pattern)) {
+                        // Optional leading paren of a case pattern
+                    } else if self.peek() == Some(b'(') {
+                        self.pos += 1;
+                        if blocks.last() == Some(&Block::Function) {
+                            blocks.pop();
+                        }
+                        self.scan_arith();
+                        command_start = false;
+                    } else if let Some(close) = self.empty_parens() {
+                        // The `()` of `name()`: the body may be on a later line
+                        self.pos = close + 1;
+                        if blocks.last() != Some(&Block::Function) {
+                            blocks.push(Block::Function);
+                        }
+                        command_start = true;
+                    } else {
+                        open(&mut blocks, Block::Paren);
+                        command_start = true;
+                    }
+                }
+                b')' => {
+                    self.pos += 1;
+                    match blocks.last_mut() {
+                        Some(Block::Case(state)) if *state == CaseState::Pattern => {
+                            *state = CaseState::Body;
+                        }
+                        Some(Block::Paren) => {
+                            blocks.pop();
+                        }
+                        _ if nested => return,
+                        _ => {}
+                    }
+                    command_start = true;
+                }
+                b'<' | b'>' => {
+                    if self.at(b"<<<") {
+                        self.pos += 3;
+                        redirect_target = true;
+                    } else if self.at(b"<<-") {
+                        self.pos += 3;
+                        heredoc_next = Some(true);
+                    } else if self.at(b"<<") {
+                        self.pos += 2;
+                        heredoc_next = Some(false);
+                    } else {
+                        self.pos += 1;
+                        if self.peek() == Some(b'(') {
+                            // Process substitution: <(...) or >(...)
+                            self.pos += 1;
+                            self.scan_list(true);
+                        } else {
+                            if matches!(self.peek(), Some(b'>') | Some(b'&') | Some(b'|')) {
+                                self.pos += 1;
+                            }
+                            redirect_target = true;
+                        }
+                    }
+                }
+                _ => {
+                    let start = self.pos;
+                    self.read_word();
+                    let word = &self.src[start..self.pos];
+
+                    if let Some(strip_tabs) = heredoc_next.take() {
+                        self.heredocs.push(Heredoc {
+                            delimiter: unquote(word),
+                            strip_tabs,
+                        });
+                        continue;
+                    }
+                    if redirect_target {
+                        redirect_target = false;
+                        continue;
+                    }
+                    continued = false;
+
+                    if let Some(Block::Case(state)) = blocks.last_mut() {
+                        match *state {
+                            CaseState::Subject => {
+                                if word == b"in" {
+                                    *state = CaseState::Pattern;
+                                }
+                                continue;
+                            }
+                            CaseState::Pattern => {
+                                if word == b"esac" {
+                                    blocks.pop();
+                                    command_start = false;
+                                }
+                                continue;
+                            }
+                            CaseState::Body => {
+                                if command_start && word == b"esac" {
+                                    blocks.pop();
+                                    command_start = false;
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+
+                    if expect_fname {
+                        expect_fname = false;
+                        blocks.push(Block::Function);
+                        command_start = true;
+                        continue;
+                    }
+                    if !command_start {
+                        continue;
+                    }
+
+                    match word {
+                        b"if" => open(&mut blocks, Block::If),
+                        b"for" | b"select" => {
+                            open(&mut blocks, Block::Loop);
+                            command_start = false;
+                        }
+                        b"while" | b"until" => open(&mut blocks, Block::Loop),
+                        b"do" => {
+                            if blocks.last() == Some(&Block::Loop) {
+                                blocks.pop();
+                            }
+                            blocks.push(Block::Do);
+                        }
+                        b"{" => open(&mut blocks, Block::Brace),
+                        b"case" => {
+                            open(&mut blocks, Block::Case(CaseState::Subject));
+                            command_start = false;
+                        }
+                        b"fi" | b"done" | b"}" => {
+                            let expected = match word {
+                                b"fi" => Block::If,
+                                b"done" => Block::Do,
+                                _ => Block::Brace,
+                            };
+                            if blocks.last() == Some(&expected) {
+                                blocks.pop();
+                            }
+                            command_start = false;
+                        }
+                        b"then" | b"else" | b"elif" | b"!" | b"time" => {}
+                        b"function" => {
+                            expect_fname = true;
+                            command_start = false;
+                        }
+                        _ if is_assignment(word) => {}
+                        _ => command_start = false,
+                    }
+                }
+            }
+        }
+    }
+
+    /// If only blanks stand between the `(` just read and a `)`, the offset
+    /// of that `)`.
+    fn empty_parens(&self) -> Option<usize> {
+        let blanks = self.src[self.pos..]
+            .iter()
+            .take_while(|&&c| c == b' ' || c == b'\t')
+            .count();
+        (self.peek_at(blanks) == Some(b')')).then_some(self.pos + blanks)
+    }
+
+    /// Read one word, including any quoted strings and substitutions in it.
+    fn read_word(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' | b'\n' | b';' | b'&' | b'|' | b'<' | b'>' | b')' => return,
+                b'(' => {
+                    // Array assignment `a=(...)` and extglob `@(...)` keep
+                    // their parens inside the word
+                    let prev = self.pos.checked_sub(1).map(|i| self.src[i]);
+                    if !matches!(
+                        prev,
+                        Some(b'=') | Some(b'@') | Some(b'!') | Some(b'+') | Some(b'*') | Some(b'?')
+                    ) {
+                        return;
+                    }
+                    self.pos += 1;
+                    self.scan_group();
+                }
+                b'\\' => self.skip_escape(),
+                b'\'' => self.scan_single_quote(),
+                b'"' => self.scan_double_quote(),
+                b'`' => self.scan_backtick(),
+                b'$' => self.scan_dollar(),
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    /// Skip a backslash and the character it escapes. A backslash-newline
+    /// at the very end means the line continues.
+    fn skip_escape(&mut self) {
+        let continues = self.peek_at(1) == Some(b'\n') && self.pos + 2 == self.src.len();
+        if self.pos + 1 >= self.src.len() || continues {
+            self.incomplete = true;
+            self.pos = self.src.len();
+        } else {
+            self.pos += 2;
+        }
+    }
+
+    fn scan_single_quote(&mut self) {
+        self.pos += 1;
+        match self.src[self.pos..].iter().position(|&c| c == b'\'') {
+            Some(offset) => self.pos += offset + 1,
+            None => {
+                self.incomplete = true;
+                self.pos = self.src.len();
+            }
+        }
+    }
+
+    /// `$'...'` strings allow backslash escapes, including `\'`.
+    fn scan_ansi_c_quote(&mut self) {
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            match c {
+                b'\\' => self.skip_escape(),
+                b'\'' => {
+                    self.pos += 1;
+                    return;
+                }
+                _ => self.pos += 1,
+            }
+        }
+        self.incomplete = true;
+    }
+
+    fn scan_double_quote(&mut self) {
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            match c {
+                b'"' => {
+                    self.pos += 1;
+                    return;
+                }
+                b'\\' => self.skip_escape(),
+                b'`' => self.scan_backtick(),
+                b'$' => self.scan_dollar(),
+                _ => self.pos += 1,
+            }
+        }
+        self.incomplete = true;
+    }
+
+    fn scan_backtick(&mut self) {
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            match c {
+                b'`' => {
+                    self.pos += 1;
+                    return;
+                }
+                b'\\' => self.skip_escape(),
+                _ => self.pos += 1,
+            }
+        }
+        self.incomplete = true;
+    }
+
+    /// Handle `$`: command substitution, arithmetic, parameter expansion or
+    /// ANSI-C quoting. A bare `$` is just a character.
+    fn scan_dollar(&mut self) {
+        self.pos += 1;
+        match self.peek() {
+            Some(b'(') if self.peek_at(1) == Some(b'(') => {
+                self.pos += 2;
+                self.scan_arith();
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                self.scan_list(true);
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                self.scan_param();
+            }
+            Some(b'\'') => self.scan_ansi_c_quote(),
+            Some(b'"') => self.scan_double_quote(),
+            _ => {}
+        }
+    }
+
+    fn scan_param(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                b'}' => {
+                    self.pos += 1;
+                    return;
+                }
+                b'\\' => self.skip_escape(),
+                b'\'' => self.scan_single_quote(),
+                b'"' => self.scan_double_quote(),
+                b'`' => self.scan_backtick(),
+                b'$' => self.scan_dollar(),
+                _ => self.pos += 1,
+            }
+        }
+        self.incomplete = true;
+    }
+
+    /// Scan `((...))` or `$((...))` up to the closing `))`.
+    fn scan_arith(&mut self) {
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                b'(' => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                b')' if depth == 0 && self.peek_at(1) == Some(b')') => {
+                    self.pos += 2;
+                    return;
+                }
+                b')' => {
+                    depth -= 1;
+                    self.pos += 1;
+                }
+                b'\\' => self.skip_escape(),
+                b'\'' => self.scan_single_quote(),
+                b'"' => self.scan_double_quote(),
+                b'`' => self.scan_backtick(),
+                b'$' => self.scan_dollar(),
+                _ => self.pos += 1,
+            }
+        }
+        self.incomplete = true;
+    }
+
+    /// Scan a balanced paren group inside a word, up to its closing paren.
+    fn scan_group(&mut self) {
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                b'(' => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                b')' if depth == 0 => {
+                    self.pos += 1;
+                    return;
+                }
+                b')' => {
+                    depth -= 1;
+                    self.pos += 1;
+                }
+                b'\\' => self.skip_escape(),
+                b'\'' => self.scan_single_quote(),
+                b'"' => self.scan_double_quote(),
+                b'`' => self.scan_backtick(),
+                b'$' => self.scan_dollar(),
+                _ => self.pos += 1,
+            }
+        }
+        self.incomplete = true;
+    }
+
+    /// Skip the bodies of heredocs started on the line that just ended.
+    fn read_heredoc_bodies(&mut self) {
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            loop {
+                if self.pos >= self.src.len() {
+                    self.incomplete = true;
+                    return;
+                }
+                let rest = &self.src[self.pos..];
+                let line_len = rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len());
+                let mut line = &rest[..line_len];
+                self.pos += (line_len + 1).min(rest.len());
+                if heredoc.strip_tabs {
+                    while let [b'\t', tail @ ..] = line {
+                        line = tail;
+                    }
+                }
+                if line == heredoc.delimiter.as_slice() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Open a compound command. One that follows a function's name is its body.
+fn open(blocks: &mut Vec<Block>, block: Block) {
+    if blocks.last() == Some(&Block::Function) {
+        blocks.pop();
+    }
+    blocks.push(block);
+}
+
+/// `NAME=value` words keep the following word in command position.
+fn is_assignment(word: &[u8]) -> bool {
+    match word.iter().position(|&c| c == b'=') {
+        Some(eq) if eq > 0 => {
+            let name = word[..eq].strip_suffix(b"+").unwrap_or(&word[..eq]);
+            !name.is_empty()
+                && !name[0].is_ascii_digit()
+                && name.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+        }
+        _ => false,
+    }
+}
+
+/// Heredoc delimiters may be quoted; the body ends at the unquoted word.
+fn unquote(word: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(word.len());
+    let mut iter = word.iter().copied();
+    while let Some(c) = iter.next() {
+        match c {
+            b'\'' | b'"' => {}
+            b'\\' => {
+                if let Some(next) = iter.next() {
+                    out.push(next);
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(source: &str) -> Vec<String> {
+        split_statements(source)
+    }
+
+    #[test]
+    fn splits_simple_lines() {
+        assert_eq!(split("echo a\necho b\n"), ["echo a", "echo b"]);
+        assert_eq!(split("# comment\n\necho a\n"), ["echo a"]);
+    }
+
+    #[test]
+    fn keeps_if_together() {
+        let source = "if true\nthen\n  echo yes\nelse\n  echo no\nfi\necho after\n";
+        assert_eq!(
+            split(source),
+            [
+                "if true\nthen\n  echo yes\nelse\n  echo no\nfi",
+                "echo after"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_loops_together_with_do_on_its_own_line() {
+        for header in [
+            "for f in a b",
+            "for ((i = 0; i < 3; i++))",
+            "while true",
+            "until false",
+            "select f in a b",
+        ] {
+            let source = format!("{}\ndo\n  echo $f\ndone\necho after\n", header);
+            let expected = format!("{}\ndo\n  echo $f\ndone", header);
+            assert_eq!(
+                split(&source),
+                [expected.as_str(), "echo after"],
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_loops_together_with_do_on_the_header_line() {
+        let source = "for f in a b; do\n  echo $f\ndone\nwhile read l; do echo $l; done\n";
+        assert_eq!(
+            split(source),
+            [
+                "for f in a b; do\n  echo $f\ndone",
+                "while read l; do echo $l; done"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_case_together() {
+        let source = "case $x in\n  a|b) echo ab ;;\n  (c)\n    echo c\n    ;;\nesac\necho after\n";
+        assert_eq!(
+            split(source),
+            [
+                "case $x in\n  a|b) echo ab ;;\n  (c)\n    echo c\n    ;;\nesac",
+                "echo after"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_functions_together() {
+        for source in [
+            "f() {\n  echo hi\n}\n",
+            "f()\n{\n  echo hi\n}\n",
+            "f ( )\n{\n  echo hi\n}\n",
+            "function f {\n  echo hi\n}\n",
+            "function f\n{\n  echo hi\n}\n",
+            "function f()\n{\n  echo hi\n}\n",
+            "f()\n(\n  echo hi\n)\n",
+            "f()\nfor x in a; do echo $x; done\n",
+        ] {
+            let mut source = source.to_string();
+            let body = source.trim_end().to_string();
+            source.push_str("echo after\n");
+            assert_eq!(split(&source), [body.as_str(), "echo after"], "{}", body);
+        }
+    }
+
+    #[test]
+    fn keeps_subshells_and_groups_together() {
+        let source = "(\n  cd /tmp\n)\n{\n  echo a\n}\n";
+        assert_eq!(split(source), ["(\n  cd /tmp\n)", "{\n  echo a\n}"]);
+    }
+
+    #[test]
+    fn keeps_heredocs_with_their_command() {
+        let source = "cat <<EOF\nif this\nEOF\ncat <<-'END'\n\tfor\n\tEND\necho after\n";
+        assert_eq!(
+            split(source),
+            [
+                "cat <<EOF\nif this\nEOF",
+                "cat <<-'END'\n\tfor\n\tEND",
+                "echo after"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_continued_lines_together() {
+        let source = "echo a \\\n  b\nls |\n  wc -l\ntrue &&\n  echo ok\n";
+        assert_eq!(
+            split(source),
+            ["echo a \\\n  b", "ls |\n  wc -l", "true &&\n  echo ok"]
+        );
+    }
+
+    #[test]
+    fn keeps_multiline_strings_and_substitutions_together() {
+        let source = "echo 'a\nb'\necho \"$(\n  ls\n)\"\n";
+        assert_eq!(split(source), ["echo 'a\nb'", "echo \"$(\n  ls\n)\""]);
+    }
+
+    #[test]
+    fn keywords_only_count_in_command_position() {
+        assert_eq!(
+            split("echo for if while\necho b\n"),
+            ["echo for if while", "echo b"]
+        );
+    }
+
+    #[test]
+    fn incomplete_after_compound_headers() {
+        for source in [
+            "for x in *",
+            "for x in *\n",
+            "for ((i = 0; i < 3; i++))",
+            "while true",
+            "until false\n",
+            "select x in a b",
+            "for x in *; do",
+            "while true\ndo\n  echo",
+            "if true",
+            "if true; then",
+            "case $x in",
+            "case $x in\n  a)",
+            "f()",
+            "f()\n",
+            "function f",
+            "f() {",
+            "{",
+            "(",
+        ] {
+            assert!(is_incomplete(source), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn incomplete_quotes_heredocs_and_continuations() {
+        for source in [
+            "echo 'a",
+            "echo \"a",
+            "echo $(ls",
+            "echo ${x",
+            "cat <<EOF",
+            "cat <<EOF\nbody",
+            "echo a \\",
+            "ls |",
+            "true &&",
+            "false ||",
+        ] {
+            assert!(is_incomplete(source), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn complete_statements() {
+        for source in [
+            "ls",
+            "echo for",
+            "for x in *; do echo $x; done",
+            "while true\ndo\n  break\ndone",
+            "if true; then echo; fi",
+            "case $x in a) ;; esac",
+            "f() { echo; }",
+            "f()\n{\n  echo\n}",
+            "cat <<EOF\nbody\nEOF",
+            "echo a \\\n  b",
+            "a=(1 2 3)",
+            "echo $((1 + 2))",
+        ] {
+            assert!(!is_incomplete(source), "{:?}", source);
+        }
+    }
+
+    fn backgrounds(statement: &str) -> Vec<&str> {
+        background_lists(statement)
+            .into_iter()
+            .map(|range| &statement[range])
+            .collect()
+    }
+
+    #[test]
+    fn finds_background_lists() {
+        assert_eq!(backgrounds("sleep 10 &"), ["sleep 10"]);
+        assert_eq!(
+            backgrounds("make && make test & tail -f log | grep x &"),
+            ["make && make test", "tail -f log | grep x"]
+        );
+        assert_eq!(backgrounds("cd /tmp; sleep 1 & echo hi"), ["sleep 1"]);
+        assert_eq!(backgrounds("a &\nb&\nc"), ["a", "b"]);
+        assert_eq!(
+            backgrounds("echo $(sleep 1 &) '&' \"&\" &"),
+            ["echo $(sleep 1 &) '&' \"&\""]
+        );
+    }
+
+    #[test]
+    fn ignores_other_ampersands() {
+        for statement in [
+            "true && false",
+            "ls &> out",
+            "ls >&2",
+            "ls |& cat",
+            "case $x in a) echo ;& b) echo ;; esac",
+            "for i in 1 2; do sleep $i & done",
+            "{ sleep 1 & }",
+            "(sleep 1 &)",
+            "f() { sleep 1 & }",
+            "cat <<EOF &\nbody\nEOF",
+            "echo a & # b &",
+        ] {
+            let expected: &[&str] = if statement.starts_with("echo a") {
+                &["echo a"]
+            } else {
+                &[]
+            };
+            assert_eq!(backgrounds(statement), expected, "{:?}", statement);
+        }
+    }
+}
@@ -0,0 +1,508 @@
+//! Which AI-generated commands may run without asking.
+//!
+//! `~/.claudesh/policy.toml` refines yolo mode. Deny rules always ask,
+//! allow rules and (optionally) read-only commands run straight away, and
+//! `[[directory]]` sections change any of that below a path. Whatever the
+//! rules say, a command that writes outside the current project asks first.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::safety::{self, SimpleCommand};
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    allow: Vec<RuleFile>,
+    #[serde(default)]
+    deny: Vec<RuleFile>,
+    #[serde(default)]
+    directory: Vec<DirectoryFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    command: Option<String>,
+    args: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectoryFile {
+    path: String,
+    yolo: Option<bool>,
+    read_only: Option<bool>,
+    #[serde(default)]
+    allow: Vec<RuleFile>,
+    #[serde(default)]
+    deny: Vec<RuleFile>,
+}
+
+#[derive(Default, Clone)]
+pub struct Policy {
+    read_only: bool,
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+    directories: Vec<Directory>,
+}
+
+/// Matches a simple command by program name and by its arguments joined
+/// with spaces.
+#[derive(Clone)]
+struct Rule {
+    command: Option<Pattern>,
+    args: Option<Pattern>,
+}
+
+#[derive(Clone)]
+struct Directory {
+    path: Pattern,
+    yolo: Option<bool>,
+    read_only: Option<bool>,
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+}
+
+/// A glob, where `*` matches anything including `/` and `?` one character,
+/// or a regex written between slashes.
+#[derive(Clone)]
+struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+/// What to do with a generated command.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Run,
+    /// Ask first, with the reason if the policy itself requires it.
+    Ask(Option<String>),
+}
+
+impl Policy {
+    /// Read the policy at `path`. A missing file is an empty policy.
+    pub fn load(path: &Path) -> Result<Policy, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Ok(Policy::default()),
+        };
+        let file: PolicyFile = toml::from_str(&text).map_err(|e| e.to_string())?;
+
+        let mut directories = Vec::new();
+        for dir in file.directory {
+            directories.push(Directory {
+                path: Pattern::glob(&expand_home(&dir.path))?,
+                yolo: dir.yolo,
+                read_only: dir.read_only,
+                allow: rules(dir.allow)?,
+                deny: rules(dir.deny)?,
+            });
+        }
+        Ok(Policy {
+            read_only: file.read_only,
+            allow: rules(file.allow)?,
+            deny: rules(file.deny)?,
+            directories,
+        })
+    }
+
+    /// Add a project's own policy, from `policy.toml` in its `.claudesh/`,
+    /// over this one. Its rules only apply inside `root`, as if they were a
+    /// `[[directory]]` section for it, and its own sections take paths
+    /// relative to `root`.
+    pub fn with_project(&self, path: &Path, root: &Path) -> Result<Policy, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Ok(self.clone()),
+        };
+        let file: PolicyFile = toml::from_str(&text).map_err(|e| e.to_string())?;
+
+        let root = root.to_string_lossy();
+        let mut policy = self.clone();
+        policy.directories.push(Directory {
+            path: Pattern::compile(&root, &format!("^{}$", regex::escape(&root)))?,
+            yolo: None,
+            // Leaving it out keeps the user's setting rather than turning it off
+            read_only: file.read_only.then_some(true),
+            allow: rules(file.allow)?,
+            deny: rules(file.deny)?,
+        });
+        for dir in file.directory {
+            if dir.path.starts_with(['/', '~', '$']) {
+                return Err(format!(
+                    "directory '{}': paths in a project policy are relative to the project",
+                    dir.path
+                ));
+            }
+            let source = format!("{}/{}", root.trim_end_matches('/'), dir.path);
+            let regex = format!(
+                "^{}/{}$",
+                regex::escape(root.trim_end_matches('/')),
+                glob(&dir.path)
+            );
+            policy.directories.push(Directory {
+                path: Pattern::compile(&source, &regex)?,
+                yolo: dir.yolo,
+                read_only: dir.read_only,
+                allow: rules(dir.allow)?,
+                deny: rules(dir.deny)?,
+            });
+        }
+        Ok(policy)
+    }
+
+    /// Decide whether `command`, run in `cwd`, can skip confirmation.
+    /// `yolo` is whether yolo mode is on.
+    pub fn decide(&self, command: &str, cwd: &Path, yolo: bool) -> Verdict {
+        let dirs: Vec<&Directory> = self
+            .directories
+            .iter()
+            .filter(|d| {
+                cwd.ancestors()
+                    .any(|a| d.path.matches(&a.to_string_lossy()))
+            })
+            .collect();
+        // The most specific section is listed last
+        let yolo = dirs.iter().rev().find_map(|d| d.yolo).unwrap_or(yolo);
+        let read_only = dirs
+            .iter()
+            .rev()
+            .find_map(|d| d.read_only)
+            .unwrap_or(self.read_only);
+        let deny: Vec<&Rule> = self
+            .deny
+            .iter()
+            .chain(dirs.iter().flat_map(|d| &d.deny))
+            .collect();
+        let allow: Vec<&Rule> = self
+            .allow
+            .iter()
+            .chain(dirs.iter().flat_map(|d| &d.allow))
+            .collect();
+        let root = project_root(cwd);
+
+        let mut approved = true;
+        for command in safety::pipelines(command).iter().flatten() {
+            if let Some(rule) = deny.iter().find(|r| r.matches(command)) {
+                return Verdict::Ask(Some(format!("denied by policy ({})", rule)));
+            }
+            if let Some(path) = command
+                .writes()
+                .into_iter()
+                .find(|p| !is_inside(p, cwd, &root))
+            {
+                return Verdict::Ask(Some(format!("writes outside the project: {}", path)));
+            }
+            approved &= allow.iter().any(|r| r.matches(command))
+                || (read_only && command.is_read_only() && !command.is_elevated());
+        }
+
+        if yolo || approved {
+            Verdict::Run
+        } else {
+            Verdict::Ask(None)
+        }
+    }
+}
+
+fn rules(files: Vec<RuleFile>) -> Result<Vec<Rule>, String> {
+    files
+        .into_iter()
+        .map(|rule| {
+            if rule.command.is_none() && rule.args.is_none() {
+                return Err("a rule needs `command`, `args` or both".to_string());
+            }
+            Ok(Rule {
+                command: rule.command.as_deref().map(Pattern::new).transpose()?,
+                args: rule.args.as_deref().map(Pattern::new).transpose()?,
+            })
+        })
+        .collect()
+}
+
+impl Rule {
+    fn matches(&self, command: &SimpleCommand) -> bool {
+        self.command
+            .as_ref()
+            .is_none_or(|p| p.matches(command.name()))
+            && self
+                .args
+                .as_ref()
+                .is_none_or(|p| p.matches(&command.args().join(" ")))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.command, &self.args) {
+            (Some(command), Some(args)) => write!(f, "{} {}", command.source, args.source),
+            (Some(command), None) => write!(f, "{}", command.source),
+            (None, Some(args)) => write!(f, "* {}", args.source),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Pattern {
+    fn new(source: &str) -> Result<Pattern, String> {
+        match source
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|s| !s.is_empty())
+        {
+            Some(regex) => Pattern::compile(source, regex),
+            None => Pattern::glob(source),
+        }
+    }
+
+    /// A glob only; directory paths start with a slash too.
+    fn glob(source: &str) -> Result<Pattern, String> {
+        Pattern::compile(source, &format!("^{}$", glob(source)))
+    }
+
+    fn compile(source: &str, regex: &str) -> Result<Pattern, String> {
+        let regex = Regex::new(regex).map_err(|e| format!("bad pattern '{}': {}", source, e))?;
+        Ok(Pattern {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+/// The regex for a glob, unanchored.
+fn glob(source: &str) -> String {
+    let mut regex = String::new();
+    for c in source.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+/// The enclosing git work tree, or `cwd` itself outside one.
+fn project_root(cwd: &Path) -> PathBuf {
+    cwd.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(cwd)
+        .to_path_buf()
+}
+
+/// Whether `path`, as written in a command run from `cwd`, stays inside the
+/// project or a temporary directory. Paths that depend on variables other
+/// than `$HOME` can't be resolved and are given the benefit of the doubt.
+fn is_inside(path: &str, cwd: &Path, root: &Path) -> bool {
+    let path = expand_home(path);
+    if path.contains('$') || path.contains('`') {
+        return true;
+    }
+    let path = normalize(&cwd.join(path));
+    path.starts_with(root)
+        || path.starts_with("/tmp")
+        || path.starts_with(env::temp_dir())
+        || matches!(
+            path.to_str(),
+            Some("/dev/null" | "/dev/stdout" | "/dev/stderr")
+        )
+}
+
+fn expand_home(path: &str) -> String {
+    let Some(home) = dirs::home_dir() else {
+        return path.to_string();
+    };
+    for prefix in ["~", "$HOME", "${HOME}"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            if rest.is_empty() || rest.starts_with('/') {
+                return format!("{}{}", home.display(), rest);
+            }
+        }
+    }
+    path.to_string()
+}
+
+/// Resolve `.` and `..` without touching the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir => {}
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A policy loaded from `text`, written out as `name`.
+    fn policy(name: &str, text: &str) -> Policy {
+        let path = env::temp_dir().join(format!(
+            "claudesh-policy-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, text).unwrap();
+        let policy = Policy::load(&path);
+        fs::remove_file(&path).unwrap();
+        policy.unwrap()
+    }
+
+    fn decide(policy: &Policy, command: &str, cwd: &str, yolo: bool) -> Verdict {
+        policy.decide(command, Path::new(cwd), yolo)
+    }
+
+    #[test]
+    fn without_rules_only_yolo_runs() {
+        let policy = Policy::default();
+        assert_eq!(
+            decide(&policy, "touch x", "/work/proj", false),
+            Verdict::Ask(None)
+        );
+        assert_eq!(decide(&policy, "touch x", "/work/proj", true), Verdict::Run);
+    }
+
+    #[test]
+    fn read_only_commands() {
+        let policy = policy("read-only", "read_only = true");
+        assert_eq!(
+            decide(&policy, "ls -la | wc -l", "/work/proj", false),
+            Verdict::Run
+        );
+        assert_eq!(
+            decide(&policy, "ls > listing", "/work/proj", false),
+            Verdict::Ask(None)
+        );
+        assert_eq!(
+            decide(&policy, "sudo cat /etc/shadow", "/work/proj", false),
+            Verdict::Ask(None)
+        );
+    }
+
+    #[test]
+    fn allow_and_deny_rules() {
+        let policy = policy(
+            "rules",
+            r#"
+            [[allow]]
+            command = "make"
+
+            [[deny]]
+            command = "git"
+            args = "push*"
+            "#,
+        );
+        assert_eq!(
+            decide(&policy, "make test", "/work/proj", false),
+            Verdict::Run
+        );
+        assert_eq!(
+            decide(&policy, "make && git push origin", "/work/proj", true),
+            Verdict::Ask(Some("denied by policy (git push*)".to_string()))
+        );
+        assert_eq!(
+            decide(&policy, "git status", "/work/proj", true),
+            Verdict::Run
+        );
+    }
+
+    #[test]
+    fn writes_outside_the_project_always_ask() {
+        let policy = Policy::default();
+        assert_eq!(
+            decide(&policy, "echo x > ../other/file", "/work/proj", true),
+            Verdict::Ask(Some(
+                "writes outside the project: ../other/file".to_string()
+            ))
+        );
+        assert_eq!(
+            decide(&policy, "echo x > out/file", "/work/proj", true),
+            Verdict::Run
+        );
+    }
+
+    #[test]
+    fn directory_sections() {
+        let policy = policy(
+            "directories",
+            r#"
+            [[directory]]
+            path = "/work/*"
+            yolo = true
+
+            [[directory]]
+            path = "/work/prod"
+            yolo = false
+            "#,
+        );
+        assert_eq!(
+            decide(&policy, "touch x", "/work/proj", false),
+            Verdict::Run
+        );
+        assert_eq!(
+            decide(&policy, "touch x", "/work/proj/sub", false),
+            Verdict::Run
+        );
+        assert_eq!(
+            decide(&policy, "touch x", "/work/prod", false),
+            Verdict::Ask(None)
+        );
+        assert_eq!(
+            decide(&policy, "touch x", "/elsewhere", false),
+            Verdict::Ask(None)
+        );
+    }
+
+    #[test]
+    fn bad_policies_are_reported() {
+        let path = env::temp_dir().join(format!("claudesh-policy-{}-bad.toml", std::process::id()));
+        fs::write(&path, "[[allow]]\n").unwrap();
+        let result = Policy::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn paths_inside_the_project() {
+        let cwd = Path::new("/work/proj/src");
+        let root = Path::new("/work/proj");
+        for path in [
+            "main.rs",
+            "../Cargo.toml",
+            "/work/proj/out",
+            "/tmp/x",
+            "/dev/null",
+            "$OUT/x",
+        ] {
+            assert!(is_inside(path, cwd, root), "{}", path);
+        }
+        for path in ["../../other", "/etc/hosts", "/work/proj/../other"] {
+            assert!(!is_inside(path, cwd, root), "{}", path);
+        }
+    }
+
+    #[test]
+    fn normalize_resolves_dots() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+        assert_eq!(normalize(Path::new("/a/../../b")), PathBuf::from("/b"));
+    }
+}
@@ -0,0 +1,321 @@
+//! Project settings: a `.claudesh/` directory in the current directory or
+//! one above it, applied over the user's own while inside it.
+//!
+//! A project can bring its own prompts (`prompts/*.txt` and `personality`),
+//! notes for the AI about how it works (`context`), and a `policy.toml`.
+//! Since those steer what the AI suggests and what runs without asking,
+//! nothing is loaded until the directory has been trusted with the `trust`
+//! builtin. Trusted directories are listed in `~/.claudesh/trusted`, each with
+//! a fingerprint of its settings; when they change, say with a `git pull`,
+//! the directory has to be trusted again.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::policy::Policy;
+
+/// Prompts a project can override, as named in `prompts/`.
+const PROMPTS: &[&str] = &[
+    "generate", "script", "explain", "ask", "fix", "judgy", "history", "hint",
+];
+
+pub struct Project {
+    /// Prompt overrides by name, `personality` included.
+    prompts: HashMap<&'static str, String>,
+    /// What the project says about itself, for the AI.
+    pub notes: Option<String>,
+    /// The user's policy with the project's on top.
+    pub policy: Option<Policy>,
+}
+
+impl Project {
+    /// Load the settings under `root`, with the project's policy merged over
+    /// `policy`. Files that exist but can't be used are reported.
+    pub fn load(root: &Path, policy: &Policy) -> Project {
+        let dir = root.join(".claudesh");
+        let read = |path: PathBuf| match fs::read_to_string(&path) {
+            Ok(text) => Some(text.trim().to_string()).filter(|t| !t.is_empty()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                eprintln!("claudesh: {}: {}", path.display(), e);
+                None
+            }
+        };
+
+        let mut prompts = HashMap::new();
+        for &name in PROMPTS {
+            let path = dir.join("prompts").join(format!("{}.txt", name));
+            if let Some(text) = read(path.clone()) {
+                if let Err(e) = crate::template::check(&text) {
+                    eprintln!("claudesh: {}: {}", path.display(), e);
+                }
+                prompts.insert(name, text);
+            }
+        }
+        if let Some(text) = read(dir.join("personality")) {
+            prompts.insert("personality", text);
+        }
+
+        let policy_path = dir.join("policy.toml");
+        let policy = policy_path.exists().then(|| {
+            policy.with_project(&policy_path, root).unwrap_or_else(|e| {
+                eprintln!("claudesh: {}: {}", policy_path.display(), e);
+                policy.clone()
+            })
+        });
+
+        Project {
+            prompts,
+            notes: read(dir.join("context")),
+            policy,
+        }
+    }
+
+    /// The project's own version of the prompt `name`, if it has one.
+    pub fn prompt(&self, name: &str) -> Option<&str> {
+        self.prompts.get(name).map(String::as_str)
+    }
+}
+
+/// Follows the shell from directory to directory, keeping the settings of
+/// the trusted project it's in loaded.
+pub struct Projects {
+    config_dir: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Where the shell was when last looked at.
+    cwd: Option<PathBuf>,
+    /// The project there, trusted or not.
+    root: Option<PathBuf>,
+    current: Option<Arc<Project>>,
+    /// Untrusted projects already pointed out this session.
+    announced: HashSet<PathBuf>,
+}
+
+/// A project whose settings aren't in use, to point out.
+pub enum Untrusted {
+    /// Never trusted.
+    New(PathBuf),
+    /// Trusted once, but its settings have changed since.
+    Changed(PathBuf),
+}
+
+impl Projects {
+    pub fn new(config_dir: &Path) -> Self {
+        Projects {
+            config_dir: config_dir.to_path_buf(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// The settings of the project the shell is in, if it's trusted.
+    pub fn current(&self) -> Option<Arc<Project>> {
+        self.state.lock().unwrap().current.clone()
+    }
+
+    /// Catch up with the shell moving to `cwd`, loading the project there if
+    /// it's trusted. An untrusted project is returned the first time it's
+    /// entered, so it can be pointed out.
+    pub fn enter(&self, cwd: &Path, policy: &Policy) -> Option<Untrusted> {
+        let mut state = self.state.lock().unwrap();
+        if state.cwd.as_deref() == Some(cwd) {
+            return None;
+        }
+        state.cwd = Some(cwd.to_path_buf());
+        let root = find(cwd, &self.config_dir);
+        if root == state.root {
+            return None;
+        }
+        state.root.clone_from(&root);
+        state.current = None;
+
+        let root = root?;
+        let recorded = self.fingerprint_when_trusted(&root);
+        if recorded.as_deref() == Some(fingerprint(&root).as_str()) {
+            state.current = Some(Arc::new(Project::load(&root, policy)));
+            None
+        } else if !state.announced.insert(root.clone()) {
+            None
+        } else if recorded.is_some() {
+            Some(Untrusted::Changed(root))
+        } else {
+            Some(Untrusted::New(root))
+        }
+    }
+
+    /// Trust the project the shell is in from now on, and load it. Returns
+    /// its root, or `None` outside a project.
+    pub fn trust(&self, policy: &Policy) -> io::Result<Option<PathBuf>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(root) = state.root.clone() else {
+            return Ok(None);
+        };
+        self.set_trusted(&root, Some(&fingerprint(&root)))?;
+        // So that it's pointed out if it changes
+        state.announced.remove(&root);
+        state.current = Some(Arc::new(Project::load(&root, policy)));
+        Ok(Some(root))
+    }
+
+    /// Stop trusting the project the shell is in, and drop its settings.
+    /// Returns its root, or `None` outside a project.
+    pub fn untrust(&self) -> io::Result<Option<PathBuf>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(root) = state.root.clone() else {
+            return Ok(None);
+        };
+        state.current = None;
+        self.set_trusted(&root, None)?;
+        Ok(Some(root))
+    }
+
+    /// The fingerprint `root`'s settings had when it was trusted, or `None`
+    /// if it isn't.
+    fn fingerprint_when_trusted(&self, root: &Path) -> Option<String> {
+        let text = fs::read_to_string(self.trusted_path()).ok()?;
+        text.lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(_, path)| Path::new(path) == root)
+            .map(|(fingerprint, _)| fingerprint.to_string())
+    }
+
+    /// Record `root` as trusted with its settings' `fingerprint`, replacing
+    /// any earlier record, or with `None` drop it.
+    fn set_trusted(&self, root: &Path, fingerprint: Option<&str>) -> io::Result<()> {
+        let text = match fs::read_to_string(self.trusted_path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut kept: String = text
+            .lines()
+            .filter(|line| {
+                line.split_once(' ')
+                    .is_none_or(|(_, path)| Path::new(path) != root)
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+        if let Some(fingerprint) = fingerprint {
+            kept.push_str(&format!("{} {}\n", fingerprint, root.display()));
+        }
+        fs::write(self.trusted_path(), kept)
+    }
+
+    fn trusted_path(&self) -> PathBuf {
+        self.config_dir.join("trusted")
+    }
+}
+
+/// A fingerprint of every settings file under `root`, whether it's there and
+/// what's in it. This is FNV-1a, which unlike the standard library's hasher
+/// stays the same from one build to the next.
+fn fingerprint(root: &Path) -> String {
+    let dir = root.join(".claudesh");
+    let mut names: Vec<String> = PROMPTS
+        .iter()
+        .map(|name| format!("prompts/{}.txt", name))
+        .collect();
+    names.extend(["personality", "context", "policy.toml"].map(String::from));
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for name in names {
+        feed(name.as_bytes());
+        match fs::read(dir.join(&name)) {
+            Ok(content) => {
+                feed(&(content.len() as u64 + 1).to_le_bytes());
+                feed(&content);
+            }
+            Err(_) => feed(&0u64.to_le_bytes()),
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// The nearest directory at or above `cwd` with a `.claudesh/` in it. The
+/// user's own `config_dir` doesn't count.
+fn find(cwd: &Path, config_dir: &Path) -> Option<PathBuf> {
+    cwd.ancestors()
+        .find(|dir| {
+            let settings = dir.join(".claudesh");
+            settings != config_dir && settings.is_dir()
+        })
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_settings_need_trusting_again() {
+        let dir = std::env::temp_dir().join(format!("claudesh-project-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (home, root) = (dir.join("home"), dir.join("proj"));
+        fs::create_dir_all(root.join(".claudesh/prompts")).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        fs::write(root.join(".claudesh/context"), "Uses make.").unwrap();
+        let policy = Policy::default();
+        let sub = root.join("src");
+
+        let projects = Projects::new(&home);
+        assert!(matches!(projects.enter(&sub, &policy), Some(Untrusted::New(r)) if r == root));
+        assert!(projects.current().is_none());
+        projects.trust(&policy).unwrap();
+        assert_eq!(
+            projects.current().unwrap().notes.as_deref(),
+            Some("Uses make.")
+        );
+
+        // A new session in the same project picks the trust up
+        let other = Projects::new(&home);
+        assert!(other.enter(&sub, &policy).is_none());
+        assert!(other.current().is_some());
+
+        // Until a settings file changes or appears
+        fs::write(root.join(".claudesh/policy.toml"), "read_only = true").unwrap();
+        assert!(projects.enter(&dir, &policy).is_none());
+        assert!(matches!(projects.enter(&sub, &policy), Some(Untrusted::Changed(r)) if r == root));
+        assert!(projects.current().is_none());
+        projects.trust(&policy).unwrap();
+        let trusted = fs::read_to_string(home.join("trusted")).unwrap();
+        assert_eq!(trusted.lines().count(), 1);
+
+        projects.untrust().unwrap();
+        let projects = Projects::new(&home);
+        assert!(matches!(
+            projects.enter(&sub, &policy),
+            Some(Untrusted::New(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprints_follow_contents() {
+        let root =
+            std::env::temp_dir().join(format!("claudesh-fingerprint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".claudesh")).unwrap();
+        let empty = fingerprint(&root);
+        fs::write(root.join(".claudesh/context"), "").unwrap();
+        let blank = fingerprint(&root);
+        fs::write(root.join(".claudesh/context"), "notes").unwrap();
+        let notes = fingerprint(&root);
+        fs::write(root.join(".claudesh/unrelated"), "x").unwrap();
+        assert_eq!(fingerprint(&root), notes);
+        assert_ne!(empty, blank);
+        assert_ne!(blank, notes);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
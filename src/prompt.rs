@@ -0,0 +1,551 @@
+//! The interactive prompt, drawn from a template.
+//!
+//! A template is text with `{name}` placeholders, set as `prompt` and
+//! `right_prompt` in the `[ui]` section of config.toml. `{name:format}`
+//! shows `format` with `%` replaced by the value, or nothing at all when
+//! the value is empty, so a separator disappears along with what it
+//! separates. Formats may contain colors, e.g. `{git: on {yellow}%{reset}}`.
+//! `{{` and `}}` are literal braces.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::Chars;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    COLOR_BLUE, COLOR_BOLD, COLOR_CYAN, COLOR_DIM, COLOR_GREEN, COLOR_MAGENTA, COLOR_RED,
+    COLOR_RESET, COLOR_YELLOW,
+};
+
+/// The prompt claudesh has always had: `~/projects [1] >`.
+pub const DEFAULT_PROMPT: &str =
+    "{magenta}{cwd}{reset}{red}{status: [%]}{reset} {bold}{cyan}{sigil}{reset} ";
+
+/// Commands quicker than this leave `{duration}` empty.
+const MIN_DURATION: Duration = Duration::from_secs(2);
+
+/// How long the prompt waits on `git status`.
+const GIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+const COLORS: &[(&str, &str)] = &[
+    ("reset", COLOR_RESET),
+    ("bold", COLOR_BOLD),
+    ("dim", COLOR_DIM),
+    ("red", COLOR_RED),
+    ("green", COLOR_GREEN),
+    ("yellow", COLOR_YELLOW),
+    ("blue", COLOR_BLUE),
+    ("magenta", COLOR_MAGENTA),
+    ("cyan", COLOR_CYAN),
+];
+
+/// Placeholders that stand for a value.
+const VALUES: &[&str] = &[
+    "cwd",
+    "cwd_short",
+    "dir",
+    "git",
+    "git_branch",
+    "git_dirty",
+    "status",
+    "duration",
+    "user",
+    "host",
+    "sigil",
+    "yolo",
+    "judgy",
+    "venv",
+];
+
+/// What the prompt can show.
+pub struct Facts<'a> {
+    pub cwd: &'a Path,
+    pub is_root: bool,
+    pub last_exit: i32,
+    /// How long the last command took.
+    pub duration: Duration,
+    pub yolo: bool,
+    pub judgy: bool,
+}
+
+pub struct PromptFormat {
+    left: Template,
+    right: Template,
+    /// Repositories where `git status` was too slow or failed. They show
+    /// their branch alone from then on.
+    no_status: Mutex<HashSet<PathBuf>>,
+}
+
+impl Default for PromptFormat {
+    fn default() -> Self {
+        PromptFormat::new(DEFAULT_PROMPT, "").expect("default prompt")
+    }
+}
+
+impl PromptFormat {
+    pub fn new(left: &str, right: &str) -> Result<Self, String> {
+        Ok(PromptFormat {
+            left: Template::parse(left).map_err(|e| format!("prompt: {}", e))?,
+            right: Template::parse(right).map_err(|e| format!("right_prompt: {}", e))?,
+            no_status: Mutex::default(),
+        })
+    }
+
+    /// The left and right prompts. The right one is empty if there isn't one.
+    pub fn render(&self, facts: &Facts) -> (String, String) {
+        let mut values = Values {
+            facts,
+            no_status: &self.no_status,
+            git: None,
+        };
+        let left = self.left.render(&mut values);
+        let right = self.right.render(&mut values);
+        (left, right)
+    }
+}
+
+enum Piece {
+    Text(String),
+    Color(&'static str),
+    Value {
+        name: String,
+        format: Option<Vec<Piece>>,
+    },
+}
+
+struct Template(Vec<Piece>);
+
+impl Template {
+    fn parse(text: &str) -> Result<Template, String> {
+        parse_pieces(&mut text.chars().peekable(), false).map(Template)
+    }
+
+    fn render(&self, values: &mut Values) -> String {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Color(code) => out.push_str(code),
+                Piece::Value { name, format } => {
+                    let value = values.get(name);
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let Some(format) = format else {
+                        out.push_str(&value);
+                        continue;
+                    };
+                    for piece in format {
+                        match piece {
+                            Piece::Text(text) => out.push_str(&text.replace('%', &value)),
+                            Piece::Color(code) => out.push_str(code),
+                            Piece::Value { .. } => {}
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Parse up to the end of `chars`, or inside a format up to its closing `}`.
+fn parse_pieces(chars: &mut Peekable<Chars>, in_format: bool) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'{').is_some() => text.push('{'),
+            '}' if chars.next_if_eq(&'}').is_some() => text.push('}'),
+            '}' if in_format => {
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(text));
+                }
+                return Ok(pieces);
+            }
+            '}' => return Err("unmatched }".to_string()),
+            '{' => {
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                let format = match chars.next() {
+                    Some('}') => None,
+                    Some(':') if !in_format => Some(parse_pieces(chars, true)?),
+                    _ => return Err(format!("unclosed {{{}", name)),
+                };
+                pieces.push(placeholder(name, format, in_format)?);
+            }
+            _ => text.push(c),
+        }
+    }
+    if in_format {
+        return Err("unclosed {".to_string());
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+/// The escape sequence for the color `name`, one of those templates take.
+pub fn color(name: &str) -> Option<&'static str> {
+    COLORS
+        .iter()
+        .find(|(color, _)| *color == name)
+        .map(|&(_, code)| code)
+}
+
+fn placeholder(name: String, format: Option<Vec<Piece>>, in_format: bool) -> Result<Piece, String> {
+    if let Some(code) = color(&name) {
+        if format.is_some() {
+            return Err(format!("{{{}}} doesn't take a format", name));
+        }
+        return Ok(Piece::Color(code));
+    }
+    if !VALUES.contains(&name.as_str()) {
+        return Err(format!("unknown placeholder {{{}}}", name));
+    }
+    if in_format {
+        return Err(format!("only colors can go in a format, not {{{}}}", name));
+    }
+    Ok(Piece::Value { name, format })
+}
+
+/// Values for one rendering, with the git state looked up once at most.
+struct Values<'a> {
+    facts: &'a Facts<'a>,
+    no_status: &'a Mutex<HashSet<PathBuf>>,
+    git: Option<Option<Git>>,
+}
+
+impl Values<'_> {
+    fn get(&mut self, name: &str) -> String {
+        let facts = self.facts;
+        match name {
+            "cwd" => crate::display_path(facts.cwd),
+            "cwd_short" => abbreviate(&crate::display_path(facts.cwd)),
+            "dir" => {
+                let path = crate::display_path(facts.cwd);
+                match path.rsplit('/').next() {
+                    Some("") | None => path,
+                    Some(dir) => dir.to_string(),
+                }
+            }
+            "git" | "git_branch" | "git_dirty" => {
+                let no_status = self.no_status;
+                let git = self
+                    .git
+                    .get_or_insert_with(|| git_state(facts.cwd, no_status));
+                let Some(git) = git else {
+                    return String::new();
+                };
+                let dirty = if git.dirty { "*" } else { "" };
+                match name {
+                    "git" => format!("{}{}", git.branch, dirty),
+                    "git_branch" => git.branch.clone(),
+                    _ => dirty.to_string(),
+                }
+            }
+            "status" => {
+                match crate::signals::from_status(facts.last_exit).and_then(crate::signals::name) {
+                    _ if facts.last_exit == 0 => String::new(),
+                    Some(signal) => format!("{} {}", facts.last_exit, signal),
+                    None => facts.last_exit.to_string(),
+                }
+            }
+            "duration" if facts.duration >= MIN_DURATION => format_duration(facts.duration),
+            "user" => env::var("USER").unwrap_or_else(|_| username()),
+            "host" => hostname(),
+            "sigil" => if facts.is_root { "#" } else { ">" }.to_string(),
+            "yolo" if facts.yolo => "yolo".to_string(),
+            "judgy" if facts.judgy => "judgy".to_string(),
+            "venv" => env::var("VIRTUAL_ENV")
+                .ok()
+                .and_then(|path| Some(Path::new(&path).file_name()?.to_string_lossy().into()))
+                .or_else(|| env::var("CONDA_DEFAULT_ENV").ok())
+                .unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// `~/projects/claudesh/src` as `~/p/c/src`, the way fish shortens it.
+fn abbreviate(path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').collect();
+    let last = parts.len() - 1;
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let keep = if part.starts_with('.') { 2 } else { 1 };
+            if i == last {
+                part.to_string()
+            } else {
+                part.chars().take(keep).collect()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `1m12s` and the like.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 60 * 60 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// The effective user's login name, for when `$USER` isn't set.
+fn username() -> String {
+    // SAFETY: getpwuid returns null or a record that stays valid until the
+    // next call, and the name is copied out before returning.
+    unsafe {
+        let passwd = libc::getpwuid(libc::geteuid());
+        if passwd.is_null() {
+            return String::new();
+        }
+        std::ffi::CStr::from_ptr((*passwd).pw_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// The host name up to its first dot.
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::new();
+    }
+    let name = buf.split(|&b| b == 0).next().unwrap_or_default();
+    let name = String::from_utf8_lossy(name);
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+struct Git {
+    branch: String,
+    dirty: bool,
+}
+
+/// The branch checked out in the repository containing `cwd`, and whether
+/// `git status` shows any changes.
+fn git_state(cwd: &Path, no_status: &Mutex<HashSet<PathBuf>>) -> Option<Git> {
+    let (root, git_dir) = find_git_dir(cwd)?;
+    let branch = head_branch(&git_dir)?;
+
+    let mut no_status = no_status.lock().unwrap();
+    let dirty = if no_status.contains(&root) {
+        false
+    } else {
+        git_dirty(&root).unwrap_or_else(|| {
+            no_status.insert(root);
+            false
+        })
+    };
+    Some(Git { branch, dirty })
+}
+
+/// The branch checked out in the repository containing `cwd`, or the
+/// commit when detached.
+pub fn git_branch(cwd: &Path) -> Option<String> {
+    head_branch(&find_git_dir(cwd)?.1)
+}
+
+/// The branch `HEAD` points at, read straight from the file.
+fn head_branch(git_dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    Some(match head.strip_prefix("ref: ") {
+        Some(head) => head.strip_prefix("refs/heads/").unwrap_or(head).to_string(),
+        // Detached
+        None => head.chars().take(7).collect(),
+    })
+}
+
+/// The working tree root and git directory for `cwd`. In a worktree or a
+/// submodule `.git` is a file naming the git directory.
+pub fn find_git_dir(cwd: &Path) -> Option<(PathBuf, PathBuf)> {
+    for dir in cwd.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some((dir.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let text = fs::read_to_string(&dot_git).ok()?;
+            let git_dir = text.trim().strip_prefix("gitdir: ")?;
+            return Some((dir.to_path_buf(), dir.join(git_dir)));
+        }
+    }
+    None
+}
+
+/// Whether the working tree at `root` has changes, or `None` if git failed
+/// or took longer than `GIT_TIMEOUT`.
+fn git_dirty(root: &Path) -> Option<bool> {
+    let child = Command::new("git")
+        .args(["--no-optional-locks", "status", "--porcelain"])
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let pid = child.id();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(child.wait_with_output()).ok());
+
+    match receiver.recv_timeout(GIT_TIMEOUT) {
+        Ok(Ok(output)) if output.status.success() => Some(!output.stdout.is_empty()),
+        Ok(_) => None,
+        Err(_) => {
+            // SAFETY: the child hasn't been reaped, so the pid is still its.
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            None
+        }
+    }
+}
+
+/// Columns `text` takes up on the terminal, not counting escape sequences.
+pub fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    width
+}
+
+/// Width of the terminal on stdout, if it is one.
+pub fn terminal_width() -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only writes to the struct we own.
+    let size = unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 {
+            return None;
+        }
+        size
+    };
+    (size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(last_exit: i32) -> Facts<'static> {
+        Facts {
+            cwd: Path::new("/"),
+            is_root: false,
+            last_exit,
+            duration: Duration::ZERO,
+            yolo: true,
+            judgy: false,
+        }
+    }
+
+    fn render(template: &str, facts: &Facts) -> String {
+        let no_status = Mutex::default();
+        let mut values = Values {
+            facts,
+            no_status: &no_status,
+            git: None,
+        };
+        Template::parse(template).unwrap().render(&mut values)
+    }
+
+    #[test]
+    fn formats_disappear_with_their_value() {
+        assert_eq!(render("{status: [%]} {sigil}", &facts(1)), " [1] >");
+        assert_eq!(render("{status: [%]} {sigil}", &facts(0)), " >");
+        assert_eq!(render("{status}", &facts(130)), "130 SIGINT");
+        assert_eq!(render("{judgy:judging }{yolo:(%)}", &facts(0)), "(yolo)");
+    }
+
+    #[test]
+    fn colors_and_literal_braces() {
+        assert_eq!(
+            render("{red}{yolo:{bold}%{reset}}", &facts(0)),
+            format!("{}{}yolo{}", COLOR_RED, COLOR_BOLD, COLOR_RESET)
+        );
+        assert_eq!(render("{{sigil}} {sigil}", &facts(0)), "{sigil} >");
+        assert_eq!(render("{status: {{%}}}", &facts(2)), " {2}");
+        assert_eq!(render("{status: {{%}}}", &facts(0)), "");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (template, error) in [
+            ("{nope}", "unknown placeholder {nope}"),
+            ("{red:x}", "{red} doesn't take a format"),
+            (
+                "{status:{cwd}}",
+                "only colors can go in a format, not {cwd}",
+            ),
+            ("{status:{status:%}}", "unclosed {status"),
+            ("a } b", "unmatched }"),
+            ("{status", "unclosed {status"),
+            ("{status: [%]", "unclosed {"),
+        ] {
+            assert_eq!(
+                Template::parse(template).err().unwrap(),
+                error,
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn abbreviates_all_but_the_last_directory() {
+        assert_eq!(abbreviate("~/projects/claudesh/src"), "~/p/c/src");
+        assert_eq!(abbreviate("/usr/local/bin"), "/u/l/bin");
+        assert_eq!(abbreviate("~/.config/nvim"), "~/.c/nvim");
+        assert_eq!(abbreviate("~"), "~");
+        assert_eq!(abbreviate("/"), "/");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::from_secs(5)), "5s");
+        assert_eq!(format_duration(Duration::from_millis(72_900)), "1m12s");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h2m");
+    }
+
+    #[test]
+    fn width_skips_escape_sequences() {
+        assert_eq!(visible_width(""), 0);
+        assert_eq!(visible_width("~/src >"), 7);
+        assert_eq!(
+            visible_width(&format!("{}{}red{} x", COLOR_BOLD, COLOR_RED, COLOR_RESET)),
+            5
+        );
+        assert_eq!(visible_width("日本"), 4);
+    }
+}
@@ -0,0 +1,1719 @@
+//! Persistent bash coprocess.
+//!
+//! Every shell command claudesh runs goes through one long-lived bash process,
+//! so aliases, functions, `set -o`/`shopt` options, traps and non-exported
+//! variables survive from one line to the next. Bash reads each line from a
+//! pipe; after it, it reports the exit status, working directory, exported
+//! environment and alias/function names on a second pipe so claudesh can
+//! mirror that state in its own process.
+//!
+//! In the interactive REPL claudesh does the job control itself. Bash runs in
+//! a process group of its own, which claudesh puts in the foreground for each
+//! line. Before running a line bash leaves behind a stopped spare copy of
+//! itself. If Ctrl-Z stops the line, the stopped bash becomes a job in
+//! claudesh's table and the spare carries on as the session; if the line kills
+//! bash, Ctrl-C included, the spare takes over the same way. Each `cmd &` at
+//! the top of a line is started in a process group of its own and joins the
+//! table too.
+//!
+//! Commands there write their output to a pseudo-terminal, so they still see
+//! a terminal, and claudesh copies it to the real one, keeping the end of it
+//! for the transcript. Input and job control stay on the real terminal, which
+//! takes on the settings programs make through stdout while they run.
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, IsTerminal, PipeReader, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::jobs::{self, JobTable, State};
+use crate::{parse, signals};
+use crate::{COLOR_DIM, COLOR_RED, COLOR_RESET};
+
+/// Max bytes of stderr to capture for error analysis (1 MB).
+/// Prevents unbounded memory growth from noisy commands.
+const STDERR_CAPTURE_LIMIT: usize = 1024 * 1024;
+
+/// Max bytes of terminal output to keep for the transcript, from the end.
+const STDOUT_CAPTURE_LIMIT: usize = 64 * 1024;
+
+/// Descriptors bash sees for its side of the session. High numbers keep them
+/// clear of anything a user script is likely to open itself.
+const FD_SCRIPT: i32 = 200;
+const FD_REPORT: i32 = 201;
+const FD_STDERR: i32 = 202;
+/// Standard output, kept aside for a process started inside `$(...)`.
+const FD_STDOUT: i32 = 203;
+/// The pseudo-terminal standard output started on, for the sync marker.
+const FD_OUTPUT: i32 = 204;
+
+/// Written to the stderr pipe, and the pseudo-terminal if there is one, after
+/// every command, so the tap threads know they have forwarded everything that
+/// command wrote before we take the capture.
+const SYNC_MARKER: &[u8] = b"\0claudesh-sync\0";
+
+/// How long to wait for a tap to catch up with a finished command.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for bash to report before checking whether it stopped
+/// or died. SIGCHLD usually cuts the wait short.
+const REPORT_POLL_MS: i32 = 100;
+
+/// How often to check on the terminal's size and the pseudo-terminal's
+/// settings while nothing is being written.
+const FOLLOW_POLL_MS: i32 = 100;
+
+/// Environment variables bash manages for itself and that must not leak back
+/// into claudesh's own environment.
+const UNSYNCED_VARS: &[&str] = &["_", "SHLVL"];
+
+/// Sent once when the session starts. `__claudesh_status` restores `$?` before
+/// each command; `__claudesh_report` writes the NUL-delimited state record:
+/// status, PWD, exported `NAME=value` pairs, an empty field, alias and
+/// function names, an empty field, the process ID and command of each
+/// background list started since the last report, an empty field.
+///
+/// With job control, `__claudesh_lease` names the file holding the process ID
+/// of the bash that is the session. A bash that was stopped and replaced
+/// finds another ID there, and exits once its line is done. With
+/// `__claudesh_pty` set, the sync marker goes to the pseudo-terminal too.
+const INIT_SCRIPT: &str = r#"
+shopt -s expand_aliases
+__claudesh_launched=()
+__claudesh_status() { return "$1"; }
+__claudesh_report() {
+    local __claudesh_rc=$1 __claudesh_name __claudesh_holder
+    if [[ -n $__claudesh_lease ]]; then
+        IFS= read -r __claudesh_holder < "$__claudesh_lease"
+        if [[ $__claudesh_holder != "$BASHPID" ]]; then
+            trap - EXIT
+            exit "$__claudesh_rc"
+        fi
+    fi
+    printf '\0claudesh-sync\0' >&202
+    if [[ -n $__claudesh_pty ]]; then
+        printf '\0claudesh-sync\0' >&204
+    fi
+    {
+        printf '%s\0%s\0' "$__claudesh_rc" "$PWD"
+        for __claudesh_name in $(compgen -e); do
+            printf '%s=%s\0' "$__claudesh_name" "${!__claudesh_name}"
+        done
+        printf '\0'
+        for __claudesh_name in $(compgen -a -A function); do
+            printf '%s\0' "$__claudesh_name"
+        done
+        printf '\0'
+        for __claudesh_name in "${__claudesh_launched[@]}"; do
+            printf '%s\0' "$__claudesh_name"
+        done
+        printf '\0'
+    } >&201
+    __claudesh_launched=()
+    return "$__claudesh_rc"
+}
+"#;
+
+/// Bash's main loop: eval each NUL-terminated line from the script pipe. Its
+/// own stderr stays out of the way of `set -x`; commands get the stderr pipe.
+const SERVE: &str = "while IFS= read -r -d '' __claudesh_line <&200; do \
+     eval \"$__claudesh_line\"; done 2>/dev/null; exit";
+
+/// Result of running a bash command
+pub struct RunResult {
+    pub exit_code: i32,
+    /// The end of what the command wrote to the terminal, when the session
+    /// could see it.
+    pub captured_stdout: String,
+    pub captured_stderr: String,
+    pub core_dumped: bool,
+}
+
+impl RunResult {
+    /// The signal that killed the command, e.g. "SIGSEGV (core dumped)".
+    pub fn signal_description(&self) -> Option<String> {
+        let name = signals::name(signals::from_status(self.exit_code)?)?;
+        if self.core_dumped {
+            Some(format!("{} (core dumped)", name))
+        } else {
+            Some(name.to_string())
+        }
+    }
+}
+
+/// State reported by bash after each command.
+#[derive(Debug, PartialEq)]
+struct Report {
+    status: i32,
+    pwd: PathBuf,
+    exported: Vec<(OsString, OsString)>,
+    names: HashSet<String>,
+    /// Background lists started by the line: process ID and command.
+    launched: Vec<(libc::pid_t, String)>,
+}
+
+impl Report {
+    /// Put a report together from its fields, read one at a time.
+    fn parse<E>(mut next: impl FnMut() -> Result<Vec<u8>, E>) -> Result<Report, E> {
+        let status = String::from_utf8_lossy(&next()?).parse().unwrap_or(1);
+        let pwd = PathBuf::from(OsString::from_vec(next()?));
+
+        let mut exported = Vec::new();
+        loop {
+            let field = next()?;
+            if field.is_empty() {
+                break;
+            }
+            if let Some(eq) = field.iter().position(|&b| b == b'=') {
+                let value = field[eq + 1..].to_vec();
+                let mut key = field;
+                key.truncate(eq);
+                exported.push((OsString::from_vec(key), OsString::from_vec(value)));
+            }
+        }
+
+        let mut names = HashSet::new();
+        loop {
+            let field = next()?;
+            if field.is_empty() {
+                break;
+            }
+            names.insert(String::from_utf8_lossy(&field).to_string());
+        }
+
+        let mut launched = Vec::new();
+        loop {
+            let pid = next()?;
+            if pid.is_empty() {
+                break;
+            }
+            let command = String::from_utf8_lossy(&next()?).to_string();
+            if let Ok(pid) = String::from_utf8_lossy(&pid).parse() {
+                launched.push((pid, command));
+            }
+        }
+
+        Ok(Report {
+            status,
+            pwd,
+            exported,
+            names,
+            launched,
+        })
+    }
+}
+
+/// Why bash never reported on a line.
+enum Cut {
+    /// Stopped by the signal, leaving the terminal modes it had.
+    Stopped(i32, Option<libc::termios>),
+    Died(State),
+}
+
+pub struct BashSession {
+    /// The bash process serving as the session. With job control it leads
+    /// its own process group.
+    pid: libc::pid_t,
+    script: io::PipeWriter,
+    reports: BufReader<PipeReader>,
+    stderr_tap: Arc<Tap>,
+    stdout_tap: Option<Arc<Tap>>,
+    /// The terminal, when the session does job control.
+    terminal: Option<Arc<Terminal>>,
+    lease: Option<Lease>,
+    cwd: PathBuf,
+    names: HashSet<String>,
+    jobs: JobTable,
+    notices: Vec<String>,
+    last_status: i32,
+}
+
+impl BashSession {
+    /// Start a bash coprocess in `cwd`. Its stdin, stdout and stderr are
+    /// inherited so commands talk to the terminal directly; commands' stderr
+    /// is tee'd through claudesh.
+    ///
+    /// With `job_control`, stdin must be the terminal. Bash then runs in its
+    /// own process group, and claudesh keeps the job table. If stdout is the
+    /// terminal too, bash gets a pseudo-terminal instead, which claudesh
+    /// copies to the terminal, keeping the end of each command's output.
+    pub fn spawn(cwd: &Path, job_control: bool) -> io::Result<Self> {
+        let (script_r, script_w) = io::pipe()?;
+        let (report_r, report_w) = io::pipe()?;
+        let (stderr_r, stderr_w) = io::pipe()?;
+
+        let mut fd_map = vec![
+            (script_r.as_raw_fd(), FD_SCRIPT),
+            (report_w.as_raw_fd(), FD_REPORT),
+            (stderr_w.as_raw_fd(), FD_STDERR),
+        ];
+
+        let mut terminal = None;
+        let mut slave = None;
+        let mut lease = None;
+        let mut init = INIT_SCRIPT.to_string();
+        if job_control {
+            let fd = io::stdin().as_fd().try_clone_to_owned()?;
+            let mut pty = None;
+            if io::stdout().is_terminal() {
+                let (master, opened) = open_pty(&fd)?;
+                fd_map.push((opened.as_raw_fd(), FD_OUTPUT));
+                pty = Some(master);
+                slave = Some(opened);
+                init.push_str("__claudesh_pty=1\n");
+            }
+            terminal = Some(Arc::new(Terminal {
+                fd,
+                pty,
+                passed: Mutex::new(None),
+            }));
+            let created = Lease::create()?;
+            init.push_str(&format!(
+                "__claudesh_lease={}\n",
+                single_quote(&created.path.to_string_lossy())
+            ));
+            lease = Some(created);
+        }
+        init.push('\0');
+
+        let mut command = Command::new("bash");
+        command
+            .arg("--noprofile")
+            .arg("--norc")
+            .arg("-c")
+            .arg(SERVE)
+            .current_dir(cwd);
+        if let Some(slave) = &slave {
+            command.stdout(slave.try_clone()?);
+        }
+
+        // SAFETY: only async-signal-safe calls between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                for &(from, to) in &fd_map {
+                    // dup2 onto itself keeps FD_CLOEXEC set, so clear it by hand
+                    let rc = if from == to {
+                        libc::fcntl(to, libc::F_SETFD, 0)
+                    } else {
+                        libc::dup2(from, to)
+                    };
+                    if rc < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if job_control {
+                    libc::setpgid(0, 0);
+                }
+                // claudesh ignores these while it owns the terminal
+                for sig in [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+                    libc::signal(sig, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+
+        // The process is waited for by ID, so the handle isn't kept
+        let pid = command.spawn()?.id() as libc::pid_t;
+
+        // Close our copies of bash's ends so EOF is seen when bash exits
+        drop(command);
+        drop(script_r);
+        drop(report_w);
+        drop(stderr_w);
+        drop(slave);
+
+        if job_control {
+            // Also set from this side, in case we get here before the child does
+            unsafe {
+                libc::setpgid(pid, pid);
+            }
+        }
+        if let Some(lease) = &lease {
+            lease.grant(pid)?;
+        }
+
+        let stderr_tap = Arc::new(Tap::new(Keep::First(STDERR_CAPTURE_LIMIT)));
+        let tap = Arc::clone(&stderr_tap);
+        thread::spawn(move || tap.pump(stderr_r, io::stderr()));
+
+        let mut stdout_tap = None;
+        if let Some(terminal) = terminal.as_ref().filter(|t| t.pty.is_some()) {
+            let reader = PtyReader::new(terminal)?;
+            let created = Arc::new(Tap::new(Keep::Last(STDOUT_CAPTURE_LIMIT)));
+            let tap = Arc::clone(&created);
+            thread::spawn(move || tap.pump(reader, io::stdout()));
+            stdout_tap = Some(created);
+        }
+
+        let mut session = BashSession {
+            pid,
+            script: script_w,
+            reports: BufReader::new(report_r),
+            stderr_tap,
+            stdout_tap,
+            terminal,
+            lease,
+            cwd: cwd.to_path_buf(),
+            names: HashSet::new(),
+            jobs: JobTable::default(),
+            notices: Vec::new(),
+            last_status: 0,
+        };
+        session.script.write_all(init.as_bytes())?;
+        if !session.refresh() {
+            return Err(io::Error::other("bash exited during startup"));
+        }
+        Ok(session)
+    }
+
+    /// The session's current working directory.
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Aliases and functions currently defined in the session.
+    pub fn names(&self) -> &HashSet<String> {
+        &self.names
+    }
+
+    /// Live jobs, as of the last `take_notices`.
+    pub fn jobs(&self) -> &JobTable {
+        &self.jobs
+    }
+
+    /// Check on the jobs, and return what there is to announce: jobs started,
+    /// stopped or finished since the last call, one line each.
+    pub fn take_notices(&mut self) -> Vec<String> {
+        let polled = self.jobs.poll();
+        self.notices.extend(polled);
+        self.reap_orphans();
+        std::mem::take(&mut self.notices)
+    }
+
+    /// Run a command in the session and mirror the resulting cwd and
+    /// environment into claudesh. Stderr is displayed in real time and also
+    /// captured for error analysis, and so is stdout when it goes through
+    /// the pseudo-terminal.
+    pub fn run(&mut self, cmd: &str) -> RunResult {
+        let target = self.stderr_tap.begin();
+        let stdout_target = self.stdout_tap.as_ref().map(|tap| tap.begin());
+
+        let (reply, spare) = self.round_trip(&self.command_line(cmd), true);
+        let report = match reply {
+            Ok(report) => report,
+            Err(cut) => return self.recover(cut, spare, cmd),
+        };
+        if let Some(spare) = spare {
+            // SAFETY: the spare is claudesh's child, waiting to be needed.
+            unsafe {
+                libc::kill(spare, libc::SIGKILL);
+            }
+            jobs::wait(spare);
+        }
+
+        let captured = self.stderr_tap.finish(target);
+        let output = match (&self.stdout_tap, stdout_target) {
+            (Some(tap), Some(target)) => tap.finish(target),
+            _ => Vec::new(),
+        };
+        self.apply(report);
+
+        // Bash mentions a core dump in its message about the dead command
+        let core_dumped = captured.windows(13).any(|w| w == b"(core dumped)");
+        RunResult {
+            exit_code: self.last_status,
+            captured_stdout: String::from_utf8_lossy(&output).to_string(),
+            captured_stderr: String::from_utf8_lossy(&captured).to_string(),
+            core_dumped,
+        }
+    }
+
+    /// Ask bash for a fresh report without running anything. Returns false
+    /// if bash is gone.
+    fn refresh(&mut self) -> bool {
+        let line = format!(
+            "{{ __claudesh_status {status}; __claudesh_report {status}; }} 2>/dev/null\0",
+            status = self.last_status,
+        );
+        match self.round_trip(&line, false).0 {
+            Ok(report) => {
+                self.apply(report);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Whether each line leaves a spare bash behind. That needs orphans to
+    /// come to claudesh, which only Linux offers.
+    fn has_spares(&self) -> bool {
+        cfg!(target_os = "linux") && self.terminal.is_some()
+    }
+
+    fn command_line(&self, cmd: &str) -> String {
+        let mut line = String::new();
+        let mut cmd = cmd.to_string();
+        if self.terminal.is_some() {
+            cmd = expand_job_specs(&launch_in_background(&cmd), &self.jobs);
+        }
+        if self.has_spares() {
+            // The spare forks from a subshell in a process group of its own,
+            // so the terminal's signals miss it, and stops until it's needed.
+            // `0<&0` keeps bash from giving it /dev/null for input.
+            line.push_str(&format!(
+                "{{ __claudesh_spare=$(set -m; {{ set +m; eval \"$(trap -p)\"; \
+                 kill -STOP \"$BASHPID\"; {serve}; }} 0<&0 1>&{stdout} {stdout}>&- & \
+                 printf %s \"$!\"); }} {stdout}>&1\n\
+                 printf '%s\\0' \"$__claudesh_spare\" >&{report}\n",
+                serve = SERVE,
+                stdout = FD_STDOUT,
+                report = FD_REPORT,
+            ));
+        }
+        // Grouping the bookkeeping calls with their own redirection keeps them
+        // out of `set -x` traces. The eval runs without our descriptors so
+        // commands can't clobber them, and with stderr on the capture pipe.
+        // The loop keeps a stray `break` from ending bash's own.
+        line.push_str(&format!(
+            "{{ __claudesh_status {status}; }} 2>/dev/null\n\
+             for __claudesh_once in 1; do \
+             eval {cmd} {script}<&- {report}>&- 2>&{stderr} {stderr}>&- {output}>&-; done\n\
+             {{ __claudesh_report $?; }} 2>/dev/null\0",
+            status = self.last_status,
+            cmd = single_quote(&cmd),
+            script = FD_SCRIPT,
+            report = FD_REPORT,
+            stderr = FD_STDERR,
+            output = FD_OUTPUT,
+        ));
+        line
+    }
+
+    /// Send a line to bash with the terminal in its hands, and read the
+    /// report it produces. For a command, also returns the spare bash it
+    /// left behind, if it got that far.
+    fn round_trip(
+        &mut self,
+        line: &str,
+        command: bool,
+    ) -> (Result<Report, Cut>, Option<libc::pid_t>) {
+        let mut handover = self
+            .terminal
+            .as_ref()
+            .map(|terminal| Handover::new(terminal, self.pid, None));
+        if handover.is_some() {
+            adopt_orphans(true);
+        }
+
+        let sent = self
+            .script
+            .write_all(line.as_bytes())
+            .and_then(|_| self.script.flush());
+        let mut spare = None;
+        let mut reply = match sent {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Cut::Died(jobs::wait(self.pid))),
+        };
+        if reply.is_ok() && command && self.has_spares() {
+            reply = self
+                .read_field(true)
+                .map(|field| spare = String::from_utf8_lossy(&field).parse().ok());
+        }
+        let mut reply = reply.and_then(|_| self.read_report(!command));
+
+        if let Some(handover) = &mut handover {
+            adopt_orphans(false);
+            // A line cut short leaves the terminal as it was before it, but
+            // a stopped one gets its modes back when it carries on
+            match &mut reply {
+                Ok(_) => handover.keep(),
+                Err(Cut::Stopped(_, modes)) => *modes = handover.modes(),
+                Err(Cut::Died(_)) => {}
+            }
+        }
+        (reply, spare)
+    }
+
+    /// Read a whole report. A stop once it has started is undone, since the
+    /// line has finished by then; before that, only if `resume`.
+    fn read_report(&mut self, resume: bool) -> Result<Report, Cut> {
+        let mut first = true;
+        Report::parse(|| {
+            let field = self.read_field(resume || !first);
+            first = false;
+            field
+        })
+    }
+
+    /// Read one NUL-terminated field, keeping an eye on bash meanwhile.
+    fn read_field(&mut self, resume: bool) -> Result<Vec<u8>, Cut> {
+        let mut field = Vec::new();
+        loop {
+            self.wait_for_report(resume)?;
+            let buf = match self.reports.fill_buf() {
+                Ok(buf) if !buf.is_empty() => buf,
+                _ => return Err(Cut::Died(jobs::wait(self.pid))),
+            };
+            match buf.iter().position(|&b| b == 0) {
+                Some(end) => {
+                    field.extend_from_slice(&buf[..end]);
+                    self.reports.consume(end + 1);
+                    return Ok(field);
+                }
+                None => {
+                    let len = buf.len();
+                    field.extend_from_slice(buf);
+                    self.reports.consume(len);
+                }
+            }
+        }
+    }
+
+    /// Wait until there is more of the report to read, or bash stops or
+    /// dies. A stop is undone on the spot if `resume`.
+    fn wait_for_report(&mut self, resume: bool) -> Result<(), Cut> {
+        let mut flags = libc::WNOHANG;
+        if self.terminal.is_some() {
+            flags |= libc::WUNTRACED;
+        }
+        loop {
+            if !self.reports.buffer().is_empty() {
+                return Ok(());
+            }
+            let mut status = 0;
+            // SAFETY: waitpid on the session's own process.
+            if unsafe { libc::waitpid(self.pid, &mut status, flags) } == self.pid {
+                match State::from_wait(status) {
+                    // SAFETY: signalling the session's own process group.
+                    State::Stopped(_) if resume => unsafe {
+                        libc::kill(-self.pid, libc::SIGCONT);
+                    },
+                    State::Stopped(sig) => return Err(Cut::Stopped(sig, None)),
+                    state => return Err(Cut::Died(state)),
+                }
+            }
+            let mut fd = libc::pollfd {
+                fd: self.reports.get_ref().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: polling one descriptor we own.
+            if unsafe { libc::poll(&mut fd, 1, REPORT_POLL_MS) } > 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Deal with a line bash never reported on: a stopped bash becomes a
+    /// job, and the spare it left takes over as the session, or failing that
+    /// a fresh bash.
+    fn recover(&mut self, cut: Cut, spare: Option<libc::pid_t>, cmd: &str) -> RunResult {
+        let captured = self.stderr_tap.abandon();
+        let output = self
+            .stdout_tap
+            .as_ref()
+            .map(|tap| tap.abandon())
+            .unwrap_or_default();
+        let state = match cut {
+            Cut::Stopped(sig, modes) => {
+                let state = State::Stopped(sig);
+                let number = self.jobs.add(self.pid, cmd, state);
+                if let Some(job) = self.jobs.get_mut(number) {
+                    job.modes = modes;
+                }
+                self.announce(number);
+                println!();
+                state
+            }
+            Cut::Died(state) => state,
+        };
+        let exit_code = state.status();
+        self.last_status = exit_code;
+
+        let promoted = spare.is_some_and(|spare| self.promote(spare));
+        // A command interrupted from the keyboard takes bash with it; that is
+        // not worth a message
+        if !state.is_stopped() && !signals::is_user_interrupt(exit_code) {
+            let next = if promoted {
+                "picking up where it was before that line"
+            } else {
+                "starting a new one"
+            };
+            eprintln!("{}bash session exited; {}{}", COLOR_DIM, next, COLOR_RESET);
+        }
+        if !promoted {
+            self.restart();
+        }
+
+        RunResult {
+            exit_code,
+            captured_stdout: String::from_utf8_lossy(&output).to_string(),
+            captured_stderr: String::from_utf8_lossy(&captured).to_string(),
+            core_dumped: matches!(state, State::Killed(_, true)),
+        }
+    }
+
+    /// Make the spare bash the session. Returns false if it's gone.
+    fn promote(&mut self, spare: libc::pid_t) -> bool {
+        // It may not have got as far as stopping itself yet
+        if !jobs::wait(spare).is_stopped() {
+            return false;
+        }
+        if let Some(lease) = &self.lease {
+            if lease.grant(spare).is_err() {
+                return false;
+            }
+        }
+        // SAFETY: the spare is claudesh's child.
+        unsafe {
+            libc::kill(spare, libc::SIGCONT);
+        }
+        self.pid = spare;
+        // Whatever a dying bash got out of its report is of no use
+        let buffered = self.reports.buffer().len();
+        self.reports.consume(buffered);
+        drain(self.reports.get_mut());
+        true
+    }
+
+    /// Start a fresh bash in the same directory and environment, keeping the
+    /// job table.
+    fn restart(&mut self) {
+        match BashSession::spawn(&self.cwd, self.terminal.is_some()) {
+            Ok(mut fresh) => {
+                fresh.last_status = self.last_status;
+                fresh.jobs = std::mem::take(&mut self.jobs);
+                fresh.notices = std::mem::take(&mut self.notices);
+                *self = fresh;
+            }
+            Err(e) => {
+                eprintln!("{}failed to restart bash: {}{}", COLOR_RED, e, COLOR_RESET);
+            }
+        }
+    }
+
+    /// Mirror the session's state into claudesh's own process.
+    fn apply(&mut self, report: Report) {
+        self.last_status = report.status;
+        self.names = report.names;
+
+        for (pid, command) in report.launched {
+            let number = self.jobs.add(pid, &command, State::Running);
+            self.notices.push(format!("[{}] {}", number, pid));
+        }
+
+        if report.pwd != self.cwd && report.pwd.is_dir() {
+            env::set_current_dir(&report.pwd).ok();
+            self.cwd = report.pwd;
+        }
+
+        let exported: Vec<(OsString, OsString)> = report
+            .exported
+            .into_iter()
+            .filter(|(key, _)| !UNSYNCED_VARS.iter().any(|v| key.as_bytes() == v.as_bytes()))
+            .collect();
+
+        for (key, _) in env::vars_os() {
+            let synced = !UNSYNCED_VARS.iter().any(|v| key.as_bytes() == v.as_bytes());
+            if synced && !exported.iter().any(|(k, _)| *k == key) {
+                env::remove_var(&key);
+            }
+        }
+        for (key, value) in exported {
+            if env::var_os(&key).as_ref() != Some(&value) {
+                env::set_var(&key, &value);
+            }
+        }
+    }
+
+    /// Queue a job's line in the table, e.g. after it stopped.
+    fn announce(&mut self, number: u32) {
+        if let Some(job) = self.jobs.get(number) {
+            let notice = self.jobs.format(job);
+            self.notices.push(notice);
+        }
+    }
+
+    // ─── Job builtins ───────────────────────────────────────────────────────
+
+    /// `fg [spec]`: give a job the terminal and wait for it to stop or end.
+    pub fn fg(&mut self, args: &str) -> i32 {
+        let number = match self.jobs.find(args.trim()) {
+            Ok(number) => number,
+            Err(e) => {
+                eprintln!("claudesh: fg: {}", e);
+                return 1;
+            }
+        };
+        self.jobs.touch(number);
+        let job = self.jobs.get_mut(number).unwrap();
+        println!("{}", job.command);
+        job.state = State::Running;
+        let pgid = job.pgid;
+        let job_modes = job.modes.take();
+
+        let mut handover = self
+            .terminal
+            .as_ref()
+            .map(|terminal| Handover::new(terminal, pgid, job_modes.as_ref()));
+        // SAFETY: signalling the job's own process group.
+        unsafe {
+            libc::kill(-pgid, libc::SIGCONT);
+        }
+        let state = jobs::wait(pgid);
+        let mut left_modes = None;
+        if let Some(handover) = &mut handover {
+            if let State::Exited(_) = state {
+                handover.keep();
+            } else {
+                left_modes = handover.modes();
+            }
+        }
+        drop(handover);
+
+        if state.is_stopped() {
+            let job = self.jobs.get_mut(number).unwrap();
+            job.state = state;
+            job.modes = left_modes;
+            self.announce(number);
+            println!();
+        } else {
+            self.jobs.remove(number);
+            if let State::Killed(sig, _) = state {
+                if sig != libc::SIGINT && sig != libc::SIGPIPE {
+                    eprintln!("{}", state.describe());
+                }
+            }
+        }
+        state.status()
+    }
+
+    /// `bg [spec]`: let a stopped job carry on in the background.
+    pub fn bg(&mut self, args: &str) -> i32 {
+        let number = match self.jobs.find(args.trim()) {
+            Ok(number) => number,
+            Err(e) => {
+                eprintln!("claudesh: bg: {}", e);
+                return 1;
+            }
+        };
+        let job = self.jobs.get_mut(number).unwrap();
+        if job.state == State::Running {
+            eprintln!("claudesh: bg: job {} already in background", number);
+            return 0;
+        }
+        job.state = State::Running;
+        // SAFETY: signalling the job's own process group.
+        unsafe {
+            libc::kill(-job.pgid, libc::SIGCONT);
+        }
+        self.jobs.touch(number);
+        let job = self.jobs.get(number).unwrap();
+        println!("[{}]{} {} &", number, self.jobs.marker(number), job.command);
+        0
+    }
+
+    /// `wait [spec|pid ...]`: wait for the jobs named, or all of them, to
+    /// finish. A job that stops ends the wait for it. Ctrl-C gives up.
+    pub fn wait(&mut self, args: &str) -> i32 {
+        let mut numbers = Vec::new();
+        let mut status = 0;
+        for word in args.split_whitespace() {
+            let found = if word.starts_with('%') {
+                self.jobs.find(word)
+            } else {
+                let pid = word.parse::<libc::pid_t>().ok();
+                self.jobs
+                    .iter()
+                    .find(|job| Some(job.pgid) == pid)
+                    .map(|job| job.number)
+                    .ok_or_else(|| format!("pid {} is not a child of this shell", word))
+            };
+            match found {
+                Ok(number) => numbers.push(number),
+                Err(e) => {
+                    eprintln!("claudesh: wait: {}", e);
+                    status = 127;
+                }
+            }
+        }
+        let all = args.trim().is_empty();
+        if all {
+            numbers = self.jobs.iter().map(|job| job.number).collect();
+        }
+
+        signals::take_interrupt();
+        for number in numbers {
+            let Some(job) = self.jobs.get(number) else {
+                continue;
+            };
+            let pgid = job.pgid;
+            let mut state = job.state;
+            while state == State::Running {
+                if signals::take_interrupt() {
+                    return 128 + libc::SIGINT;
+                }
+                match jobs::check(pgid) {
+                    Some(next) => state = next,
+                    None => thread::sleep(Duration::from_millis(50)),
+                }
+            }
+            if state.is_finished() {
+                self.jobs.remove(number);
+            } else if let Some(job) = self.jobs.get_mut(number) {
+                if job.state != state {
+                    job.state = state;
+                    self.jobs.touch(number);
+                    self.announce(number);
+                }
+            }
+            status = state.status();
+        }
+        if all {
+            0
+        } else {
+            status
+        }
+    }
+
+    /// `disown [-a] [-r] [spec ...]`: forget jobs, leaving them running.
+    pub fn disown(&mut self, args: &str) -> i32 {
+        let mut all = false;
+        let mut running = false;
+        let mut specs = Vec::new();
+        for word in args.split_whitespace() {
+            match word {
+                "-a" => all = true,
+                "-r" => running = true,
+                "-ar" | "-ra" => (all, running) = (true, true),
+                _ => specs.push(word),
+            }
+        }
+
+        let mut status = 0;
+        let numbers: Vec<u32> = if all || running {
+            self.jobs
+                .iter()
+                .filter(|job| !running || job.state == State::Running)
+                .map(|job| job.number)
+                .collect()
+        } else {
+            if specs.is_empty() {
+                specs.push("");
+            }
+            let mut numbers = Vec::new();
+            for spec in specs {
+                match self.jobs.find(spec) {
+                    Ok(number) => numbers.push(number),
+                    Err(e) => {
+                        eprintln!("claudesh: disown: {}", e);
+                        status = 1;
+                    }
+                }
+            }
+            numbers
+        };
+        for number in numbers {
+            if let Some(job) = self.jobs.remove(number) {
+                if job.state.is_stopped() {
+                    eprintln!(
+                        "claudesh: warning: deleting stopped job {} with process group {}",
+                        job.number, job.pgid
+                    );
+                }
+            }
+        }
+        status
+    }
+
+    /// Reap the orphans claudesh took in that aren't jobs once they finish:
+    /// what a killed job left behind, disowned jobs, and anything a command
+    /// sent off on its own. Claudesh's own helpers stay in its process group
+    /// and are left to whoever started them.
+    #[cfg(target_os = "linux")]
+    fn reap_orphans(&self) {
+        let Ok(tasks) = std::fs::read_dir("/proc/self/task") else {
+            return;
+        };
+        // SAFETY: getpgrp/getpgid/waitpid on claudesh's own children.
+        let own_group = unsafe { libc::getpgrp() };
+        for task in tasks.flatten() {
+            let Ok(children) = std::fs::read_to_string(task.path().join("children")) else {
+                continue;
+            };
+            for pid in children.split_whitespace().filter_map(|p| p.parse().ok()) {
+                if pid == self.pid
+                    || self.jobs.iter().any(|job| job.pgid == pid)
+                    || unsafe { libc::getpgid(pid) } == own_group
+                {
+                    continue;
+                }
+                let mut status = 0;
+                unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn reap_orphans(&self) {}
+}
+
+/// Start each `cmd &` at the top of a line in a process group of its own,
+/// from a subshell so it's handed on to claudesh, and note it for the report.
+/// Lists inside compound commands stay bash's own. As in an interactive
+/// shell, they keep the terminal for input and stop if they read from it.
+fn launch_in_background(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = 0;
+    for range in parse::background_lists(line) {
+        let list = &line[range.clone()];
+        let Some(amp) = line[range.end..].find('&') else {
+            continue;
+        };
+        out.push_str(&line[rest..range.start]);
+        out.push_str(&format!(
+            "{{ __claudesh_pid=$(set -m; ( set +m; {list} ) 0<&0 1>&{stdout} {stdout}>&- & \
+             printf %s \"$!\"); }} {stdout}>&1; \
+             __claudesh_launched+=(${{__claudesh_pid:+\"$__claudesh_pid\" {quoted}}});",
+            list = list,
+            quoted = single_quote(list),
+            stdout = FD_STDOUT,
+        ));
+        rest = range.end + amp + 1;
+    }
+    out.push_str(&line[rest..]);
+    out
+}
+
+/// `kill %1` signals the job's process group, as in bash. A stopped job sent
+/// SIGTERM or SIGHUP is continued too, so it gets to act on it.
+fn expand_job_specs(line: &str, jobs: &JobTable) -> String {
+    let Some(args) = line.strip_prefix("kill ") else {
+        return line.to_string();
+    };
+    if !args.contains('%') || args.contains(|c| ";&|<>()$`'\"\\\n".contains(c)) {
+        return line.to_string();
+    }
+
+    let mut words = vec!["kill".to_string()];
+    let mut signal = "TERM".to_string();
+    let mut args = args.split_whitespace().peekable();
+    // Options, including `-s`/`-n` and the signal after them
+    while let Some(word) = args.next_if(|w| w.starts_with('-')) {
+        words.push(word.to_string());
+        if word == "--" {
+            break;
+        }
+        if word == "-s" || word == "-n" {
+            if let Some(name) = args.next() {
+                signal = name.to_string();
+                words.push(signal.clone());
+            }
+        } else {
+            signal = word[1..].to_string();
+        }
+    }
+    let signal = signal.to_ascii_uppercase();
+    let wakes = matches!(
+        signal.strip_prefix("SIG").unwrap_or(&signal),
+        "TERM" | "HUP" | "15" | "1"
+    );
+
+    let mut stopped = Vec::new();
+    let operands: Vec<String> = args
+        .map(
+            |word| match word.starts_with('%').then(|| jobs.find(word)) {
+                Some(Ok(number)) => {
+                    let job = jobs.get(number).unwrap();
+                    let group = format!("-{}", job.pgid);
+                    if wakes && job.state.is_stopped() {
+                        stopped.push(group.clone());
+                    }
+                    group
+                }
+                _ => word.to_string(),
+            },
+        )
+        .collect();
+    // A process group looks like an option unless options have ended
+    if operands.iter().any(|w| w.starts_with('-')) && words.last().is_none_or(|w| w != "--") {
+        words.push("--".to_string());
+    }
+    words.extend(operands);
+    let mut out = words.join(" ");
+    if !stopped.is_empty() {
+        out.push_str(&format!(" && kill -CONT -- {}", stopped.join(" ")));
+    }
+    out
+}
+
+/// Have processes orphaned meanwhile handed to claudesh rather than init, so
+/// a spare or a background list started inside `$(...)` can be waited for.
+#[cfg(target_os = "linux")]
+fn adopt_orphans(on: bool) {
+    // SAFETY: sets a flag on claudesh's own process.
+    unsafe {
+        libc::prctl(libc::PR_SET_CHILD_SUBREAPER, on as libc::c_ulong, 0, 0, 0);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn adopt_orphans(_: bool) {}
+
+/// Throw away whatever is waiting in a pipe.
+fn drain(pipe: &mut PipeReader) {
+    let fd = pipe.as_raw_fd();
+    // SAFETY: fcntl on a descriptor we own.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+    let mut buf = [0u8; 4096];
+    while matches!(pipe.read(&mut buf), Ok(n) if n > 0) {}
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFL, flags);
+    }
+}
+
+/// Quote a string for bash so it is passed through `eval` unchanged.
+fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// ─── Terminal ────────────────────────────────────────────────────────────────
+
+/// The terminal the interactive shell runs on.
+struct Terminal {
+    fd: OwnedFd,
+    /// The master side of the pseudo-terminal bash writes its output to,
+    /// when claudesh's own output goes to the terminal.
+    pty: Option<OwnedFd>,
+    /// While a line or job has the terminal, the pseudo-terminal's settings
+    /// as last passed on to it.
+    passed: Mutex<Option<libc::termios>>,
+}
+
+impl Terminal {
+    /// Start passing on settings, from the pseudo-terminal set up like the
+    /// terminal is now.
+    fn share(&self) {
+        let Some(pty) = &self.pty else {
+            return;
+        };
+        let mut passed = self.passed.lock().unwrap();
+        if let Some(mut modes) = terminal_modes(&self.fd) {
+            // The terminal does the output processing
+            modes.c_oflag &= !libc::OPOST;
+            set_terminal_modes(pty, &modes);
+        }
+        copy_size(&self.fd, pty);
+        *passed = terminal_modes(pty);
+    }
+
+    /// Pass on what a command changed through the pseudo-terminal since the
+    /// last look, as programs set their terminal up through stdout too. The
+    /// pseudo-terminal follows the terminal's size.
+    fn follow(&self) {
+        let Some(pty) = &self.pty else {
+            return;
+        };
+        let mut passed = self.passed.lock().unwrap();
+        let Some(last) = passed.as_mut() else {
+            return;
+        };
+        if copy_size(&self.fd, pty) {
+            // The command may have looked at the old size when the terminal
+            // told it about the new one
+            // SAFETY: tcgetpgrp and killpg on the terminal's foreground group.
+            unsafe {
+                let group = libc::tcgetpgrp(self.fd.as_raw_fd());
+                if group > 0 {
+                    libc::killpg(group, libc::SIGWINCH);
+                }
+            }
+        }
+        let Some(now) = terminal_modes(pty) else {
+            return;
+        };
+        if same_modes(&now, last) {
+            return;
+        }
+        if let Some(mut modes) = terminal_modes(&self.fd) {
+            modes.c_iflag = now.c_iflag;
+            modes.c_oflag = (now.c_oflag & !libc::OPOST) | (modes.c_oflag & libc::OPOST);
+            modes.c_lflag = now.c_lflag;
+            modes.c_cc = now.c_cc;
+            set_terminal_modes(&self.fd, &modes);
+        }
+        *last = now;
+    }
+}
+
+/// The terminal in the hands of a line or a job, which meanwhile gets the
+/// settings changed through the pseudo-terminal. Dropping it takes the
+/// terminal back, set up as it was unless `keep` was called.
+struct Handover {
+    terminal: Arc<Terminal>,
+    saved: Option<libc::termios>,
+    keep: bool,
+}
+
+impl Handover {
+    /// Give the terminal to process group `pgid`, with `modes` if it had some
+    /// when it stopped.
+    fn new(terminal: &Arc<Terminal>, pgid: libc::pid_t, modes: Option<&libc::termios>) -> Self {
+        let saved = terminal_modes(&terminal.fd);
+        if let Some(modes) = modes {
+            set_terminal_modes(&terminal.fd, modes);
+        }
+        terminal.share();
+        set_foreground(&terminal.fd, pgid);
+        Handover {
+            terminal: Arc::clone(terminal),
+            saved,
+            keep: false,
+        }
+    }
+
+    /// The terminal's modes as they are now, for a job that stopped.
+    fn modes(&self) -> Option<libc::termios> {
+        self.terminal.follow();
+        terminal_modes(&self.terminal.fd)
+    }
+
+    /// Leave the terminal set up as the line left it, as with `stty`.
+    fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for Handover {
+    fn drop(&mut self) {
+        if self.keep {
+            self.terminal.follow();
+        }
+        *self.terminal.passed.lock().unwrap() = None;
+        if let Some(modes) = self.saved.as_ref().filter(|_| !self.keep) {
+            set_terminal_modes(&self.terminal.fd, modes);
+        }
+        // SAFETY: getpgrp can't fail.
+        set_foreground(&self.terminal.fd, unsafe { libc::getpgrp() });
+    }
+}
+
+/// Reads the master side of the pseudo-terminal for the stdout tap, passing
+/// settings on to the terminal before anything drawn with them.
+struct PtyReader {
+    master: File,
+    terminal: Arc<Terminal>,
+}
+
+impl PtyReader {
+    fn new(terminal: &Arc<Terminal>) -> io::Result<Self> {
+        let pty = terminal.pty.as_ref().ok_or(io::ErrorKind::NotFound)?;
+        Ok(PtyReader {
+            master: File::from(pty.try_clone()?),
+            terminal: Arc::clone(terminal),
+        })
+    }
+}
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut fd = libc::pollfd {
+                fd: self.master.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: polling one descriptor we own.
+            let ready = unsafe { libc::poll(&mut fd, 1, FOLLOW_POLL_MS) };
+            self.terminal.follow();
+            // Once no one has the slave side open, reading fails with EIO
+            if ready > 0 {
+                return self.master.read(buf);
+            }
+        }
+    }
+}
+
+/// Open a pseudo-terminal set up like `terminal`, but leaving output
+/// processing to it. Returns the master and slave sides.
+fn open_pty(terminal: &OwnedFd) -> io::Result<(OwnedFd, OwnedFd)> {
+    let (mut master, mut slave) = (-1, -1);
+    // SAFETY: openpty fills in the two descriptors on success.
+    let (master, slave) = unsafe {
+        if libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+    };
+    for fd in [&master, &slave] {
+        // SAFETY: fcntl on a descriptor we own.
+        unsafe {
+            libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+    if let Some(mut modes) = terminal_modes(terminal) {
+        modes.c_oflag &= !libc::OPOST;
+        set_terminal_modes(&master, &modes);
+    }
+    copy_size(terminal, &master);
+    Ok((master, slave))
+}
+
+/// Give `to` the window size of `from`. Returns whether it changed.
+fn copy_size(from: &OwnedFd, to: &OwnedFd) -> bool {
+    // SAFETY: window size ioctls into zeroed structs.
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        let mut current: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(from.as_raw_fd(), libc::TIOCGWINSZ, &mut size) < 0 {
+            return false;
+        }
+        libc::ioctl(to.as_raw_fd(), libc::TIOCGWINSZ, &mut current);
+        if (size.ws_row, size.ws_col) == (current.ws_row, current.ws_col) {
+            return false;
+        }
+        libc::ioctl(to.as_raw_fd(), libc::TIOCSWINSZ, &size);
+        true
+    }
+}
+
+/// Put process group `pgid` in the foreground of `terminal`.
+fn set_foreground(terminal: &OwnedFd, pgid: libc::pid_t) {
+    // SAFETY: plain ioctl on a descriptor we own. claudesh ignores SIGTTOU,
+    // so this works from the background too.
+    unsafe {
+        libc::tcsetpgrp(terminal.as_raw_fd(), pgid);
+    }
+}
+
+fn terminal_modes(terminal: &OwnedFd) -> Option<libc::termios> {
+    // SAFETY: tcgetattr fills in the struct on success.
+    unsafe {
+        let mut modes: libc::termios = std::mem::zeroed();
+        (libc::tcgetattr(terminal.as_raw_fd(), &mut modes) == 0).then_some(modes)
+    }
+}
+
+fn set_terminal_modes(terminal: &OwnedFd, modes: &libc::termios) {
+    // SAFETY: plain ioctl on a descriptor we own.
+    unsafe {
+        libc::tcsetattr(terminal.as_raw_fd(), libc::TCSADRAIN, modes);
+    }
+}
+
+fn same_modes(a: &libc::termios, b: &libc::termios) -> bool {
+    (a.c_iflag, a.c_oflag, a.c_lflag, a.c_cc) == (b.c_iflag, b.c_oflag, b.c_lflag, b.c_cc)
+}
+
+// ─── Lease ───────────────────────────────────────────────────────────────────
+
+/// The file naming the bash process that is the session, private to the
+/// user and removed with the session.
+struct Lease {
+    path: PathBuf,
+    file: File,
+}
+
+impl Lease {
+    fn create() -> io::Result<Lease> {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        let path = env::temp_dir().join(format!(
+            "claudesh-{}-{}.lease",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok(Lease { path, file })
+    }
+
+    fn grant(&self, pid: libc::pid_t) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.write_all_at(format!("{}\n", pid).as_bytes(), 0)
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+// ─── Output taps ─────────────────────────────────────────────────────────────
+
+/// Which part of a command's output a tap keeps, in bytes.
+#[derive(Clone, Copy)]
+enum Keep {
+    First(usize),
+    Last(usize),
+}
+
+/// Forwards what bash writes on one stream to claudesh's own, keeping a
+/// bounded copy of each command's share.
+struct Tap {
+    state: Mutex<TapState>,
+    synced: Condvar,
+    keep: Keep,
+}
+
+#[derive(Default)]
+struct TapState {
+    captured: Vec<u8>,
+    syncs: u64,
+    closed: bool,
+}
+
+impl Tap {
+    fn new(keep: Keep) -> Self {
+        Tap {
+            state: Mutex::default(),
+            synced: Condvar::new(),
+            keep,
+        }
+    }
+
+    /// Start capturing for a new command. Returns the sync count to wait for.
+    fn begin(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.captured.clear();
+        state.syncs + 1
+    }
+
+    /// Wait until the command's sync marker has been seen, then take the
+    /// captured bytes.
+    fn finish(&self, target: u64) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        while state.syncs < target && !state.closed {
+            let (next, timeout) = self.synced.wait_timeout(state, SYNC_TIMEOUT).unwrap();
+            state = next;
+            if timeout.timed_out() {
+                break;
+            }
+        }
+        std::mem::take(&mut state.captured)
+    }
+
+    /// Take the captured bytes of a command that will never write its sync
+    /// marker.
+    fn abandon(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state.lock().unwrap().captured)
+    }
+
+    /// Forward raw bytes from `source` to `out`. Raw bytes preserve \r
+    /// progress bars, ANSI color codes, and other terminal sequences.
+    fn pump(&self, mut source: impl Read, mut out: impl Write) {
+        let mut buf = [0u8; 4096];
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            match source.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => pending.extend_from_slice(&buf[..n]),
+            }
+
+            while let Some(pos) = find(&pending, SYNC_MARKER) {
+                self.forward(&pending[..pos], &mut out);
+                pending.drain(..pos + SYNC_MARKER.len());
+                let mut state = self.state.lock().unwrap();
+                state.syncs += 1;
+                self.synced.notify_all();
+            }
+
+            // Hold back anything that could be the start of a marker
+            let ready = pending.len() - partial_marker_len(&pending);
+            self.forward(&pending[..ready], &mut out);
+            pending.drain(..ready);
+        }
+
+        self.forward(&pending, &mut out);
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.synced.notify_all();
+    }
+
+    fn forward(&self, bytes: &[u8], out: &mut impl Write) {
+        if bytes.is_empty() {
+            return;
+        }
+        out.write_all(bytes).ok();
+        out.flush().ok();
+        // Capture for error analysis and the transcript (bounded)
+        let mut state = self.state.lock().unwrap();
+        match self.keep {
+            Keep::First(limit) => {
+                if state.captured.len() < limit {
+                    let remaining = limit - state.captured.len();
+                    state
+                        .captured
+                        .extend_from_slice(&bytes[..bytes.len().min(remaining)]);
+                }
+            }
+            Keep::Last(limit) => {
+                state.captured.extend_from_slice(bytes);
+                let excess = state.captured.len().saturating_sub(limit);
+                state.captured.drain(..excess);
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Length of the longest suffix of `bytes` that is a proper prefix of the
+/// sync marker.
+fn partial_marker_len(bytes: &[u8]) -> usize {
+    (1..SYNC_MARKER.len())
+        .rev()
+        .find(|&len| bytes.ends_with(&SYNC_MARKER[..len]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the fields bash would have written, NUL-terminated.
+    fn parse(fields: &[&str]) -> Result<Report, ()> {
+        let mut fields = fields.iter().map(|f| f.as_bytes().to_vec());
+        Report::parse(|| fields.next().ok_or(()))
+    }
+
+    #[test]
+    fn parses_reports() {
+        let report = parse(&[
+            "3",
+            "/tmp/work",
+            "FOO=a=b",
+            "EMPTY=",
+            "",
+            "ll",
+            "greet",
+            "",
+            "4242",
+            "sleep 30",
+            "4343",
+            "make >log",
+            "",
+        ])
+        .unwrap();
+        assert_eq!(report.status, 3);
+        assert_eq!(report.pwd, PathBuf::from("/tmp/work"));
+        assert_eq!(
+            report.exported,
+            vec![
+                (OsString::from("FOO"), OsString::from("a=b")),
+                (OsString::from("EMPTY"), OsString::new()),
+            ]
+        );
+        assert_eq!(
+            report.names,
+            HashSet::from(["ll".to_string(), "greet".to_string()])
+        );
+        assert_eq!(
+            report.launched,
+            vec![
+                (4242, "sleep 30".to_string()),
+                (4343, "make >log".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_reports_with_nothing_launched() {
+        let report = parse(&["0", "/", "", "", ""]).unwrap();
+        assert_eq!(report.status, 0);
+        assert!(report.exported.is_empty());
+        assert!(report.names.is_empty());
+        assert!(report.launched.is_empty());
+    }
+
+    #[test]
+    fn a_short_report_is_an_error() {
+        assert!(parse(&["0", "/", "A=1"]).is_err());
+    }
+
+    #[test]
+    fn launches_background_lists_as_jobs() {
+        let line = launch_in_background("sleep 30 & echo started");
+        assert!(
+            line.starts_with("{ __claudesh_pid=$(set -m; ( set +m; sleep 30 ) 0<&0 "),
+            "{}",
+            line
+        );
+        assert!(
+            line.contains(
+                "__claudesh_launched+=(${__claudesh_pid:+\"$__claudesh_pid\" 'sleep 30'});"
+            ),
+            "{}",
+            line
+        );
+        assert!(line.ends_with(" echo started"), "{}", line);
+
+        // The command is noted as typed, quoted for bash
+        let quoted = launch_in_background("echo 'hi' >out &");
+        assert!(quoted.contains(r"'echo '\''hi'\'' >out'"), "{}", quoted);
+    }
+
+    #[test]
+    fn leaves_other_lines_to_bash() {
+        for line in ["echo hi", "a && b", "{ sleep 1 & }", "echo '&'", "x=1 # &"] {
+            assert_eq!(launch_in_background(line), line);
+        }
+    }
+
+    #[test]
+    fn job_specs_name_process_groups() {
+        let mut jobs = JobTable::default();
+        jobs.add(4242, "sleep 30", State::Running);
+        jobs.add(4343, "vim", State::Stopped(libc::SIGTSTP));
+
+        assert_eq!(expand_job_specs("kill %1", &jobs), "kill -- -4242");
+        assert_eq!(expand_job_specs("kill -9 %1", &jobs), "kill -9 -- -4242");
+        assert_eq!(
+            expand_job_specs("kill -s INT %sleep 99", &jobs),
+            "kill -s INT -- -4242 99"
+        );
+        assert_eq!(expand_job_specs("kill -- %1", &jobs), "kill -- -4242");
+        // Unknown specs are left for kill to complain about
+        assert_eq!(expand_job_specs("kill %7", &jobs), "kill %7");
+        assert_eq!(expand_job_specs("kill 99", &jobs), "kill 99");
+        assert_eq!(expand_job_specs("kill %1; ls", &jobs), "kill %1; ls");
+        assert_eq!(expand_job_specs("echo %1", &jobs), "echo %1");
+    }
+
+    #[test]
+    fn stopped_jobs_are_continued_to_take_a_termination() {
+        let mut jobs = JobTable::default();
+        jobs.add(4242, "sleep 30", State::Running);
+        jobs.add(4343, "vim", State::Stopped(libc::SIGTSTP));
+
+        assert_eq!(
+            expand_job_specs("kill %1 %2", &jobs),
+            "kill -- -4242 -4343 && kill -CONT -- -4343"
+        );
+        assert_eq!(
+            expand_job_specs("kill -s sighup %vim", &jobs),
+            "kill -s sighup -- -4343 && kill -CONT -- -4343"
+        );
+        assert_eq!(expand_job_specs("kill -9 %2", &jobs), "kill -9 -- -4343");
+        assert_eq!(
+            expand_job_specs("kill -INT %2", &jobs),
+            "kill -INT -- -4343"
+        );
+    }
+
+    /// A terminal to test against: the slave side of a pseudo-terminal, and
+    /// the master side, which keeps it open.
+    fn fake_terminal() -> (OwnedFd, OwnedFd) {
+        let (not_a_terminal, _) = io::pipe().unwrap();
+        let (master, slave) = open_pty(&OwnedFd::from(not_a_terminal)).unwrap();
+        (slave, master)
+    }
+
+    #[test]
+    fn taps_keep_the_start_or_the_end() {
+        let output: Vec<u8> = (0..10_000).map(|i| b'0' + (i % 10) as u8).collect();
+        for (keep, kept) in [
+            (Keep::First(12), &b"012345678901"[..]),
+            (Keep::Last(12), &b"890123456789"[..]),
+        ] {
+            let tap = Tap::new(keep);
+            let (reader, mut writer) = io::pipe().unwrap();
+            let target = tap.begin();
+            let written = output.clone();
+            let feeder = thread::spawn(move || writer.write_all(&written).unwrap());
+            tap.pump(reader, io::sink());
+            feeder.join().unwrap();
+            assert_eq!(tap.finish(target), kept);
+        }
+    }
+
+    #[test]
+    fn taps_sync_across_the_pseudo_terminal() {
+        let (terminal, _master) = fake_terminal();
+        let (master, slave) = open_pty(&terminal).unwrap();
+        let tap = Arc::new(Tap::new(Keep::Last(STDOUT_CAPTURE_LIMIT)));
+        let pump = Arc::clone(&tap);
+        thread::spawn(move || pump.pump(File::from(master), io::sink()));
+        let mut slave = File::from(slave);
+
+        // Newlines come through as written, for the terminal to process
+        let target = tap.begin();
+        slave.write_all(b"one\ntwo\n").unwrap();
+        slave.write_all(SYNC_MARKER).unwrap();
+        assert_eq!(tap.finish(target), b"one\ntwo\n");
+
+        let target = tap.begin();
+        slave.write_all(b"three\0claudesh").unwrap();
+        slave.write_all(b"-sync\0").unwrap();
+        assert_eq!(tap.finish(target), b"three");
+    }
+
+    #[test]
+    fn handover_passes_settings_on_and_puts_them_back() {
+        let (fd, _master) = fake_terminal();
+        let (pty, slave) = open_pty(&fd).unwrap();
+        let terminal = Arc::new(Terminal {
+            fd,
+            pty: Some(pty),
+            passed: Mutex::new(None),
+        });
+        let before = terminal_modes(&terminal.fd).unwrap();
+        // SAFETY: getpgrp can't fail.
+        let group = unsafe { libc::getpgrp() };
+
+        // A program setting the terminal up through stdout, as curses does
+        let handover = Handover::new(&terminal, group, None);
+        let mut raw = terminal_modes(&slave).unwrap();
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON);
+        set_terminal_modes(&slave, &raw);
+        terminal.follow();
+        let during = terminal_modes(&terminal.fd).unwrap();
+        assert_eq!(during.c_lflag & (libc::ECHO | libc::ICANON), 0);
+        assert_ne!(during.c_oflag & libc::OPOST, 0);
+        drop(handover);
+        assert!(same_modes(&terminal_modes(&terminal.fd).unwrap(), &before));
+
+        // Once the line is over, changes to the pseudo-terminal stay put
+        set_terminal_modes(&slave, &raw);
+        terminal.follow();
+        assert!(same_modes(&terminal_modes(&terminal.fd).unwrap(), &before));
+
+        // A line that finished keeps what it did, as `stty` does
+        let mut handover = Handover::new(&terminal, group, None);
+        let mut quiet = before;
+        quiet.c_lflag &= !libc::ECHO;
+        set_terminal_modes(&terminal.fd, &quiet);
+        handover.keep();
+        drop(handover);
+        assert_eq!(
+            terminal_modes(&terminal.fd).unwrap().c_lflag & libc::ECHO,
+            0
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Signal dispositions for claudesh itself, and naming the signals that
+//! end its children.
+//!
+//! claudesh catches SIGINT and SIGQUIT rather than ignoring them: a caught
+//! signal is reset to its default action on exec, so every child still dies
+//! from Ctrl-C while claudesh carries on.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Survive SIGINT and SIGQUIT meant for a child sharing the terminal.
+pub fn install() {
+    // SAFETY: the handler only stores to an atomic. SA_RESTART keeps blocking
+    // reads and waits from failing with EINTR.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for sig in [libc::SIGINT, libc::SIGQUIT] {
+            libc::sigaction(sig, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Stay put when the terminal is handed to a job or taken back, like any
+/// job-control shell.
+pub fn ignore_job_control() {
+    // SAFETY: setting a standard disposition.
+    unsafe {
+        for sig in [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+            libc::signal(sig, libc::SIG_IGN);
+        }
+    }
+}
+
+extern "C" fn on_child(_: libc::c_int) {}
+
+/// Catch SIGCHLD, so a job stopping or finishing cuts short a `poll` that is
+/// waiting on the session.
+pub fn watch_children() {
+    // SAFETY: the handler does nothing; it only has to exist for the signal
+    // to interrupt poll. SA_RESTART keeps other blocking calls going.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_child as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut());
+    }
+}
+
+/// Whether SIGINT or SIGQUIT arrived since the last call.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// Whether SIGINT or SIGQUIT arrived since `take_interrupt` last looked,
+/// without resetting it.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The signal behind an exit status, if it looks like bash's 128+N
+/// convention for a command killed by a signal.
+pub fn from_status(status: i32) -> Option<i32> {
+    let sig = status - 128;
+    name(sig).map(|_| sig)
+}
+
+/// Whether a command ending with `status` was interrupted from the keyboard.
+pub fn is_user_interrupt(status: i32) -> bool {
+    matches!(from_status(status), Some(libc::SIGINT | libc::SIGQUIT))
+}
+
+/// Whether `status` means the command was stopped, not terminated.
+pub fn is_stop(status: i32) -> bool {
+    matches!(
+        from_status(status),
+        Some(libc::SIGTSTP | libc::SIGSTOP | libc::SIGTTIN | libc::SIGTTOU)
+    )
+}
+
+pub fn name(sig: i32) -> Option<&'static str> {
+    let name = match sig {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGURG => "SIGURG",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGVTALRM => "SIGVTALRM",
+        libc::SIGPROF => "SIGPROF",
+        libc::SIGWINCH => "SIGWINCH",
+        libc::SIGIO => "SIGIO",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,184 @@
+//! `{{name}}` placeholders in the AI prompt files.
+//!
+//! A prompt with placeholders says for itself what the AI is told about the
+//! user's situation: they're filled in when a request is made, and nothing
+//! else is added, so the message is just what the feature sends. A prompt
+//! without any gets the personality appended and the current directory, OS,
+//! shell and user put before the message, with a summary of the system for
+//! requests for commands.
+
+use std::env;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::context::Collector;
+use crate::transcript::Transcript;
+
+/// What a prompt can ask for.
+pub const NAMES: &[&str] = &[
+    "cwd",
+    "os",
+    "distro",
+    "package_manager",
+    "git_branch",
+    "user",
+    "shell",
+    "last_command",
+    "last_stderr",
+    "personality",
+    "project_notes",
+    "environment",
+    "transcript",
+];
+
+/// What the placeholders that aren't looked up on the spot are filled in
+/// from.
+pub struct Vars<'a> {
+    pub cwd: &'a Path,
+    pub personality: &'a str,
+    /// What the current project says about itself.
+    pub project_notes: Option<&'a str>,
+    pub transcript: &'a Transcript,
+    /// The system summary given with requests for commands.
+    pub environment: Option<&'a str>,
+    /// What's known about the system.
+    pub context: &'a Collector,
+}
+
+/// Whether `text` has any placeholders, known or not.
+pub fn is_template(text: &str) -> bool {
+    !placeholders(text).is_empty()
+}
+
+/// Report placeholders that aren't in `NAMES`.
+pub fn check(text: &str) -> Result<(), String> {
+    let unknown: Vec<String> = placeholders(text)
+        .into_iter()
+        .map(|(_, name)| name)
+        .filter(|name| !NAMES.contains(name))
+        .map(|name| format!("{{{{{}}}}}", name))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "unknown placeholder{} {} (expected one of {})",
+        if unknown.len() == 1 { "" } else { "s" },
+        unknown.join(", "),
+        NAMES.join(", ")
+    ))
+}
+
+/// `text` with its placeholders filled in. Unknown ones, already reported
+/// by `check`, are left as they are.
+pub fn render(text: &str, vars: &Vars) -> String {
+    let mut out = String::new();
+    let mut rest = 0;
+    for (range, name) in placeholders(text) {
+        out.push_str(&text[rest..range.start]);
+        match value(name, vars) {
+            Some(value) => out.push_str(value.trim()),
+            None => out.push_str(&text[range.clone()]),
+        }
+        rest = range.end;
+    }
+    out.push_str(&text[rest..]);
+    out
+}
+
+fn value(name: &str, vars: &Vars) -> Option<String> {
+    let last = vars.transcript.last();
+    Some(match name {
+        "cwd" => vars.cwd.display().to_string(),
+        "os" => env::consts::OS.to_string(),
+        "distro" => vars.context.distro().to_string(),
+        "package_manager" => vars.context.package_manager().to_string(),
+        "git_branch" => crate::prompt::git_branch(vars.cwd).unwrap_or_default(),
+        "user" => env::var("USER").unwrap_or_else(|_| "unknown".into()),
+        "shell" => "claudesh".to_string(),
+        "last_command" => last.map(|l| l.command.clone()).unwrap_or_default(),
+        "last_stderr" => last.map(|l| l.stderr.clone()).unwrap_or_default(),
+        "personality" => vars.personality.to_string(),
+        "project_notes" => vars.project_notes.unwrap_or_default().to_string(),
+        "environment" => vars.environment.unwrap_or_default().to_string(),
+        "transcript" => vars.transcript.render(),
+        _ => return None,
+    })
+}
+
+/// Every `{{name}}` in `text`, spaces inside the braces allowed. Braces
+/// around anything but a name are left alone, so JSON and the like can be
+/// written as usual.
+fn placeholders(text: &str) -> Vec<(Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(open) = text[from..].find("{{").map(|i| from + i) {
+        let Some(close) = text[open + 2..].find("}}").map(|i| open + 2 + i) else {
+            break;
+        };
+        let name = text[open + 2..close].trim();
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            found.push((open..close + 2, name));
+            from = close + 2;
+        } else {
+            from = open + 1;
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(text: &str, transcript: &Transcript) -> String {
+        let vars = Vars {
+            cwd: Path::new("/work/proj"),
+            personality: "Be brief.\n",
+            project_notes: Some("Uses make."),
+            transcript,
+            environment: None,
+            context: &Collector::default(),
+        };
+        render(text, &vars)
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        let mut transcript = Transcript::new(10, 100);
+        transcript.record("make", None, 2, "", "make: *** No rule\n");
+        let text = "In {{cwd}} ({{ shell }}). {{personality}} {{project_notes}}\n\
+                    Last: {{last_command}} said {{last_stderr}}.{{environment}}";
+        assert_eq!(
+            render_with(text, &transcript),
+            "In /work/proj (claudesh). Be brief. Uses make.\n\
+             Last: make said make: *** No rule."
+        );
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        let transcript = Transcript::new(10, 100);
+        let text = r#"Reply as {"command": "..."} or {{ NotAName }} or {{unknown}}"#;
+        assert_eq!(render_with(text, &transcript), text);
+        assert!(!is_template(r#"{"a": {"b": 1}}"#));
+        assert!(is_template("{{unknown}}"));
+    }
+
+    #[test]
+    fn check_reports_unknown_placeholders() {
+        assert!(check("{{cwd}} {{ transcript }} {not one}").is_ok());
+        let error = check("{{cwd}} {{cwdd}} {{usr}}").unwrap_err();
+        assert!(
+            error.starts_with("unknown placeholders {{cwdd}}, {{usr}} (expected one of cwd, "),
+            "{}",
+            error
+        );
+        let error = check("{{cwdd}}").unwrap_err();
+        assert!(
+            error.starts_with("unknown placeholder {{cwdd}} "),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! The commands run this session, so the AI can make sense of follow-ups
+//! like "now do the same for the other directory" or "delete those".
+//!
+//! Only the last few commands are kept, and only the end of what each wrote
+//! to the terminal and to stderr, as plain text. What goes to the terminal is
+//! only seen in the interactive shell, where commands write it to a
+//! pseudo-terminal that claudesh copies to the real one.
+
+use std::collections::VecDeque;
+
+/// How many commands are kept, unless `[context] commands` says otherwise.
+pub const DEFAULT_COMMANDS: usize = 10;
+
+/// How much of each command's output, and of its error output, is kept, in
+/// characters.
+pub const DEFAULT_OUTPUT: usize = 1000;
+
+pub struct Transcript {
+    entries: VecDeque<Entry>,
+    /// Commands sent to the AI; none turns the transcript off.
+    commands: usize,
+    output: usize,
+}
+
+pub struct Entry {
+    pub command: String,
+    /// How the AI came into it, e.g. "generated for: list big files".
+    pub note: Option<String>,
+    pub exit_code: i32,
+    /// The end of what the command wrote to the terminal.
+    pub stdout: String,
+    /// The end of the command's stderr.
+    pub stderr: String,
+}
+
+impl Transcript {
+    pub fn new(commands: usize, output: usize) -> Self {
+        Transcript {
+            entries: VecDeque::new(),
+            commands,
+            output,
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        command: &str,
+        note: Option<String>,
+        exit_code: i32,
+        stdout: &str,
+        stderr: &str,
+    ) {
+        // The last command is kept even with the transcript off, for the
+        // `{{last_command}}` placeholder
+        while self.entries.len() >= self.commands.max(1) {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            command: command.to_string(),
+            note,
+            exit_code,
+            stdout: tail(&plain(stdout), self.output),
+            stderr: tail(&plain(stderr), self.output),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn last(&self) -> Option<&Entry> {
+        self.entries.back()
+    }
+
+    /// The transcript as the AI sees it, oldest first. Empty when there's
+    /// nothing to tell.
+    pub fn render(&self) -> String {
+        if self.commands == 0 {
+            return String::new();
+        }
+        let mut text = String::new();
+        for entry in &self.entries {
+            text.push_str(&format!("$ {}\n", entry.command));
+            if let Some(note) = &entry.note {
+                text.push_str(&format!("({})\n", note));
+            }
+            text.push_str(&format!("(exit status {})\n", entry.exit_code));
+            if !entry.stdout.is_empty() {
+                text.push_str(&entry.stdout);
+                text.push('\n');
+            }
+            if !entry.stderr.is_empty() {
+                text.push_str("(stderr)\n");
+                text.push_str(&entry.stderr);
+                text.push('\n');
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
+/// Roughly what `text` leaves on a terminal: escape sequences dropped, and
+/// each line as carriage returns and backspaces leave it, so a progress bar
+/// comes down to its last state. Programs that move the cursor about the
+/// screen, or switch to a screen of their own, can't be followed that way,
+/// so their output is left out altogether.
+fn plain(text: &str) -> String {
+    const FULL_SCREEN: &str = "[full-screen program]";
+    let mut lines = Vec::new();
+    let mut line: Vec<char> = Vec::new();
+    let mut column = 0usize;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // Control sequence: parameters, then a final byte
+                Some('[') => {
+                    let mut params = String::new();
+                    let end = loop {
+                        match chars.next() {
+                            Some(c) if ('@'..='~').contains(&c) => break c,
+                            Some(c) => params.push(c),
+                            None => break ' ',
+                        }
+                    };
+                    let screen = matches!(end, 'H' | 'f')
+                        || (end == 'J' && params == "2")
+                        || (end == 'h' && ["?1049", "?1047", "?47"].contains(&params.as_str()));
+                    if screen {
+                        return FULL_SCREEN.to_string();
+                    }
+                }
+                // Strings, up to BEL or ESC \
+                Some(']' | 'P' | 'X' | '^' | '_') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Anything else, e.g. ESC ( B: intermediates, then a final byte
+                Some(' '..='/') => while chars.next().is_some_and(|c| (' '..='/').contains(&c)) {},
+                _ => {}
+            },
+            '\r' => column = 0,
+            '\n' => {
+                lines.push(line.drain(..).collect::<String>().trim_end().to_string());
+                column = 0;
+            }
+            '\x08' => column = column.saturating_sub(1),
+            c if c.is_control() && c != '\t' => {}
+            c => {
+                if column < line.len() {
+                    line[column] = c;
+                } else {
+                    line.push(c);
+                }
+                column += 1;
+            }
+        }
+    }
+    lines.push(line.into_iter().collect::<String>().trim_end().to_string());
+    lines.join("\n").trim_end().to_string()
+}
+
+/// The last `limit` characters of `text`, marked as cut when they're not all
+/// of it.
+fn tail(text: &str, limit: usize) -> String {
+    let count = text.chars().count();
+    if count <= limit {
+        return text.to_string();
+    }
+    let rest: String = text.chars().skip(count - limit).collect();
+    format!("[...]{}", rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tail_keeps_the_end() {
+        assert_eq!(tail("short", 10), "short");
+        assert_eq!(tail("exactly", 7), "exactly");
+        assert_eq!(tail("0123456789", 4), "[...]6789");
+        assert_eq!(tail("ééééé", 2), "[...]éé");
+        assert_eq!(tail("anything", 0), "[...]");
+    }
+
+    #[test]
+    fn plain_keeps_what_shows() {
+        assert_eq!(plain("\x1b[1;34msrc\x1b[0m  a.txt\r\n"), "src  a.txt");
+        assert_eq!(plain(" 10%\r 50%\r100%\r\ndone\n"), "100%\ndone");
+        assert_eq!(plain("\x1b]0;title\x07ab\x08c\ttab"), "ac\ttab");
+        assert_eq!(plain("\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"), "link");
+        assert_eq!(plain("\x1b(Bplain\x1b[m\x1b[K"), "plain");
+        assert_eq!(plain("\x1b[2J\x1b[1;1Htop"), "[full-screen program]");
+        assert_eq!(
+            plain("\x1b[?1049hanything\x1b[?1049l"),
+            "[full-screen program]"
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_commands() {
+        let mut transcript = Transcript::new(2, 5);
+        transcript.record("one", None, 0, "", "");
+        transcript.record("two", Some("generated for: 2".to_string()), 0, "", "");
+        transcript.record("three", None, 1, "a\r\nb\r\n", "error: it broke\n");
+        assert_eq!(
+            transcript.render(),
+            "$ two\n(generated for: 2)\n(exit status 0)\n\
+             $ three\n(exit status 1)\na\nb\n(stderr)\n[...]broke"
+        );
+        assert_eq!(transcript.last().unwrap().command, "three");
+        transcript.clear();
+        assert_eq!(transcript.render(), "");
+    }
+
+    #[test]
+    fn off_keeps_only_the_last_command() {
+        let mut transcript = Transcript::new(0, 5);
+        transcript.record("one", None, 0, "", "");
+        transcript.record("two", None, 0, "", "");
+        assert_eq!(transcript.render(), "");
+        assert_eq!(transcript.last().unwrap().command, "two");
+    }
+}
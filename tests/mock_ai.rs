@@ -0,0 +1,563 @@
+//! Drive the shell with the offline mock AI, checking what it prints and the
+//! requests it makes: with piped input, and at the REPL on a pseudo-terminal.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// A home and a working directory of its own for one test.
+struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("claudesh-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("home")).unwrap();
+        fs::create_dir_all(root.join("work")).unwrap();
+        Sandbox {
+            root: root.canonicalize().unwrap(),
+        }
+    }
+
+    fn work(&self) -> PathBuf {
+        self.root.join("work")
+    }
+
+    /// claudesh in the work directory, answered by the mock from `fixtures`.
+    fn command(&self, fixtures: &str) -> Command {
+        let fixtures_path = self.root.join("fixtures.toml");
+        let record_path = self.root.join("requests.jsonl");
+        fs::write(&fixtures_path, fixtures).unwrap();
+        let _ = fs::remove_file(&record_path);
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_claudesh"));
+        command
+            .current_dir(self.work())
+            .env("HOME", self.root.join("home"))
+            .env("CLAUDESH_MOCK_AI", &fixtures_path)
+            .env("CLAUDESH_MOCK_AI_RECORD", &record_path)
+            .env_remove("ANTHROPIC_API_KEY");
+        command
+    }
+
+    /// The requests the mock has recorded.
+    fn requests(&self) -> Vec<Value> {
+        fs::read_to_string(self.root.join("requests.jsonl"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    /// Run claudesh on `input` with `fixtures` as the mock's responses.
+    /// Returns its output and the requests it made.
+    fn run(&self, fixtures: &str, input: &str) -> (Output, Vec<Value>) {
+        let mut child = self
+            .command(fixtures)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        (output, self.requests())
+    }
+
+    /// Start claudesh at the REPL on a terminal of its own, with `fixtures`
+    /// as the mock's responses and `ready> ` as its prompt.
+    fn terminal(&self, fixtures: &str) -> Terminal {
+        let config_dir = self.root.join("home/.claudesh");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join("config.toml"),
+            "[ui]\nprompt = \"ready> \"\n",
+        )
+        .unwrap();
+
+        let (master, slave) = open_pty();
+        let mut command = self.command(fixtures);
+        command
+            .env("TERM", "xterm-256color")
+            .stdin(slave.try_clone().unwrap())
+            .stdout(slave.try_clone().unwrap())
+            .stderr(slave);
+        // A session of its own, with the terminal as its controlling one
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn().unwrap();
+        drop(command);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut reader = master.try_clone().unwrap();
+        let sink = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // Reading fails with EIO once claudesh and its children are gone
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                sink.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+
+        let mut terminal = Terminal {
+            master,
+            child,
+            output,
+            seen: 0,
+        };
+        terminal.expect("ready> ");
+        terminal
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// A pseudo-terminal pair, 100 columns by 24 rows.
+fn open_pty() -> (File, File) {
+    let (mut master, mut slave) = (0, 0);
+    let size = libc::winsize {
+        ws_row: 24,
+        ws_col: 100,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &size,
+        )
+    };
+    assert_eq!(result, 0, "openpty: {}", io::Error::last_os_error());
+    unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) }
+}
+
+/// claudesh running on a pseudo-terminal, typed at like a user would.
+struct Terminal {
+    master: File,
+    child: Child,
+    output: Arc<Mutex<Vec<u8>>>,
+    /// How much of the plain output `expect` has read past.
+    seen: usize,
+}
+
+impl Terminal {
+    /// How long to wait for output before failing.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn send(&mut self, keys: &str) {
+        self.master.write_all(keys.as_bytes()).unwrap();
+    }
+
+    /// Wait for `text` to appear after what earlier calls read past, and
+    /// return the output up to and including it, without escape sequences.
+    fn expect(&mut self, text: &str) -> String {
+        let deadline = Instant::now() + Self::TIMEOUT;
+        loop {
+            let screen = plain(&self.output.lock().unwrap());
+            if let Some(at) = screen[self.seen..].find(text) {
+                let end = self.seen + at + text.len();
+                let read = screen[self.seen..end].to_string();
+                self.seen = end;
+                return read;
+            }
+            if Instant::now() > deadline {
+                panic!("{:?} never appeared in:\n{}", text, &screen[self.seen..]);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Type `line` at the prompt and return what it printed before prompting
+    /// again. The prompt is redrawn as keys arrive, so the line going by
+    /// with its newline marks where the output starts.
+    fn run(&mut self, line: &str) -> String {
+        self.send(line);
+        self.send("\r");
+        self.expect(&format!("{}\n", line));
+        self.expect("ready> ")
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Terminal output without escape sequences or carriage returns.
+fn plain(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL
+                Some(']') => {
+                    for c in chars.by_ref() {
+                        if c == '\x07' {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn plain_english_prints_the_generated_command() {
+    let sandbox = Sandbox::new("generate");
+    let fixtures = r#"
+        [[response]]
+        kind = "generate"
+        input = "*big files*"
+        reply = "du -sh * | sort -h"
+    "#;
+    let (output, requests) = sandbox.run(fixtures, "please show the big files here\n");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(stdout(&output).trim(), "du -sh * | sort -h");
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_eq!(request["kind"], "generate");
+    assert_eq!(request["input"], "please show the big files here");
+    assert_eq!(request["cwd"], sandbox.work().to_str().unwrap());
+    assert!(request["system"].as_str().unwrap().contains("claudesh"));
+    let message = request["message"].as_str().unwrap();
+    assert!(message.contains("User input: please show the big files here"));
+}
+
+#[test]
+fn explain_sends_the_command_to_explain() {
+    let sandbox = Sandbox::new("explain");
+    let fixtures = r#"
+        [[response]]
+        kind = "explain"
+        reply = "Unpacks a.tgz into the current directory."
+    "#;
+    let (output, requests) = sandbox.run(fixtures, "? tar -xzf a.tgz\n");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("Unpacks a.tgz"));
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["kind"], "explain");
+    assert_eq!(requests[0]["input"], "tar -xzf a.tgz");
+}
+
+#[test]
+fn shell_commands_never_reach_the_ai() {
+    let sandbox = Sandbox::new("shell");
+    let (output, requests) = sandbox.run("", "echo hello\nfor x in a b\ndo\n  echo $x\ndone\n");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(stdout(&output), "hello\na\nb\n");
+    assert!(requests.is_empty());
+}
+
+#[test]
+fn a_request_without_a_fixture_fails() {
+    let sandbox = Sandbox::new("unmatched");
+    let fixtures = r#"
+        [[response]]
+        kind = "explain"
+        reply = "unused"
+    "#;
+    let (output, requests) = sandbox.run(fixtures, "please show the big files here\n");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no fixture"));
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["kind"], "generate");
+}
+
+#[test]
+fn scripts_dont_point_out_untrusted_projects() {
+    let sandbox = Sandbox::new("untrusted");
+    fs::create_dir_all(sandbox.work().join(".claudesh")).unwrap();
+    fs::write(sandbox.work().join(".claudesh/context"), "Uses make.").unwrap();
+    let fixtures = r#"
+        [[response]]
+        reply = "make"
+    "#;
+    let (output, requests) = sandbox.run(fixtures, "echo hi\nplease build the thing\n");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(stdout(&output), "hi\nmake\n");
+    assert!(String::from_utf8_lossy(&output.stderr).is_empty());
+    // Nor are its settings used
+    assert!(!requests[0]["system"]
+        .as_str()
+        .unwrap()
+        .contains("Uses make."));
+}
+
+// ─── Interactive ─────────────────────────────────────────────────────────────
+
+const GENERATE_ECHO: &str = r#"
+    [[response]]
+    kind = "generate"
+    reply = "echo generated-ran"
+"#;
+
+#[test]
+fn a_generated_command_runs_on_enter() {
+    let sandbox = Sandbox::new("tty-run");
+    let mut terminal = sandbox.terminal(GENERATE_ECHO);
+    terminal.send("please say something nice\r");
+    terminal.expect("> echo generated-ran");
+    terminal.expect("[enter] run / [e]dit / [s]kip");
+    terminal.send("\r");
+    assert!(terminal.expect("ready> ").contains("\ngenerated-ran\n"));
+
+    let requests = sandbox.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["kind"], "generate");
+    assert_eq!(requests[0]["input"], "please say something nice");
+}
+
+#[test]
+fn a_skipped_command_never_runs() {
+    let sandbox = Sandbox::new("tty-skip");
+    let mut terminal = sandbox.terminal(GENERATE_ECHO);
+    terminal.send("please say something nice\r");
+    terminal.expect("[enter] run / [e]dit / [s]kip");
+    terminal.send("s\r");
+    let after = terminal.expect("ready> ");
+    assert!(after.contains("skipped"), "{}", after);
+    assert!(!after.contains("\ngenerated-ran"), "{}", after);
+}
+
+#[test]
+fn an_edited_command_runs_as_edited() {
+    let sandbox = Sandbox::new("tty-edit");
+    let mut terminal = sandbox.terminal(GENERATE_ECHO);
+    terminal.send("please say something nice\r");
+    terminal.expect("[enter] run / [e]dit / [s]kip");
+    terminal.send("e\r");
+    terminal.expect("> echo generated-ran");
+    // Replace the last word: Ctrl-W, then the new one
+    terminal.send("\x17edited-ran\r");
+    let after = terminal.expect("ready> ");
+    assert!(after.contains("\nedited-ran\n"), "{}", after);
+    assert!(!after.contains("\ngenerated-ran"), "{}", after);
+}
+
+#[test]
+fn a_destructive_command_needs_yes() {
+    let sandbox = Sandbox::new("tty-yes");
+    fs::create_dir_all(sandbox.work().join("victim")).unwrap();
+    fs::write(sandbox.work().join("victim/file"), "").unwrap();
+    let fixtures = r#"
+        [[response]]
+        kind = "generate"
+        reply = "rm -r victim/*"
+    "#;
+    let mut terminal = sandbox.terminal(fixtures);
+
+    // Enter alone, which runs an ordinary command, skips this one
+    terminal.send("please empty the victim directory\r");
+    terminal.expect("# deletes everything matching victim/* recursively");
+    terminal.expect("type yes to run / [e]dit / [s]kip");
+    terminal.send("\r");
+    assert!(terminal.expect("ready> ").contains("skipped"));
+    assert!(sandbox.work().join("victim/file").exists());
+
+    terminal.send("please empty the victim directory\r");
+    terminal.expect("type yes to run / [e]dit / [s]kip");
+    terminal.send("yes\r");
+    terminal.expect("ready> ");
+    assert!(!sandbox.work().join("victim/file").exists());
+}
+
+#[test]
+fn a_failed_command_gets_a_fix() {
+    let sandbox = Sandbox::new("tty-fix");
+    let fixtures = r#"
+        [[response]]
+        kind = "fix"
+        reply = """
+There is no such directory.
+
+echo fix-ran"""
+    "#;
+    let mut terminal = sandbox.terminal(fixtures);
+    terminal.send("ls no-such-dir\r");
+    terminal.expect("press f for AI help or enter to continue");
+    terminal.send("f\r");
+    terminal.expect("There is no such directory.");
+    terminal.expect("> echo fix-ran");
+    terminal.expect("[enter] run / [s]kip");
+    terminal.send("\r");
+    assert!(terminal.expect("ready> ").contains("\nfix-ran\n"));
+
+    let requests = sandbox.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["kind"], "fix");
+    let input = requests[0]["input"].as_str().unwrap();
+    assert!(input.contains("Command: ls no-such-dir"), "{}", input);
+    assert!(input.contains("Exit code: 2"), "{}", input);
+    assert!(input.contains("no-such-dir"), "{}", input);
+}
+
+#[test]
+fn judgy_mode_comments_on_requests_and_questions() {
+    let sandbox = Sandbox::new("tty-judgy");
+    let fixtures = r#"
+        [[response]]
+        kind = "judgy"
+        reply = "Bold of you to ask.\nThis line is dropped."
+
+        [[response]]
+        kind = "ask"
+        reply = "Forty-two."
+
+        [[response]]
+        kind = "generate"
+        reply = "echo generated-ran"
+    "#;
+    let mut terminal = sandbox.terminal(fixtures);
+    terminal.run("judgy on");
+
+    let answer = terminal.run("?? what is the answer");
+    assert!(answer.contains("Bold of you to ask."), "{}", answer);
+    assert!(answer.contains("Forty-two."), "{}", answer);
+    assert!(!answer.contains("dropped"), "{}", answer);
+
+    terminal.send("please say something nice\r");
+    let offer = terminal.expect("[enter] run / [e]dit / [s]kip");
+    assert!(offer.contains("Bold of you to ask."), "{}", offer);
+    terminal.send("s\r");
+    terminal.expect("ready> ");
+
+    // Each comment sees the session so far, its own earlier ones included
+    let requests = sandbox.requests();
+    let judgy: Vec<&str> = requests
+        .iter()
+        .filter(|r| r["kind"] == "judgy")
+        .map(|r| r["input"].as_str().unwrap())
+        .collect();
+    assert_eq!(judgy.len(), 2);
+    assert!(judgy[0].ends_with("The user just typed: ?? what is the answer"));
+    assert!(
+        judgy[1].contains("[judgy]: Bold of you to ask."),
+        "{}",
+        judgy[1]
+    );
+    assert!(judgy[1].ends_with("The user just typed: please say something nice"));
+}
+
+#[test]
+fn jobs_stop_resume_and_end_on_a_terminal() {
+    let sandbox = Sandbox::new("tty-jobs");
+    let mut terminal = sandbox.terminal("");
+    terminal.run("kept=still-here");
+
+    // Ctrl-Z stops the line, and the session carries on as it was
+    terminal.send("sleep 30\r");
+    terminal.expect("sleep 30\n");
+    thread::sleep(Duration::from_millis(300));
+    terminal.send("\x1a");
+    terminal.expect("[1]+  Stopped                 sleep 30\n");
+    terminal.expect("ready> ");
+    assert!(terminal.run("echo $kept").contains("still-here"));
+
+    let started = terminal.run("sleep 31 &");
+    assert!(started.contains("[2] "), "{}", started);
+    let jobs = terminal.run("jobs");
+    assert!(
+        jobs.contains("[1]+  Stopped                 sleep 30\n"),
+        "{}",
+        jobs
+    );
+    assert!(
+        jobs.contains("[2]-  Running                 sleep 31 &\n"),
+        "{}",
+        jobs
+    );
+
+    // Back in the foreground, Ctrl-C reaches it
+    terminal.send("fg %1\r");
+    terminal.expect("fg %1\nsleep 30\n");
+    thread::sleep(Duration::from_millis(300));
+    terminal.send("\x03");
+    terminal.expect("ready> ");
+
+    let ended = terminal.run("kill %2") + &terminal.run("wait");
+    assert!(
+        ended.contains("[2]+  Terminated              sleep 31\n"),
+        "{}",
+        ended
+    );
+    assert!(!terminal.run("jobs").contains("sleep"));
+    assert!(terminal.run("echo $kept").contains("still-here"));
+}
+
+#[test]
+fn requests_carry_what_commands_printed() {
+    let sandbox = Sandbox::new("tty-context");
+    let fixtures = r#"
+        [[response]]
+        kind = "ask"
+        reply = "Two files."
+    "#;
+    let mut terminal = sandbox.terminal(fixtures);
+    let listed = terminal.run("printf '\\033[1mfirst.txt\\033[0m\\nsecond.txt\\n'; echo oops >&2");
+    assert!(listed.contains("first.txt\nsecond.txt\n"), "{}", listed);
+    terminal.run("?? how many files was that");
+
+    let requests = sandbox.requests();
+    assert_eq!(requests.len(), 1);
+    let message = requests[0]["message"].as_str().unwrap();
+    assert!(
+        message.contains("(exit status 0)\nfirst.txt\nsecond.txt\n(stderr)\noops"),
+        "{}",
+        message
+    );
+}